@@ -0,0 +1,2 @@
+DROP TABLE psk_rotations;
+DROP TABLE psk_rotation_policies
@@ -0,0 +1,22 @@
+CREATE TABLE psk_rotation_policies (
+  id INTEGER NOT NULL PRIMARY KEY,
+  interface TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  interval_secs BIGINT NOT NULL,
+  cutover_delay_secs BIGINT NOT NULL,
+  next_rotation_at BIGINT NOT NULL,
+  UNIQUE (interface, public_key)
+);
+
+-- Rows are kept after the new key is applied, for auditing. The key itself is cleared once it's
+-- no longer pending, since the config file is then the only place it needs to be.
+CREATE TABLE psk_rotations (
+  id INTEGER NOT NULL PRIMARY KEY,
+  interface TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  preshared_key TEXT,
+  status TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  cutover_at BIGINT NOT NULL,
+  applied_at BIGINT
+)
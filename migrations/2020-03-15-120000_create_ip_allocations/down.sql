@@ -0,0 +1,2 @@
+DROP TABLE ip_reservations;
+DROP TABLE ip_allocations
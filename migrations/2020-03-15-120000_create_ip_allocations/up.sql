@@ -0,0 +1,20 @@
+-- Addresses handed out to peers from the interface's networks. A peer's addresses are also in its
+-- AllowedIPs, but keeping them here stops them being handed out again while a peer is being added.
+CREATE TABLE ip_allocations (
+  id INTEGER NOT NULL PRIMARY KEY,
+  interface TEXT NOT NULL,
+  address TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  UNIQUE (interface, address)
+);
+
+-- Without a public key, nothing in the network is handed out. With one, it's a static reservation
+-- that's only handed out to that peer.
+CREATE TABLE ip_reservations (
+  id INTEGER NOT NULL PRIMARY KEY,
+  interface TEXT NOT NULL,
+  network TEXT NOT NULL,
+  public_key TEXT,
+  description TEXT NOT NULL
+)
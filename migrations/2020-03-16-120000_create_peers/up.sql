@@ -0,0 +1,14 @@
+-- What WireGuard doesn't know about a peer, such as whose device it is. A peer that's on the
+-- device without a row here was added some other way, such as with `wg set`.
+CREATE TABLE peers (
+  id INTEGER NOT NULL PRIMARY KEY,
+  interface TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  name TEXT NOT NULL,
+  description TEXT NOT NULL,
+  owner_id INTEGER REFERENCES users (id),
+  created_by INTEGER REFERENCES users (id),
+  created_at BIGINT NOT NULL,
+  updated_at BIGINT NOT NULL,
+  UNIQUE (interface, public_key)
+)
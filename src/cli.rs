@@ -1,48 +1,221 @@
-use clap::{clap_app, crate_name, crate_version};
+use crate::config::document::{Format, Secrets};
+use crate::config::peer::AllowedIps;
+use clap::{clap_app, crate_name, crate_version, ArgMatches};
 use failure::{format_err, Error};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub struct Args {
+    pub command: Option<Command>,
+    pub allow_insecure_permissions: bool,
     pub bind_ip: String,
     pub db_path: String,
+    pub drift: bool,
     pub foreground: bool,
-    pub interface: String,
-    pub interface_config: PathBuf,
+    pub import: bool,
+    pub interfaces: Vec<InterfaceArgs>,
+    pub lookup: Option<IpAddr>,
     pub port: u16,
+    pub public_host: Option<String>,
+    pub reresolve_interval: Option<Duration>,
+    pub watch_config: bool,
+}
+
+pub struct InterfaceArgs {
+    pub name: String,
+    pub config_path: PathBuf,
+}
+
+// Subcommands run on their own instead of starting the server.
+pub enum Command {
+    Check(InterfaceArgs),
+    Export {
+        interface: InterfaceArgs,
+        format: Format,
+        secrets: Secrets,
+    },
+    Import {
+        interface: InterfaceArgs,
+        format: Format,
+    },
+    GenKey,
+    PubKey,
+    GenPsk,
+    AllowedIps {
+        networks: AllowedIps,
+        except: AllowedIps,
+    },
 }
 
 impl Args {
     pub fn get_from_clap() -> Result<Self, Error> {
         let matches = clap_app!(myapp =>
             (version: (crate_version!()))
+            (@arg ALLOW_INSECURE_PERMISSIONS: --("allow-insecure-permissions")
+                "Start even if config, key or database files can be read or replaced by other users")
             (@arg BIND_IP: -b --bind default_value("localhost"))
             (@arg DB_PATH: -d --("database-path") +takes_value)
+            (@arg DRIFT: --drift
+                "Print how each interface differs from its config file, then exit")
             (@arg FOREGROUND: -f --foreground)
-            (@arg INTERFACE_CONFIG: -c --("interface-config") +takes_value)
+            (@arg IMPORT: --import
+                "Write the current configuration of interfaces that are already up to their config files")
+            (@arg INTERFACE_CONFIG: -c --("interface-config") +takes_value +multiple
+                number_of_values(1)
+                "Config file for each interface, given once per interface in the same order")
+            (@arg LOOKUP: --lookup +takes_value value_name("ADDRESS")
+                "Print which peer on each interface an address belongs to, then exit")
             (@arg PORT: -p --port default_value("8000"))
+            (@arg PUBLIC_HOST: --("public-host") +takes_value
+                "Hostname or address clients connect to, used in generated client configs")
+            (@arg RERESOLVE_INTERVAL: --("reresolve-interval") default_value("30s")
+                "How often to look up hostname endpoints again, or 0 to disable")
+            (@arg WATCH_CONFIG: --("watch-config")
+                "Reload an interface's config whenever its file changes, as well as on SIGHUP")
             // Not sure if wg0 is a good default, or if we should require this.
-            (@arg INTERFACE: default_value("wg0"))
+            (@arg INTERFACE: +multiple default_value("wg0"))
+            (@subcommand check =>
+                (about: "Checks a config file for errors and common mistakes")
+                (@arg INTERFACE_CONFIG: -c --("interface-config") +takes_value)
+                (@arg INTERFACE: default_value("wg0"))
+            )
+            (@subcommand export =>
+                (about: "Prints a config file as JSON or TOML")
+                (@arg FORMAT: --format +takes_value possible_value[json toml] default_value("json"))
+                (@arg INTERFACE_CONFIG: -c --("interface-config") +takes_value)
+                (@arg REDACT: --redact "Leave out private and preshared keys")
+                (@arg INTERFACE: default_value("wg0"))
+            )
+            (@subcommand import =>
+                (about: "Reads a JSON or TOML config from standard input and writes it to a config file")
+                (@arg FORMAT: --format +takes_value possible_value[json toml] default_value("json"))
+                (@arg INTERFACE_CONFIG: -c --("interface-config") +takes_value)
+                (@arg INTERFACE: default_value("wg0"))
+            )
+            (@subcommand genkey =>
+                (about: "Generates a new private key and prints it, like wg genkey")
+            )
+            (@subcommand pubkey =>
+                (about: "Reads a private key from standard input and prints its public key, like wg pubkey")
+            )
+            (@subcommand genpsk =>
+                (about: "Generates a new preshared key and prints it, like wg genpsk")
+            )
+            (@subcommand allowedips =>
+                (about: "Prints networks as the fewest AllowedIPs prefixes that cover them")
+                (@arg EXCEPT: -x --except +takes_value +multiple number_of_values(1)
+                    "Leave out a network, such as a LAN that shouldn't go through the tunnel")
+                (@arg NETWORK: +required +multiple)
+            )
         )
         .get_matches();
 
-        let interface = matches.value_of("INTERFACE").unwrap().to_string();
+        let names: Vec<String> = matches
+            .values_of("INTERFACE")
+            .unwrap()
+            .map(ToString::to_string)
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(format_err!("interface {} was given more than once", name));
+            }
+        }
 
         let root_dir = format!("/var/lib/{}", crate_name!());
-        let default_interface_config = format!("{}/{}.conf", root_dir, interface);
+        let default_config_path = |name: &str| PathBuf::from(format!("{}/{}.conf", root_dir, name));
+
+        let subcommand_interface = |matches: &ArgMatches| {
+            let name = matches.value_of("INTERFACE").unwrap().to_string();
+            InterfaceArgs {
+                config_path: matches
+                    .value_of("INTERFACE_CONFIG")
+                    .map_or_else(|| default_config_path(&name), PathBuf::from),
+                name,
+            }
+        };
+        let format = |matches: &ArgMatches| match matches.value_of("FORMAT") {
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        };
+
+        let command = match matches.subcommand() {
+            ("check", Some(matches)) => Some(Command::Check(subcommand_interface(matches))),
+            ("export", Some(matches)) => Some(Command::Export {
+                interface: subcommand_interface(matches),
+                format: format(matches),
+                secrets: if matches.is_present("REDACT") {
+                    Secrets::Redact
+                } else {
+                    Secrets::Include
+                },
+            }),
+            ("import", Some(matches)) => Some(Command::Import {
+                interface: subcommand_interface(matches),
+                format: format(matches),
+            }),
+            ("genkey", _) => Some(Command::GenKey),
+            ("pubkey", _) => Some(Command::PubKey),
+            ("genpsk", _) => Some(Command::GenPsk),
+            ("allowedips", Some(matches)) => {
+                let allowed_ips = |name| -> Result<AllowedIps, Error> {
+                    let networks: Vec<&str> =
+                        matches.values_of(name).into_iter().flatten().collect();
+                    networks.join(",").parse()
+                };
+                Some(Command::AllowedIps {
+                    networks: allowed_ips("NETWORK")?,
+                    except: allowed_ips("EXCEPT")?,
+                })
+            }
+            _ => None,
+        };
+
+        let interfaces: Vec<InterfaceArgs> = match matches.values_of("INTERFACE_CONFIG") {
+            Some(config_paths) => {
+                let config_paths: Vec<PathBuf> = config_paths.map(PathBuf::from).collect();
+                if config_paths.len() != names.len() {
+                    return Err(format_err!(
+                        "--interface-config must be given once for each interface"
+                    ));
+                }
+                names
+                    .into_iter()
+                    .zip(config_paths)
+                    .map(|(name, config_path)| InterfaceArgs { name, config_path })
+                    .collect()
+            }
+            None => names
+                .into_iter()
+                .map(|name| InterfaceArgs {
+                    config_path: default_config_path(&name),
+                    name,
+                })
+                .collect(),
+        };
 
         Ok(Self {
+            command,
+            allow_insecure_permissions: matches.is_present("ALLOW_INSECURE_PERMISSIONS"),
             bind_ip: matches.value_of("BIND_IP").unwrap().to_string(),
+            // The database is named after the first interface so that a server that used to
+            // manage a single interface keeps using the database it already has.
             db_path: matches.value_of("DB_PATH").map_or_else(
-                || format!("{}/{}.sqlite3", root_dir, interface),
+                || format!("{}/{}.sqlite3", root_dir, interfaces[0].name),
                 std::string::ToString::to_string,
             ),
+            drift: matches.is_present("DRIFT"),
             foreground: matches.is_present("FOREGROUND"),
-            interface,
-            interface_config: PathBuf::from(
-                matches
-                    .value_of("INTERFACE_CONFIG")
-                    .unwrap_or(&default_interface_config),
-            ),
+            import: matches.is_present("IMPORT"),
+            interfaces,
+            lookup: matches
+                .value_of("LOOKUP")
+                .map(|address| {
+                    address
+                        .parse()
+                        .map_err(|err| format_err!("invalid address to look up: {}", err))
+                })
+                .transpose()?,
             port: matches
                 .value_of("PORT")
                 .unwrap()
@@ -53,6 +226,13 @@ impl Args {
                         2u32.pow(16) - 1
                     )
                 })?,
+            public_host: matches.value_of("PUBLIC_HOST").map(ToString::to_string),
+            reresolve_interval: Some(
+                humantime::parse_duration(matches.value_of("RERESOLVE_INTERVAL").unwrap())
+                    .map_err(|err| format_err!("invalid re-resolve interval: {}", err))?,
+            )
+            .filter(|interval| *interval != Duration::from_secs(0)),
+            watch_config: matches.is_present("WATCH_CONFIG"),
         })
     }
 }
@@ -0,0 +1,368 @@
+use crate::impl_with_fromstr_with_error;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+// A network routed to a peer. It's kept as written, so host bits can be pointed out rather than
+// silently dropped. WireGuard only ever sees the network itself.
+#[derive(Clone)]
+pub struct AllowedIp {
+    written: IpNet,
+    // The network address of the above, kept here so wireguard_uapi can borrow it.
+    network: IpAddr,
+}
+
+impl AllowedIp {
+    // What's actually routed to the peer, without any host bits.
+    pub fn net(&self) -> IpNet {
+        self.written.trunc()
+    }
+
+    pub fn has_host_bits(&self) -> bool {
+        self.written != self.written.trunc()
+    }
+}
+
+impl From<IpNet> for AllowedIp {
+    fn from(net: IpNet) -> Self {
+        Self {
+            written: net,
+            network: net.network(),
+        }
+    }
+}
+
+impl From<&IpNet> for AllowedIp {
+    fn from(net: &IpNet) -> Self {
+        Self::from(*net)
+    }
+}
+
+impl<'a> From<&'a AllowedIp> for wireguard_uapi::set::AllowedIp<'a> {
+    fn from(allowed_ip: &'a AllowedIp) -> Self {
+        Self {
+            ipaddr: &allowed_ip.network,
+            cidr_mask: Some(allowed_ip.written.prefix_len()),
+        }
+    }
+}
+
+impl From<&AllowedIp> for wireguard_uapi::get::AllowedIp {
+    fn from(allowed_ip: &AllowedIp) -> Self {
+        Self {
+            family: match allowed_ip.network {
+                IpAddr::V4(_) => libc::AF_INET as u16,
+                IpAddr::V6(_) => libc::AF_INET6 as u16,
+            },
+            ipaddr: allowed_ip.network,
+            cidr_mask: allowed_ip.written.prefix_len(),
+        }
+    }
+}
+
+// The kernel never reports a prefix that's too long for its address, but one would be read as the
+// single address rather than panicking.
+impl From<&wireguard_uapi::get::AllowedIp> for AllowedIp {
+    fn from(allowed_ip: &wireguard_uapi::get::AllowedIp) -> Self {
+        Self::from(
+            new_net(allowed_ip.ipaddr, allowed_ip.cidr_mask)
+                .unwrap_or_else(|| IpNet::from(allowed_ip.ipaddr)),
+        )
+    }
+}
+
+// None if the prefix is too long for the address.
+fn new_net(addr: IpAddr, prefix_len: u8) -> Option<IpNet> {
+    match addr {
+        IpAddr::V4(addr) => Ipv4Net::new(addr, prefix_len).ok().map(IpNet::V4),
+        IpAddr::V6(addr) => Ipv6Net::new(addr, prefix_len).ok().map(IpNet::V6),
+    }
+}
+
+impl fmt::Display for AllowedIp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.written)
+    }
+}
+
+// A missing prefix length covers just the one address.
+impl FromStr for AllowedIp {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = s.trim().split('/').collect();
+        if tokens.len() > 2 {
+            return Err(InvalidAllowedIpError {
+                allowed_ip: s.trim().to_string(),
+            }
+            .into());
+        }
+
+        let addr: IpAddr = tokens[0].parse()?;
+        let net = match tokens.get(1) {
+            Some(prefix_len) => {
+                let prefix_len: u8 = prefix_len.parse()?;
+                new_net(addr, prefix_len).ok_or_else(|| PrefixTooLongError {
+                    prefix_len,
+                    max_prefix_len: IpNet::from(addr).max_prefix_len(),
+                })?
+            }
+            None => IpNet::from(addr),
+        };
+        Ok(Self::from(net))
+    }
+}
+
+// Wrapper type needed until https://github.com/SergioBenitez/Rocket/issues/205
+#[derive(Clone, Default)]
+pub struct AllowedIps(pub Vec<AllowedIp>);
+
+impl AllowedIps {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn nets(&self) -> Vec<IpNet> {
+        self.0.iter().map(AllowedIp::net).collect()
+    }
+
+    // The same networks as the fewest possible prefixes, with duplicates and adjacent networks
+    // merged. IPv4 comes first, then each family is sorted.
+    pub fn canonical(&self) -> Self {
+        Self::from_nets(aggregate(&self.nets()))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut nets = self.nets();
+        nets.extend(other.nets());
+        Self::from_nets(aggregate(&nets))
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut nets = vec![];
+        for a in self.nets() {
+            for b in other.nets() {
+                if a.contains(&b) {
+                    nets.push(b);
+                } else if b.contains(&a) {
+                    nets.push(a);
+                }
+            }
+        }
+        Self::from_nets(aggregate(&nets))
+    }
+
+    // Everything in these networks except the other's, such as 0.0.0.0/0 except 192.168.0.0/16.
+    pub fn difference(&self, other: &Self) -> Self {
+        let excluded = other.nets();
+        let nets: Vec<IpNet> = self
+            .nets()
+            .into_iter()
+            .flat_map(|net| subtract(net, &excluded))
+            .collect();
+        Self::from_nets(aggregate(&nets))
+    }
+
+    // Forms are stricter than config files, which only get a warning for this.
+    pub fn check_host_bits(&self) -> Result<(), failure::Error> {
+        match self.0.iter().find(|allowed_ip| allowed_ip.has_host_bits()) {
+            Some(allowed_ip) => Err(HostBitsError {
+                allowed_ip: allowed_ip.to_string(),
+                net: allowed_ip.net(),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn from_nets(nets: Vec<IpNet>) -> Self {
+        Self(nets.into_iter().map(AllowedIp::from).collect())
+    }
+}
+
+// The fewest prefixes covering the same addresses. IpNet::aggregate isn't used since it doesn't
+// merge networks at the end of the address space, such as 255.255.255.254/31 and 255.255.255.255/32.
+fn aggregate(nets: &[IpNet]) -> Vec<IpNet> {
+    let mut sorted: Vec<IpNet> = nets.iter().map(IpNet::trunc).collect();
+    // A network sorts before any network within it.
+    sorted.sort();
+
+    let mut merged: Vec<IpNet> = vec![];
+    for net in sorted {
+        if merged.last().map_or(false, |last| last.contains(&net)) {
+            continue;
+        }
+        merged.push(net);
+
+        // Two halves of the same network are replaced by it, which may in turn complete another.
+        while merged.len() >= 2 {
+            let (a, b) = (merged[merged.len() - 2], merged[merged.len() - 1]);
+            match a.supernet() {
+                Some(supernet)
+                    if a.prefix_len() == b.prefix_len() && b.supernet() == Some(supernet) =>
+                {
+                    merged.truncate(merged.len() - 2);
+                    merged.push(supernet);
+                }
+                _ => break,
+            }
+        }
+    }
+    merged
+}
+
+// Splits the network in half until each half is either clear of the excluded networks or entirely
+// within one of them. Only the halves along the edge of an excluded network are split, so the
+// result is already as short as it can be.
+fn subtract(net: IpNet, excluded: &[IpNet]) -> Vec<IpNet> {
+    match excluded
+        .iter()
+        .find(|other| net.contains(*other) || other.contains(&net))
+    {
+        None => vec![net],
+        Some(other) if other.contains(&net) => vec![],
+        Some(_) => match halves(net) {
+            Some((lower, upper)) => {
+                let mut nets = subtract(lower, excluded);
+                nets.extend(subtract(upper, excluded));
+                nets
+            }
+            None => vec![],
+        },
+    }
+}
+
+// None for a single address. IpNet::subnets isn't used since it stops short of the last network
+// in the address space, such as 255.255.255.255/32.
+fn halves(net: IpNet) -> Option<(IpNet, IpNet)> {
+    if net.prefix_len() == net.max_prefix_len() {
+        return None;
+    }
+    let prefix_len = net.prefix_len() + 1;
+    let upper_bit = net.max_prefix_len() - prefix_len;
+
+    let upper = match net.network() {
+        IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) | 1 << upper_bit)),
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) | 1 << upper_bit)),
+    };
+    Some((
+        new_net(net.network(), prefix_len)?,
+        new_net(upper, prefix_len)?,
+    ))
+}
+
+impl fmt::Display for AllowedIps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let allowed_ips = self
+            .0
+            .iter()
+            .map(std::string::ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{}", allowed_ips)
+    }
+}
+
+impl FromStr for AllowedIps {
+    type Err = failure::Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        Ok(Self(
+            str.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(AllowedIp::from_str)
+                .collect::<Result<Vec<_>, _>>()?,
+        ))
+    }
+}
+
+impl_with_fromstr_with_error!(AllowedIp, AllowedIps);
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} isn't an address with an optional prefix length",
+    allowed_ip
+)]
+struct InvalidAllowedIpError {
+    allowed_ip: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "/{} is too long for the address, which only has {} bits",
+    prefix_len, max_prefix_len
+)]
+struct PrefixTooLongError {
+    prefix_len: u8,
+    max_prefix_len: u8,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} has host bits set, which would be ignored. Did you mean {}?",
+    allowed_ip, net
+)]
+struct HostBitsError {
+    allowed_ip: String,
+    net: IpNet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AllowedIp, AllowedIps};
+
+    fn allowed_ips(s: &str) -> AllowedIps {
+        s.parse().expect("valid AllowedIPs")
+    }
+
+    #[test]
+    fn prefixes_are_checked_and_host_bits_kept() -> Result<(), failure::Error> {
+        assert_eq!(
+            "10.0.0.1/40"
+                .parse::<AllowedIp>()
+                .err()
+                .map(|err| err.to_string()),
+            Some("/40 is too long for the address, which only has 32 bits".to_string())
+        );
+        assert!("fd00::/129".parse::<AllowedIp>().is_err());
+        assert_eq!("fd00::1".parse::<AllowedIp>()?.to_string(), "fd00::1/128");
+
+        let with_host_bits = allowed_ips("10.0.0.0/24, 10.0.1.5/24");
+        assert_eq!(with_host_bits.to_string(), "10.0.0.0/24, 10.0.1.5/24");
+        assert_eq!(with_host_bits.0[1].net().to_string(), "10.0.1.0/24");
+        assert_eq!(
+            with_host_bits.check_host_bits().unwrap_err().to_string(),
+            "10.0.1.5/24 has host bits set, which would be ignored. Did you mean 10.0.1.0/24?"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = allowed_ips("10.0.0.0/25, 10.0.0.128/25, 10.0.0.7/32, fd00::/64");
+        let b = allowed_ips("10.0.0.64/26, 192.168.0.0/16, fd00::/48");
+
+        assert_eq!(a.canonical().to_string(), "10.0.0.0/24, fd00::/64");
+        assert_eq!(
+            a.union(&b).to_string(),
+            "10.0.0.0/24, 192.168.0.0/16, fd00::/48"
+        );
+        assert_eq!(a.intersection(&b).to_string(), "10.0.0.64/26, fd00::/64");
+        assert_eq!(a.difference(&b).to_string(), "10.0.0.0/26, 10.0.0.128/25");
+    }
+
+    #[test]
+    fn exclusions_are_expanded_into_the_fewest_prefixes() {
+        let everything = allowed_ips("0.0.0.0/0");
+        let except_lan = everything.difference(&allowed_ips("192.168.0.0/16"));
+        assert_eq!(
+            except_lan.to_string(),
+            "0.0.0.0/1, 128.0.0.0/2, 192.0.0.0/9, 192.128.0.0/11, 192.160.0.0/13, \
+             192.169.0.0/16, 192.170.0.0/15, 192.172.0.0/14, 192.176.0.0/12, 192.192.0.0/10, \
+             193.0.0.0/8, 194.0.0.0/7, 196.0.0.0/6, 200.0.0.0/5, 208.0.0.0/4, 224.0.0.0/3"
+        );
+        assert!(everything.difference(&everything).0.is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+use super::interface::Dns;
+use super::peer::{AllowedIp, AllowedIps};
+use super::{Config, Endpoint, Peer, PresharedKey};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+// Addresses are handed out in order, so this only comes into play for a mostly full IPv6 network,
+// which would otherwise take practically forever to search.
+const MAX_ADDRESSES_SEARCHED: usize = 1 << 16;
+
+// What a new client needs besides its own keys and address, all of which are generated.
+pub struct ClientOptions {
+    // How the client reaches this server, which can't be worked out from the interface alone.
+    pub endpoint: Endpoint,
+    // What the client sends through the tunnel, such as the interface's networks or 0.0.0.0/0.
+    pub allowed_ips: AllowedIps,
+    pub dns: Vec<Dns>,
+    pub preshared_key: bool,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl Config {
+    // The lowest address in each of the interface's networks that isn't the interface's own and
+    // isn't within any peer's AllowedIPs or the taken networks. The first address of an IPv6
+    // network is skipped too, since it's the subnet-router anycast address.
+    pub fn next_free_addresses(&self, taken: &[IpNet]) -> Result<Vec<IpNet>, failure::Error> {
+        if self.interface.address.is_empty() {
+            return Err(NoAddressError.into());
+        }
+
+        let own_addrs: Vec<IpAddr> = self.interface.address.iter().map(IpNet::addr).collect();
+        let peer_nets: Vec<IpNet> = self
+            .peers
+            .iter()
+            .flat_map(|peer| peer.allowed_ips.nets())
+            .chain(taken.iter().cloned())
+            .collect();
+
+        self.interface
+            .address
+            .iter()
+            .map(|net| {
+                net.hosts()
+                    .take(MAX_ADDRESSES_SEARCHED)
+                    .filter(|addr| *addr != net.network())
+                    .find(|addr| {
+                        !own_addrs.contains(addr) && !peer_nets.iter().any(|n| n.contains(addr))
+                    })
+                    .map(IpNet::from)
+                    .ok_or_else(|| NetworkFullError { net: net.trunc() }.into())
+            })
+            .collect()
+    }
+
+    // Generates a keypair for a new client and assigns it the next free addresses. Returns the
+    // peer to add to this interface, and the client's own config. The client's private key is
+    // only ever in the latter, so it isn't kept anywhere once that's been handed over.
+    pub fn new_client(
+        &self,
+        options: ClientOptions,
+        taken: &[IpNet],
+    ) -> Result<(Peer, Config), failure::Error> {
+        let addresses = self.next_free_addresses(taken)?;
+
+        let mut client = Config::new(self.name.clone())?;
+        client.interface.address = addresses.clone();
+        client.interface.dns = options.dns;
+
+        let preshared_key = if options.preshared_key {
+            Some(PresharedKey::new()?)
+        } else {
+            None
+        };
+
+        let peer = Peer {
+            public_key: client.interface.private_key.public_key(),
+            preshared_key: preshared_key
+                .as_ref()
+                .map(|preshared_key| PresharedKey::from(*preshared_key.as_bytes())),
+            preshared_key_source: None,
+            allowed_ips: AllowedIps(addresses.iter().map(AllowedIp::from).collect()),
+            endpoint: None,
+            persistent_keepalive: None,
+        };
+
+        client.peers.push(Peer {
+            public_key: self.interface.private_key.public_key(),
+            preshared_key,
+            preshared_key_source: None,
+            allowed_ips: options.allowed_ips,
+            endpoint: Some(options.endpoint),
+            persistent_keepalive: options.persistent_keepalive,
+        });
+
+        Ok((peer, client))
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "the interface has no Address to assign client addresses from")]
+pub struct NoAddressError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "there are no free addresses left in {}", net)]
+pub struct NetworkFullError {
+    net: IpNet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientOptions;
+    use crate::config::Config;
+    use ipnet::IpNet;
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+Address = 10.0.0.1/24, fd00::1/64
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32, 10.0.0.4/31, fd00::2/128
+";
+
+    fn config() -> Result<Config, failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        config.ok_or_else(|| diagnostics.into())
+    }
+
+    #[test]
+    fn skips_addresses_in_use() -> Result<(), failure::Error> {
+        let config = config()?;
+        let addresses = |taken: &[IpNet]| -> Result<Vec<String>, failure::Error> {
+            Ok(config
+                .next_free_addresses(taken)?
+                .iter()
+                .map(ToString::to_string)
+                .collect())
+        };
+        assert_eq!(addresses(&[])?, vec!["10.0.0.3/32", "fd00::3/128"]);
+
+        // Such as addresses that are allocated or reserved.
+        let taken = vec!["10.0.0.0/30".parse()?, "fd00::3/128".parse()?];
+        assert_eq!(addresses(&taken)?, vec!["10.0.0.6/32", "fd00::4/128"]);
+        Ok(())
+    }
+
+    #[test]
+    fn full_networks_are_reported() -> Result<(), failure::Error> {
+        let mut config = config()?;
+        config.interface.address = vec!["10.0.0.1/30".parse()?];
+        assert_eq!(
+            config.next_free_addresses(&[]).unwrap_err().to_string(),
+            "there are no free addresses left in 10.0.0.0/30"
+        );
+
+        config.interface.address = vec![];
+        assert!(config.next_free_addresses(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn client_config_points_back_at_the_server() -> Result<(), failure::Error> {
+        let config = config()?;
+        let (peer, client) = config.new_client(
+            ClientOptions {
+                endpoint: "vpn.example.com:51820".parse()?,
+                allowed_ips: "10.0.0.0/24".parse()?,
+                dns: vec!["10.0.0.1".parse()?],
+                preshared_key: true,
+                persistent_keepalive: Some(25),
+            },
+            &[],
+        )?;
+
+        assert_eq!(peer.allowed_ips.to_string(), "10.0.0.3/32, fd00::3/128");
+        assert_eq!(
+            peer.public_key.to_string(),
+            client.interface.private_key.public_key().to_string()
+        );
+
+        let server = &client.peers[0];
+        assert_eq!(
+            server.public_key.to_string(),
+            config.interface.private_key.public_key().to_string()
+        );
+        assert_eq!(
+            server.preshared_key.as_ref().map(|key| key.expose_secret()),
+            peer.preshared_key.as_ref().map(|key| key.expose_secret())
+        );
+
+        let written = client.to_string();
+        assert!(written.contains("Address = 10.0.0.3/32,fd00::3/128\n"));
+        assert!(written.contains("DNS = 10.0.0.1\n"));
+        assert!(written.contains("Endpoint = vpn.example.com:51820\n"));
+        assert!(written.contains("AllowedIPs = 10.0.0.0/24\n"));
+        assert!(written.contains("PersistentKeepalive = 25\n"));
+        Ok(())
+    }
+}
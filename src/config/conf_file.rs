@@ -1,69 +1,738 @@
-use failure;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use super::diagnostics::{Diagnostic, Diagnostics, Severity};
+use super::KeySource;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
 
 // WireGuard configuration files are based on the Windows INI format, but allow multiple sections.
+//
+// The file is kept as a list of lines with their original text so that it can be written back
+// byte-for-byte. Comments, blank lines, key order, key casing and spacing all survive a round trip,
+// and edits only rewrite the lines they affect.
 
-type ConfFile = Vec<Section>;
+#[derive(Clone, Default)]
+pub struct ConfFile {
+    lines: Vec<Line>,
+}
+
+#[derive(Clone)]
+struct Line {
+    text: String,
+    // The line terminator as it appeared in the file. This is empty for a final line without a
+    // trailing newline.
+    ending: String,
+    kind: LineKind,
+}
 
+#[derive(Clone)]
+enum LineKind {
+    Blank,
+    Comment,
+    Section { name: String },
+    Entry { key: String, value: Range<usize> },
+    // Lines that couldn't be understood are kept as-is so the file isn't mangled further.
+    Invalid,
+}
+
+// A parsed view of a section used when reading the config. Fields are removed from the section as
+// they're consumed so anything left over can be reported.
 pub struct Section {
     pub name: String,
-    pub values: HashMap<String, String>,
+    pub index: usize,
+    pub line_num: usize,
+    pub column: usize,
+    pub values: Vec<Field>,
+    has_errors: bool,
 }
 
-pub fn parse(file: File) -> Result<ConfFile, failure::Error> {
-    let mut conf = vec![];
+pub struct Field {
+    pub key: String,
+    pub value: String,
+    pub line_num: usize,
+    pub key_column: usize,
+    pub value_column: usize,
+}
+
+impl Section {
+    // Like wg(8), section names and keys are matched case-insensitively.
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    // Whether any errors were reported against this section while taking its fields.
+    pub fn has_errors(&self) -> bool {
+        self.has_errors
+    }
+
+    // A diagnostic pointing at this section's header.
+    pub fn diagnostic(&self, severity: Severity, message: String) -> Diagnostic {
+        Diagnostic::new(
+            severity,
+            message,
+            self.line_num,
+            self.column,
+            self.name.len() + 2,
+        )
+        .in_section(self.index, &self.name)
+    }
+
+    fn report(&mut self, diagnostics: &mut Vec<Diagnostic>, diagnostic: Diagnostic) {
+        if diagnostic.severity == Severity::Error {
+            self.has_errors = true;
+        }
+        diagnostics.push(diagnostic.in_section(self.index, &self.name));
+    }
+
+    fn report_missing(&mut self, key: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let diagnostic = self.diagnostic(
+            Severity::Error,
+            format!(
+                "[{}] section is missing the required {} field",
+                self.name, key
+            ),
+        );
+        self.report(diagnostics, diagnostic);
+    }
+
+    // Removes and returns the field with the given key. Most fields may only be given once, so
+    // every repeat is reported against its own line.
+    pub fn take(&mut self, key: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<Field> {
+        let mut fields = self.take_all(key).into_iter();
+        let field = fields.next();
+
+        for duplicate in fields {
+            let diagnostic = Diagnostic::error(
+                format!("{} may only be given once per section", key),
+                duplicate.line_num,
+                duplicate.key_column,
+                duplicate.key.len(),
+            );
+            self.report(diagnostics, diagnostic);
+        }
+
+        field
+    }
+
+    // Removes and returns every field with the given key in the order they appeared. wg-quick
+    // allows fields such as Address, DNS and PostUp to be specified multiple times.
+    pub fn take_all(&mut self, key: &str) -> Vec<Field> {
+        let (taken, remaining): (Vec<Field>, Vec<Field>) = self
+            .values
+            .drain(..)
+            .partition(|field| field.key.eq_ignore_ascii_case(key));
+        self.values = remaining;
+        taken
+    }
 
-    for (i, line) in BufReader::new(file).lines().enumerate() {
-        let line_num = i + 1;
-        let line = line?;
-        let line = line.trim();
+    pub fn parse<T>(&mut self, key: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<T>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        let field = self.take(key, diagnostics)?;
+        self.parse_value(&field, &field.value, field.value_column, diagnostics)
+    }
 
-        if line.is_empty() {
-            continue;
+    pub fn require<T>(&mut self, key: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<T>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        let field = self.take(key, diagnostics);
+        if field.is_none() {
+            self.report_missing(key, diagnostics);
         }
 
-        if line.len() > 2 && line.starts_with('[') && line.ends_with(']') {
-            conf.push(Section {
-                name: line[1..line.len() - 1].to_string(),
-                values: HashMap::new(),
-            });
+        let field = field?;
+        self.parse_value(&field, &field.value, field.value_column, diagnostics)
+    }
+
+    // Secrets are given either inline under key, or as a KeySource under file_key so they can be
+    // kept out of the config file. The secret is checked by the same FromStr either way. Returns
+    // the secret along with where it was read from, which is None for an inline secret.
+    pub fn parse_secret<T>(
+        &mut self,
+        key: &str,
+        file_key: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<(T, Option<KeySource>)>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        let inline = self.take(key, diagnostics);
+        let file = self.take(file_key, diagnostics);
 
-            continue;
+        match (inline, file) {
+            (Some(_), Some(file)) => {
+                let diagnostic = Diagnostic::error(
+                    format!("{} and {} can't both be given", key, file_key),
+                    file.line_num,
+                    file.key_column,
+                    file.key.len(),
+                );
+                self.report(diagnostics, diagnostic);
+                None
+            }
+            (Some(inline), None) => self
+                .parse_value(&inline, &inline.value, inline.value_column, diagnostics)
+                .map(|secret| (secret, None)),
+            (None, Some(file)) => {
+                let source: KeySource =
+                    self.parse_value(&file, &file.value, file.value_column, diagnostics)?;
+                let secret = source
+                    .read()
+                    .map_err(|err| format!("couldn't read {} from {}: {}", key, source, err))
+                    .and_then(|secret| {
+                        secret
+                            .parse()
+                            .map_err(|err| format!("invalid {} in {}: {}", key, source, err))
+                    });
+
+                match secret {
+                    Ok(secret) => Some((secret, Some(source))),
+                    Err(message) => {
+                        let diagnostic = Diagnostic::error(
+                            message,
+                            file.line_num,
+                            file.value_column,
+                            file.value.len(),
+                        );
+                        self.report(diagnostics, diagnostic);
+                        None
+                    }
+                }
+            }
+            (None, None) => None,
         }
+    }
 
-        match line.splitn(2, '=').collect::<Vec<&str>>().as_slice() {
-            [field, value] => {
-                let section = conf.last_mut().ok_or(ConfNoSectionFound)?;
+    pub fn require_secret<T>(
+        &mut self,
+        key: &str,
+        file_key: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<(T, Option<KeySource>)>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        let is_given = self.values.iter().any(|field| {
+            field.key.eq_ignore_ascii_case(key) || field.key.eq_ignore_ascii_case(file_key)
+        });
+        if !is_given {
+            self.report_missing(key, diagnostics);
+            return None;
+        }
+
+        self.parse_secret(key, file_key, diagnostics)
+    }
 
-                let field = field.trim().to_string();
-                let value = value.trim().to_string();
+    // Fields such as Address and AllowedIPs hold a comma separated list, and may also be repeated.
+    // Each entry is checked on its own so the diagnostic can point right at the bad one.
+    pub fn parse_list<T>(&mut self, key: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<T>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        let mut values = vec![];
 
-                if section.values.contains_key(&field) {
-                    return Err(ConfDuplicateField { line_num }.into());
+        for field in self.take_all(key) {
+            for (column, entry) in field.entries() {
+                if let Some(value) = self.parse_value(&field, entry, column, diagnostics) {
+                    values.push(value);
                 }
-                section.values.insert(field, value);
             }
-            _ => return Err(ConfUnrecognizedLine { line_num }.into()),
+        }
+
+        values
+    }
+
+    fn parse_value<T>(
+        &mut self,
+        field: &Field,
+        value: &str,
+        column: usize,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<T>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: fmt::Display,
+    {
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                let diagnostic = Diagnostic::error(
+                    format!("invalid {} value: {}", field.key, err),
+                    field.line_num,
+                    column,
+                    value.len(),
+                );
+                self.report(diagnostics, diagnostic);
+                None
+            }
+        }
+    }
+
+    // Called once all known fields have been taken. Anything left over is most likely a typo or a
+    // field from a newer version of WireGuard, neither of which should stop the config from
+    // loading.
+    pub fn warn_unknown(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        for field in std::mem::replace(&mut self.values, vec![]) {
+            let diagnostic = Diagnostic::warning(
+                format!("unknown field {} will be ignored", field.key),
+                field.line_num,
+                field.key_column,
+                field.key.len(),
+            );
+            self.report(diagnostics, diagnostic);
+        }
+    }
+}
+
+impl Field {
+    // The entries of a comma separated value along with the column each starts at. Empty entries
+    // are skipped.
+    pub fn entries(&self) -> Vec<(usize, &str)> {
+        let mut entries = vec![];
+        let mut offset = 0;
+
+        for entry in self.value.split(',') {
+            let column = self.value_column + offset + leading_whitespace(entry);
+            offset += entry.len() + 1;
+
+            let entry = entry.trim();
+            if !entry.is_empty() {
+                entries.push((column, entry));
+            }
+        }
+
+        entries
+    }
+}
+
+impl Line {
+    fn parse(text: &str, ending: &str) -> Self {
+        // Like wg(8), everything after a # is a comment, even in the middle of a line.
+        let content_end = text.find('#').unwrap_or_else(|| text.len());
+        let content = text[..content_end].trim();
+
+        let kind = if content.is_empty() && content_end == text.len() {
+            LineKind::Blank
+        } else if content.is_empty() {
+            LineKind::Comment
+        } else if content.len() > 2 && content.starts_with('[') && content.ends_with(']') {
+            LineKind::Section {
+                name: content[1..content.len() - 1].trim().to_string(),
+            }
+        } else if let Some(equals) = text[..content_end].find('=') {
+            let key = text[..equals].trim().to_string();
+            let value_start = equals + 1 + leading_whitespace(&text[equals + 1..content_end]);
+            let value_end = value_start + text[value_start..content_end].trim_end().len();
+
+            LineKind::Entry {
+                key,
+                value: value_start..value_end,
+            }
+        } else {
+            LineKind::Invalid
         };
+
+        Self {
+            text: text.to_string(),
+            ending: ending.to_string(),
+            kind,
+        }
     }
 
-    Ok(conf)
+    fn blank() -> Self {
+        Self {
+            text: String::new(),
+            ending: String::new(),
+            kind: LineKind::Blank,
+        }
+    }
+
+    fn section(name: &str) -> Self {
+        Self {
+            text: format!("[{}]", name),
+            ending: String::new(),
+            kind: LineKind::Section {
+                name: name.to_string(),
+            },
+        }
+    }
+
+    fn entry(key: &str, value: &str) -> Self {
+        let text = format!("{} = {}", key, value);
+        let value_start = key.len() + 3;
+        let value_end = text.len();
+
+        Self {
+            text,
+            ending: String::new(),
+            kind: LineKind::Entry {
+                key: key.to_string(),
+                value: value_start..value_end,
+            },
+        }
+    }
+
+    fn is_entry_for(&self, key: &str) -> bool {
+        match &self.kind {
+            LineKind::Entry { key: entry_key, .. } => entry_key.eq_ignore_ascii_case(key),
+            _ => false,
+        }
+    }
+
+    fn value(&self) -> Option<&str> {
+        match &self.kind {
+            LineKind::Entry { value, .. } => Some(&self.text[value.clone()]),
+            _ => None,
+        }
+    }
+
+    // Replaces only the value portion of the line. The key, spacing around the equals sign and any
+    // trailing comment are kept as they were.
+    fn set_value(&mut self, new_value: &str) {
+        if let LineKind::Entry { value, .. } = &mut self.kind {
+            self.text.replace_range(value.clone(), new_value);
+            *value = value.start..value.start + new_value.len();
+        }
+    }
+}
+
+fn leading_whitespace(s: &str) -> usize {
+    s.len() - s.trim_start().len()
 }
 
-#[derive(Debug, failure::Fail)]
-#[fail(display = "no start of section found before first line of conf file")]
-pub struct ConfNoSectionFound;
+impl ConfFile {
+    // Reads a file without stopping at the first problem. Lines that can't be understood are
+    // reported and kept in the file untouched.
+    pub fn parse_with_diagnostics(source: &str) -> (Self, Vec<Diagnostic>) {
+        let mut lines = vec![];
+        let mut diagnostics = vec![];
+        let mut seen_section = false;
+        let mut rest = source;
+
+        while !rest.is_empty() {
+            let (text, ending, remaining) = match rest.find('\n') {
+                Some(newline) if rest[..newline].ends_with('\r') => {
+                    (&rest[..newline - 1], "\r\n", &rest[newline + 1..])
+                }
+                Some(newline) => (&rest[..newline], "\n", &rest[newline + 1..]),
+                None => (rest, "", ""),
+            };
+            rest = remaining;
 
-#[derive(Debug, failure::Fail)]
-#[fail(display = "line {} is not a field value pair or a section", line_num)]
-pub struct ConfUnrecognizedLine {
-    line_num: usize,
+            let line_num = lines.len() + 1;
+            let line = Line::parse(text, ending);
+            let text_start = leading_whitespace(text) + 1;
+            let text_len = text.trim().len();
+
+            match line.kind {
+                LineKind::Section { .. } => seen_section = true,
+                LineKind::Entry { .. } if !seen_section => diagnostics.push(Diagnostic::error(
+                    "fields must be inside of a section such as [Interface]".to_string(),
+                    line_num,
+                    text_start,
+                    text_len,
+                )),
+                LineKind::Invalid => diagnostics.push(Diagnostic::error(
+                    "expected a section header such as [Peer] or a Key = Value field".to_string(),
+                    line_num,
+                    text_start,
+                    text_len,
+                )),
+                _ => {}
+            }
+
+            lines.push(line);
+        }
+
+        (Self { lines }, diagnostics)
+    }
+
+    pub fn sections(&self) -> Vec<Section> {
+        let mut sections: Vec<Section> = vec![];
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let line_num = i + 1;
+            match &line.kind {
+                LineKind::Section { name } => sections.push(Section {
+                    name: name.clone(),
+                    index: sections.len(),
+                    line_num,
+                    column: leading_whitespace(&line.text) + 1,
+                    values: vec![],
+                    has_errors: false,
+                }),
+                LineKind::Entry { key, value } => {
+                    // Entries before the first section have already been reported while parsing.
+                    if let Some(section) = sections.last_mut() {
+                        section.values.push(Field {
+                            key: key.clone(),
+                            value: line.value().unwrap_or_default().to_string(),
+                            line_num,
+                            key_column: leading_whitespace(&line.text) + 1,
+                            value_column: value.start + 1,
+                        });
+                    }
+                }
+                LineKind::Blank | LineKind::Comment | LineKind::Invalid => {}
+            }
+        }
+
+        sections
+    }
+
+    // Sets the values for a key in the given section. Existing lines for the key are rewritten in
+    // place, extra lines are removed, and any additional values are added after the last entry in
+    // the section. Passing no values removes the key entirely.
+    pub fn set_values(&mut self, section: usize, key: &str, values: &[String]) {
+        let range = self.section_range(section);
+        let existing: Vec<usize> = range
+            .clone()
+            .filter(|&i| self.lines[i].is_entry_for(key))
+            .collect();
+
+        for (&i, value) in existing.iter().zip(values) {
+            self.lines[i].set_value(value);
+        }
+
+        for &i in existing.iter().skip(values.len()).rev() {
+            self.lines.remove(i);
+        }
+
+        if values.len() > existing.len() {
+            let last_entry = range
+                .clone()
+                .filter(|&i| matches!(self.lines[i].kind, LineKind::Entry { .. }))
+                .last();
+            let insert_at = existing
+                .last()
+                .cloned()
+                .or(last_entry)
+                .map(|i| i + 1)
+                .unwrap_or(range.start);
+
+            let new_lines = values[existing.len()..]
+                .iter()
+                .map(|value| Line::entry(key, value))
+                .collect();
+            self.insert_lines(insert_at, new_lines);
+        }
+    }
+
+    pub fn push_section(&mut self, name: &str, entries: &[(&str, String)]) {
+        let mut new_lines = vec![];
+
+        let needs_separator = match self.lines.last().map(|line| &line.kind) {
+            Some(LineKind::Blank) | None => false,
+            Some(_) => true,
+        };
+        if needs_separator {
+            new_lines.push(Line::blank());
+        }
+
+        new_lines.push(Line::section(name));
+        for (key, value) in entries {
+            new_lines.push(Line::entry(key, value));
+        }
+
+        let end = self.lines.len();
+        self.insert_lines(end, new_lines);
+    }
+
+    // Removes a section along with the comments directly above its header, since those usually
+    // describe the section being removed.
+    pub fn remove_section(&mut self, section: usize) {
+        let headers = self.section_headers();
+        let start = match headers.get(section) {
+            Some(&header) => self.leading_comments_start(header),
+            None => return,
+        };
+
+        let next_section = headers.get(section + 1);
+        let end = match next_section {
+            Some(&next_header) => self.leading_comments_start(next_header),
+            None => self.lines.len(),
+        };
+
+        self.lines.drain(start..end);
+
+        // Don't leave the blank lines that separated this section from the previous one dangling
+        // at the end of the file.
+        if next_section.is_none() {
+            while let Some(LineKind::Blank) = self.lines.last().map(|line| &line.kind) {
+                self.lines.pop();
+            }
+        }
+    }
+
+    fn section_headers(&self) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(line.kind, LineKind::Section { .. }))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // The lines belonging to a section's body, excluding its header.
+    fn section_range(&self, section: usize) -> Range<usize> {
+        let headers = self.section_headers();
+        match headers.get(section) {
+            Some(&header) => {
+                let end = headers
+                    .get(section + 1)
+                    .cloned()
+                    .unwrap_or_else(|| self.lines.len());
+                header + 1..end
+            }
+            None => self.lines.len()..self.lines.len(),
+        }
+    }
+
+    fn leading_comments_start(&self, header: usize) -> usize {
+        let mut start = header;
+        while start > 0 {
+            match self.lines[start - 1].kind {
+                LineKind::Comment => start -= 1,
+                _ => break,
+            }
+        }
+        start
+    }
+
+    // New lines use the same line endings as the rest of the file.
+    fn newline(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| &line.ending)
+            .find(|ending| !ending.is_empty())
+            .cloned()
+            .unwrap_or_else(|| "\n".to_string())
+    }
+
+    fn insert_lines(&mut self, at: usize, mut new_lines: Vec<Line>) {
+        let newline = self.newline();
+        for line in &mut new_lines {
+            line.ending = newline.clone();
+        }
+
+        // A final line without a newline needs one before anything can follow it. The file keeps
+        // ending without one.
+        if at > 0 && self.lines[at - 1].ending.is_empty() {
+            self.lines[at - 1].ending = newline;
+            if let Some(last) = new_lines.last_mut() {
+                last.ending = String::new();
+            }
+        }
+
+        self.lines.splice(at..at, new_lines);
+    }
 }
 
-#[derive(Debug, failure::Fail)]
-#[fail(display = "line {} contains a duplicate field", line_num)]
-pub struct ConfDuplicateField {
-    line_num: usize,
+impl FromStr for ConfFile {
+    type Err = Diagnostics;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (conf_file, diagnostics) = Self::parse_with_diagnostics(s);
+        let diagnostics = Diagnostics::new(None, s.to_string(), diagnostics);
+
+        if diagnostics.has_errors() {
+            return Err(diagnostics);
+        }
+        Ok(conf_file)
+    }
+}
+
+impl fmt::Display for ConfFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            write!(f, "{}{}", line.text, line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfFile;
+
+    const CONF: &str = "# Managed by hand, please keep these notes.\r
+[Interface]\r
+PrivateKey=yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\r
+ListenPort   =  51820   # the usual port\r
+\r
+# Alice's laptop\r
+[Peer]\r
+publickey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\r
+AllowedIPs = 10.0.0.2/32\r
+\r
+# Bob's phone\r
+[Peer]\r
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\r
+AllowedIPs = 10.0.0.3/32";
+
+    #[test]
+    fn round_trips_byte_for_byte() -> Result<(), failure::Error> {
+        let conf: ConfFile = CONF.parse()?;
+        assert_eq!(conf.to_string(), CONF);
+        Ok(())
+    }
+
+    #[test]
+    fn set_value_keeps_spacing_and_comments() -> Result<(), failure::Error> {
+        let mut conf: ConfFile = CONF.parse()?;
+        conf.set_values(0, "ListenPort", &["51821".to_string()]);
+        assert_eq!(
+            conf.to_string(),
+            CONF.replace("=  51820   #", "=  51821   #")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn new_values_follow_last_entry() -> Result<(), failure::Error> {
+        let mut conf: ConfFile = CONF.parse()?;
+        conf.set_values(2, "Endpoint", &["192.168.1.2:51820".to_string()]);
+        assert!(conf
+            .to_string()
+            .ends_with("AllowedIPs = 10.0.0.3/32\r\nEndpoint = 192.168.1.2:51820"));
+        Ok(())
+    }
+
+    #[test]
+    fn removing_a_section_takes_its_comments() -> Result<(), failure::Error> {
+        let mut conf: ConfFile = CONF.parse()?;
+        conf.remove_section(1);
+
+        let expected = CONF.replace(
+            "# Alice's laptop\r\n[Peer]\r\npublickey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\r\nAllowedIPs = 10.0.0.2/32\r\n\r\n",
+            "",
+        );
+        assert_eq!(conf.to_string(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn removing_the_last_section_trims_blank_lines() -> Result<(), failure::Error> {
+        let mut conf: ConfFile = CONF.parse()?;
+        conf.remove_section(2);
+        assert!(conf.to_string().ends_with("AllowedIPs = 10.0.0.2/32\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn pushed_sections_are_separated_by_a_blank_line() -> Result<(), failure::Error> {
+        let mut conf: ConfFile = "[Interface]\nListenPort = 51820\n".parse()?;
+        conf.push_section("Peer", &[("PublicKey", "abc=".to_string())]);
+        assert_eq!(
+            conf.to_string(),
+            "[Interface]\nListenPort = 51820\n\n[Peer]\nPublicKey = abc=\n"
+        );
+        Ok(())
+    }
 }
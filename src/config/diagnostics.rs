@@ -0,0 +1,186 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A single problem found in a config file, pointing at the text responsible for it. Lines and
+// columns are 1-based, the same as most editors.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line_num: usize,
+    pub column: usize,
+    pub len: usize,
+    // The index of the section the problem is in along with that section's name, if the problem
+    // is inside of one.
+    pub section: Option<(usize, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        severity: Severity,
+        message: String,
+        line_num: usize,
+        column: usize,
+        len: usize,
+    ) -> Self {
+        Self {
+            severity,
+            message,
+            line_num,
+            column,
+            len,
+            section: None,
+        }
+    }
+
+    pub fn error(message: String, line_num: usize, column: usize, len: usize) -> Self {
+        Self::new(Severity::Error, message, line_num, column, len)
+    }
+
+    pub fn warning(message: String, line_num: usize, column: usize, len: usize) -> Self {
+        Self::new(Severity::Warning, message, line_num, column, len)
+    }
+
+    pub fn in_section(mut self, index: usize, name: &str) -> Self {
+        self.section = Some((index, name.to_string()));
+        self
+    }
+}
+
+// Every problem found while reading a config file. Parsing carries on past errors so that a file
+// with many mistakes can be fixed in one go instead of one error at a time.
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub path: Option<PathBuf>,
+    pub items: Vec<Diagnostic>,
+    source: String,
+}
+
+impl Diagnostics {
+    pub fn new(path: Option<PathBuf>, source: String, items: Vec<Diagnostic>) -> Self {
+        Self {
+            path,
+            items,
+            source: redact_secrets(&source),
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items
+            .iter()
+            .any(|item| item.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.severity == severity)
+            .count()
+    }
+
+    fn fmt_item(&self, f: &mut fmt::Formatter, item: &Diagnostic) -> fmt::Result {
+        let severity = match item.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let path = self
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "<config>".to_string());
+        let line = self
+            .source
+            .lines()
+            .nth(item.line_num.saturating_sub(1))
+            .unwrap_or("");
+        let gutter = " ".repeat(item.line_num.to_string().len());
+
+        writeln!(f, "{}: {}", severity, item.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, path, item.line_num, item.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", item.line_num, line)?;
+        writeln!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(item.column.saturating_sub(1)),
+            "^".repeat(item.len.max(1))
+        )?;
+
+        if let Some((index, name)) = &item.section {
+            writeln!(
+                f,
+                "{} = note: in section {} ([{}])",
+                gutter,
+                index + 1,
+                name
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Diagnostics quote lines from the file, so the values of secret fields are masked before they're
+// kept. Each character is replaced rather than the whole value so columns still line up.
+fn redact_secrets(source: &str) -> String {
+    source
+        .split('\n')
+        .map(|line| {
+            let (key, value) = match line.find('=') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => return line.to_string(),
+            };
+            let is_secret = ["PrivateKey", "PresharedKey"]
+                .iter()
+                .any(|secret| key.trim().eq_ignore_ascii_case(secret));
+            if !is_secret {
+                return line.to_string();
+            }
+
+            let comment_start = value.find('#').unwrap_or_else(|| value.len());
+            let masked: String = value[..comment_start]
+                .chars()
+                .map(|c| if c.is_whitespace() { c } else { '*' })
+                .collect();
+            format!("{}={}{}", key, masked, &value[comment_start..])
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+impl failure::Fail for Diagnostics {}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            self.fmt_item(f, item)?;
+            writeln!(f)?;
+        }
+
+        let errors = self.count(Severity::Error);
+        let warnings = self.count(Severity::Warning);
+        write!(
+            f,
+            "{} error{} and {} warning{} found",
+            errors,
+            if errors == 1 { "" } else { "s" },
+            warnings,
+            if warnings == 1 { "" } else { "s" }
+        )
+    }
+}
@@ -0,0 +1,369 @@
+use super::{Config, Diagnostics, KeySource, PresharedKey, PrivateKey, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn serialize(self, document: &ConfigDocument) -> Result<String, failure::Error> {
+        Ok(match self {
+            Format::Json => serde_json::to_string_pretty(document)? + "\n",
+            Format::Toml => toml::to_string(document)?,
+        })
+    }
+
+    pub fn deserialize(self, s: &str) -> Result<ConfigDocument, failure::Error> {
+        Ok(match self {
+            Format::Json => serde_json::from_str(s)?,
+            Format::Toml => toml::from_str(s)?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Secrets {
+    Include,
+    // Private and preshared keys are left out. The other fields are enough to review or diff a
+    // config, but the result can't be imported again.
+    Redact,
+}
+
+// A config in a form that can be read and written by other tools. Keys are base64 strings, the
+// same as in a config file. Keys that are read from elsewhere are exported as where they're read
+// from rather than as the key itself.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigDocument {
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub redacted: bool,
+    pub interface: InterfaceDocument,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peers: Vec<PeerDocument>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceDocument {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<PrivateKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key_file: Option<KeySource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub address: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_up: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_up: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_down: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_down: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub save_config: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerDocument {
+    pub public_key: PublicKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preshared_key: Option<PresharedKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preshared_key_file: Option<KeySource>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn to_strings<T: ToString>(values: &[T]) -> Vec<String> {
+    values.iter().map(ToString::to_string).collect()
+}
+
+impl ConfigDocument {
+    pub fn from_config(config: &Config, secrets: Secrets) -> Self {
+        let include_secrets = secrets == Secrets::Include;
+        let interface = &config.interface;
+
+        Self {
+            redacted: !include_secrets,
+            interface: InterfaceDocument {
+                private_key: Some(*interface.private_key.as_bytes())
+                    .filter(|_| include_secrets && interface.private_key_source.is_none())
+                    .map(PrivateKey::from),
+                private_key_file: interface.private_key_source.clone(),
+                listen_port: interface.listen_port,
+                fwmark: interface.fwmark,
+                address: to_strings(&interface.address),
+                dns: to_strings(&interface.dns),
+                mtu: interface.mtu,
+                table: interface.table.as_ref().map(ToString::to_string),
+                pre_up: interface.pre_up.clone(),
+                post_up: interface.post_up.clone(),
+                pre_down: interface.pre_down.clone(),
+                post_down: interface.post_down.clone(),
+                save_config: interface.save_config,
+            },
+            peers: config
+                .peers
+                .iter()
+                .map(|peer| PeerDocument {
+                    public_key: peer.public_key.clone(),
+                    preshared_key: peer
+                        .preshared_key
+                        .as_ref()
+                        .filter(|_| include_secrets && peer.preshared_key_source.is_none())
+                        .map(|preshared_key| PresharedKey::from(*preshared_key.as_bytes())),
+                    preshared_key_file: peer
+                        .preshared_key
+                        .as_ref()
+                        .and(peer.preshared_key_source.clone()),
+                    allowed_ips: to_strings(&peer.allowed_ips.0),
+                    endpoint: peer.endpoint.as_ref().map(ToString::to_string),
+                    persistent_keepalive: peer.persistent_keepalive,
+                })
+                .collect(),
+        }
+    }
+
+    // The document is written out as a config file and read back by the same parser, so it's
+    // held to exactly the same rules, and its warnings are returned the same way.
+    pub fn into_config(self, name: String) -> Result<(Config, Diagnostics), failure::Error> {
+        if self.redacted {
+            return Err(RedactedDocumentError.into());
+        }
+        Config::from_source(name, &self.to_conf_source()?, None)
+    }
+
+    fn to_conf_source(&self) -> Result<String, failure::Error> {
+        let interface = &self.interface;
+        let mut fields = vec![];
+
+        if let Some(private_key) = &interface.private_key {
+            fields.push(("PrivateKey", private_key.expose_secret()));
+        }
+        if let Some(private_key_file) = &interface.private_key_file {
+            fields.push(("PrivateKeyFile", private_key_file.to_string()));
+        }
+        if let Some(listen_port) = interface.listen_port {
+            fields.push(("ListenPort", listen_port.to_string()));
+        }
+        if let Some(fwmark) = interface.fwmark {
+            fields.push(("FwMark", fwmark.to_string()));
+        }
+        for address in &interface.address {
+            fields.push(("Address", address.clone()));
+        }
+        for dns in &interface.dns {
+            fields.push(("DNS", dns.clone()));
+        }
+        if let Some(mtu) = interface.mtu {
+            fields.push(("MTU", mtu.to_string()));
+        }
+        if let Some(table) = &interface.table {
+            fields.push(("Table", table.clone()));
+        }
+        for (key, commands) in &[
+            ("PreUp", &interface.pre_up),
+            ("PostUp", &interface.post_up),
+            ("PreDown", &interface.pre_down),
+            ("PostDown", &interface.post_down),
+        ] {
+            for command in commands.iter() {
+                fields.push((key, command.clone()));
+            }
+        }
+        if let Some(save_config) = interface.save_config {
+            fields.push(("SaveConfig", save_config.to_string()));
+        }
+
+        let mut source = String::new();
+        write_section(&mut source, "Interface", &fields)?;
+
+        for peer in &self.peers {
+            let mut fields = vec![("PublicKey", peer.public_key.to_string())];
+
+            if let Some(preshared_key) = &peer.preshared_key {
+                fields.push(("PresharedKey", preshared_key.expose_secret()));
+            }
+            if let Some(preshared_key_file) = &peer.preshared_key_file {
+                fields.push(("PresharedKeyFile", preshared_key_file.to_string()));
+            }
+            for allowed_ip in &peer.allowed_ips {
+                fields.push(("AllowedIPs", allowed_ip.clone()));
+            }
+            if let Some(endpoint) = &peer.endpoint {
+                fields.push(("Endpoint", endpoint.clone()));
+            }
+            if let Some(persistent_keepalive) = peer.persistent_keepalive {
+                fields.push(("PersistentKeepalive", persistent_keepalive.to_string()));
+            }
+
+            source.push('\n');
+            write_section(&mut source, "Peer", &fields)?;
+        }
+
+        Ok(source)
+    }
+}
+
+// Values that would end the line early or start a comment would be read back as something else,
+// so they're refused instead.
+fn write_section(
+    source: &mut String,
+    name: &str,
+    fields: &[(&'static str, String)],
+) -> Result<(), failure::Error> {
+    writeln!(source, "[{}]", name)?;
+
+    for (key, value) in fields {
+        if value.contains(|c| c == '\n' || c == '\r' || c == '#') {
+            return Err(UnrepresentableValueError { key }.into());
+        }
+        writeln!(source, "{} = {}", key, value)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "redacted configs can't be imported since their keys were left out")]
+pub struct RedactedDocumentError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} values can't contain line breaks or '#'", key)]
+pub struct UnrepresentableValueError {
+    key: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigDocument, Format, Secrets};
+    use crate::config::Config;
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+FwMark = 0x1234
+Address = 10.0.0.1/24, fd00::1/64
+DNS = 10.0.0.53, example.com
+MTU = 1420
+Table = off
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+PostUp = iptables -t nat -A POSTROUTING -o eth0 -j MASQUERADE
+SaveConfig = false
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+PresharedKey = FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=
+AllowedIPs = 10.0.0.2/32, fd00::2/128
+Endpoint = vpn.example.com:51820
+PersistentKeepalive = 25
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.0.0.3
+";
+
+    fn config() -> Result<Config, failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        config.ok_or_else(|| diagnostics.into())
+    }
+
+    #[test]
+    fn round_trips_through_json_and_toml() -> Result<(), failure::Error> {
+        let config = config()?;
+
+        for format in &[Format::Json, Format::Toml] {
+            let exported =
+                format.serialize(&ConfigDocument::from_config(&config, Secrets::Include))?;
+            let imported = format
+                .deserialize(&exported)?
+                .into_config("wg0".to_string())?
+                .0;
+            assert_eq!(imported.to_string(), config.to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keys_are_base64_and_redactable() -> Result<(), failure::Error> {
+        let config = config()?;
+
+        let exported =
+            Format::Json.serialize(&ConfigDocument::from_config(&config, Secrets::Include))?;
+        assert!(
+            exported.contains("\"private_key\": \"yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\"")
+        );
+
+        let redacted =
+            Format::Json.serialize(&ConfigDocument::from_config(&config, Secrets::Redact))?;
+        assert!(!redacted.contains("private_key"));
+        assert!(!redacted.contains("preshared_key"));
+        assert!(redacted.contains("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="));
+
+        let result = Format::Json
+            .deserialize(&redacted)?
+            .into_config("wg0".to_string());
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn imports_are_validated_like_config_files() -> Result<(), failure::Error> {
+        let invalid = r#"
+            [interface]
+            private_key = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk="
+            mtu = 20
+            table = "main table"
+
+            [[peers]]
+            public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+            allowed_ips = ["10.0.0.256/32"]
+        "#;
+        let error = Format::Toml
+            .deserialize(invalid)?
+            .into_config("wg0".to_string())
+            .err()
+            .map(|error| error.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("MTUs must be a number no smaller than 68"));
+        assert!(error.contains("tables must be"));
+        assert!(error.contains("3 errors"));
+
+        let bad_key = r#"{"interface": {"private_key": "not a key"}}"#;
+        assert!(Format::Json.deserialize(bad_key).is_err());
+
+        let injected = r#"{"interface": {
+            "private_key": "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=",
+            "post_up": ["true\n[Peer]"]
+        }}"#;
+        assert!(Format::Json
+            .deserialize(injected)?
+            .into_config("wg0".to_string())
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+use super::{Config, Peer, PublicKey};
+use std::collections::BTreeSet;
+use std::fmt;
+use wireguard_uapi::get;
+
+// A field whose value on the device no longer matches the config.
+pub struct Difference {
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+pub struct PeerDrift {
+    pub public_key: PublicKey,
+    pub differences: Vec<Difference>,
+}
+
+// Changes made to the device outside of this server, such as through `wg set`. Added peers are on
+// the device but not in the config, and removed peers are in the config but not on the device.
+pub struct Drift {
+    pub interface: Vec<Difference>,
+    pub added_peers: Vec<PublicKey>,
+    pub removed_peers: Vec<PublicKey>,
+    pub changed_peers: Vec<PeerDrift>,
+}
+
+impl Drift {
+    pub fn between(config: &Config, device: &get::Device) -> Self {
+        let mut interface = vec![];
+
+        if device.private_key.as_ref() != Some(config.interface.private_key.as_bytes()) {
+            interface.push(key_difference("PrivateKey"));
+        }
+
+        // Without a ListenPort the kernel picks a random port, so any port is expected.
+        if let Some(listen_port) = config.interface.listen_port {
+            if listen_port != device.listen_port {
+                interface.push(Difference {
+                    field: "ListenPort",
+                    expected: listen_port.to_string(),
+                    actual: device.listen_port.to_string(),
+                });
+            }
+        }
+
+        let fwmark = config.interface.fwmark.unwrap_or(0);
+        if fwmark != device.fwmark {
+            interface.push(Difference {
+                field: "FwMark",
+                expected: fwmark.to_string(),
+                actual: device.fwmark.to_string(),
+            });
+        }
+
+        let find_config_peer = |public_key: &[u8; 32]| {
+            config
+                .peers
+                .iter()
+                .find(|peer| peer.public_key.as_bytes() == public_key)
+        };
+
+        let added_peers = device
+            .peers
+            .iter()
+            .filter(|device_peer| find_config_peer(&device_peer.public_key).is_none())
+            .map(|device_peer| PublicKey::from(device_peer.public_key))
+            .collect();
+
+        let removed_peers = config
+            .peers
+            .iter()
+            .filter(|peer| {
+                !device
+                    .peers
+                    .iter()
+                    .any(|device_peer| &device_peer.public_key == peer.public_key.as_bytes())
+            })
+            .map(|peer| peer.public_key.clone())
+            .collect();
+
+        let changed_peers = device
+            .peers
+            .iter()
+            .filter_map(|device_peer| {
+                let peer = find_config_peer(&device_peer.public_key)?;
+                let differences = peer_differences(peer, device_peer);
+                if differences.is_empty() {
+                    return None;
+                }
+                Some(PeerDrift {
+                    public_key: peer.public_key.clone(),
+                    differences,
+                })
+            })
+            .collect();
+
+        Self {
+            interface,
+            added_peers,
+            removed_peers,
+            changed_peers,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interface.is_empty()
+            && self.added_peers.is_empty()
+            && self.removed_peers.is_empty()
+            && self.changed_peers.is_empty()
+    }
+}
+
+// Endpoints aren't compared. WireGuard updates a peer's endpoint whenever it roams, so a
+// difference there is expected rather than a sign of someone changing the device.
+fn peer_differences(peer: &Peer, device_peer: &get::Peer) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    let preshared_key = peer
+        .preshared_key
+        .as_ref()
+        .map_or([0u8; 32], |preshared_key| *preshared_key.as_bytes());
+    if preshared_key != device_peer.preshared_key {
+        differences.push(key_difference("PresharedKey"));
+    }
+
+    // The kernel may report allowed IPs in a different order than they were given in.
+    let expected_ips: BTreeSet<String> = peer
+        .allowed_ips
+        .0
+        .iter()
+        .map(|allowed_ip| format_allowed_ip(&allowed_ip.into()))
+        .collect();
+    let actual_ips: BTreeSet<String> = device_peer
+        .allowed_ips
+        .iter()
+        .map(format_allowed_ip)
+        .collect();
+    if expected_ips != actual_ips {
+        let join = |ips: BTreeSet<String>| ips.into_iter().collect::<Vec<String>>().join(", ");
+        differences.push(Difference {
+            field: "AllowedIPs",
+            expected: join(expected_ips),
+            actual: join(actual_ips),
+        });
+    }
+
+    let persistent_keepalive = peer.persistent_keepalive.unwrap_or(0);
+    if persistent_keepalive != device_peer.persistent_keepalive_interval {
+        differences.push(Difference {
+            field: "PersistentKeepalive",
+            expected: persistent_keepalive.to_string(),
+            actual: device_peer.persistent_keepalive_interval.to_string(),
+        });
+    }
+
+    differences
+}
+
+fn format_allowed_ip(allowed_ip: &get::AllowedIp) -> String {
+    format!("{}/{}", allowed_ip.ipaddr, allowed_ip.cidr_mask)
+}
+
+// Keys are never shown, only that they don't match.
+fn key_difference(field: &'static str) -> Difference {
+    Difference {
+        field,
+        expected: "(hidden)".to_string(),
+        actual: "(differs)".to_string(),
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No drift");
+        }
+
+        for difference in &self.interface {
+            writeln!(f, "{}", difference)?;
+        }
+        for public_key in &self.added_peers {
+            writeln!(f, "Peer {} was added", public_key)?;
+        }
+        for public_key in &self.removed_peers {
+            writeln!(f, "Peer {} was removed", public_key)?;
+        }
+        for peer in &self.changed_peers {
+            writeln!(f, "Peer {} was changed", peer.public_key)?;
+            for difference in &peer.differences {
+                writeln!(f, "  {}", difference)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is {}, expected {}",
+            self.field, self.actual, self.expected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Drift;
+    use crate::config::{Config, PrivateKey, PublicKey};
+    use std::time::Duration;
+    use wireguard_uapi::get;
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32, 10.0.2.0/24
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.0.0.3/32
+";
+
+    fn device_peer(
+        public_key: &str,
+        allowed_ips: &[(&str, u8)],
+    ) -> Result<get::Peer, failure::Error> {
+        let allowed_ips = allowed_ips
+            .iter()
+            .map(|(ipaddr, cidr_mask)| {
+                Ok(get::AllowedIp {
+                    family: libc::AF_INET as u16,
+                    ipaddr: ipaddr.parse()?,
+                    cidr_mask: *cidr_mask,
+                })
+            })
+            .collect::<Result<_, failure::Error>>()?;
+
+        Ok(get::Peer {
+            public_key: *public_key.parse::<PublicKey>()?.as_bytes(),
+            preshared_key: [0u8; 32],
+            endpoint: None,
+            persistent_keepalive_interval: 0,
+            last_handshake_time: Duration::from_secs(0),
+            rx_bytes: 0,
+            tx_bytes: 0,
+            allowed_ips,
+            protocol_version: 1,
+        })
+    }
+
+    fn device(listen_port: u16, peers: Vec<get::Peer>) -> Result<get::Device, failure::Error> {
+        let private_key: PrivateKey = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=".parse()?;
+        Ok(get::Device {
+            ifindex: 4,
+            ifname: "wg0".to_string(),
+            private_key: Some(*private_key.as_bytes()),
+            public_key: None,
+            listen_port,
+            fwmark: 0,
+            peers,
+        })
+    }
+
+    fn config() -> Result<Config, failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        config.ok_or_else(|| diagnostics.into())
+    }
+
+    #[test]
+    fn matching_device_has_no_drift() -> Result<(), failure::Error> {
+        let device = device(
+            51820,
+            vec![
+                // Allowed IPs in a different order are still a match.
+                device_peer(
+                    "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+                    &[("10.0.2.0", 24), ("10.0.0.2", 32)],
+                )?,
+                device_peer(
+                    "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=",
+                    &[("10.0.0.3", 32)],
+                )?,
+            ],
+        )?;
+
+        assert!(Drift::between(&config()?, &device).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_each_kind_of_drift() -> Result<(), failure::Error> {
+        let mut changed = device_peer(
+            "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            &[("10.0.0.2", 32)],
+        )?;
+        changed.preshared_key = [1u8; 32];
+        let added = device_peer(
+            "SwgTyJpz0og0NH/1YagZ2pWuaR06b0nlVUUo0WFdbAY=",
+            &[("10.0.0.4", 32)],
+        )?;
+        let device = device(51821, vec![changed, added])?;
+
+        let drift = Drift::between(&config()?, &device);
+        assert_eq!(
+            drift.to_string(),
+            "\
+ListenPort is 51821, expected 51820
+Peer SwgTyJpz0og0NH/1YagZ2pWuaR06b0nlVUUo0WFdbAY= was added
+Peer TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0= was removed
+Peer xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg= was changed
+  PresharedKey is (differs), expected (hidden)
+  AllowedIPs is 10.0.0.2/32, expected 10.0.0.2/32, 10.0.2.0/24
+"
+        );
+        Ok(())
+    }
+}
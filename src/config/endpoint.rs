@@ -0,0 +1,194 @@
+use crate::impl_with_fromstr_with_error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+#[derive(Clone, PartialEq)]
+pub enum Host {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+// A peer endpoint as written in the config. Hostnames are kept as they were given so they can be
+// written back out and resolved again later, as the address behind them may change.
+#[derive(Clone)]
+pub struct Endpoint {
+    pub host: Host,
+    pub port: u16,
+    resolved: Option<SocketAddr>,
+}
+
+impl Endpoint {
+    pub fn is_hostname(&self) -> bool {
+        match self.host {
+            Host::Domain(_) => true,
+            Host::Ip(_) => false,
+        }
+    }
+
+    // The address to hand to WireGuard. This is None for a hostname that hasn't been resolved yet.
+    pub fn resolved(&self) -> Option<&SocketAddr> {
+        self.resolved.as_ref()
+    }
+
+    pub fn resolve(&mut self) -> Result<SocketAddr, failure::Error> {
+        let addr = lookup(&self.host, self.port)?;
+        self.resolved = Some(addr);
+        Ok(addr)
+    }
+
+    pub fn set_resolved(&mut self, addr: SocketAddr) {
+        self.resolved = Some(addr);
+    }
+}
+
+// Like wg(8), the first address returned by the resolver is used.
+pub fn lookup(host: &Host, port: u16) -> Result<SocketAddr, failure::Error> {
+    match host {
+        Host::Ip(ip) => Ok(SocketAddr::new(*ip, port)),
+        Host::Domain(domain) => (domain.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                UnresolvableHostError {
+                    host: domain.clone(),
+                }
+                .into()
+            }),
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            host: Host::Ip(addr.ip()),
+            port: addr.port(),
+            resolved: Some(addr),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        // Covers ipv4:port and [ipv6]:port.
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            if addr.port() == 0 {
+                return Err(InvalidPortError.into());
+            }
+            return Ok(addr.into());
+        }
+
+        let colon = s.rfind(':').ok_or(MissingPortError)?;
+        let (host, port) = (&s[..colon], &s[colon + 1..]);
+        let port = match port.parse() {
+            Ok(0) | Err(_) => return Err(InvalidPortError.into()),
+            Ok(port) => port,
+        };
+
+        // A numeric last label, as in 10.0.0.300, is a mistyped address rather than a hostname.
+        let is_valid_hostname = !host.is_empty()
+            && host.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            && !host
+                .rsplit('.')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .all(|c| c.is_ascii_digit());
+
+        if !is_valid_hostname {
+            return Err(InvalidHostError.into());
+        }
+
+        Ok(Self {
+            host: Host::Domain(host.to_string()),
+            port,
+            resolved: None,
+        })
+    }
+}
+
+impl_with_fromstr_with_error!(Endpoint);
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.host {
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]:{}", ip, self.port),
+            Host::Ip(ip) => write!(f, "{}:{}", ip, self.port),
+            Host::Domain(domain) => write!(f, "{}:{}", domain, self.port),
+        }
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "endpoints must end with a port, such as example.com:51820")]
+struct MissingPortError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "endpoint ports must be a number between 1 and 65535")]
+struct InvalidPortError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "endpoints must be a hostname, an IPv4 address or a bracketed IPv6 address")]
+struct InvalidHostError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} did not resolve to any addresses", host)]
+pub struct UnresolvableHostError {
+    host: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endpoint, Host};
+
+    #[test]
+    fn parses_each_host_form() -> Result<(), failure::Error> {
+        for input in &[
+            "vpn.example.com:51820",
+            "192.0.2.1:51820",
+            "[2001:db8::1]:51820",
+        ] {
+            let endpoint: Endpoint = input.parse()?;
+            assert_eq!(&endpoint.to_string(), input);
+        }
+
+        let endpoint: Endpoint = "vpn.example.com:51820".parse()?;
+        assert!(endpoint.host == Host::Domain("vpn.example.com".to_string()));
+        assert!(endpoint.resolved().is_none());
+
+        let endpoint: Endpoint = "192.0.2.1:51820".parse()?;
+        assert_eq!(endpoint.resolved(), Some(&"192.0.2.1:51820".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_endpoints() {
+        for input in &[
+            "vpn.example.com",
+            "vpn.example.com:port",
+            "2001:db8::1:51820",
+            "bad host:1",
+            "10.0.0.300:51820",
+            "vpn.example.300:51820",
+            "vpn.example.com:0",
+            "192.0.2.1:0",
+            "[2001:db8::1]:0",
+        ] {
+            assert!(
+                input.parse::<Endpoint>().is_err(),
+                "{} should not parse",
+                input
+            );
+        }
+    }
+}
@@ -1,24 +1,451 @@
-use super::PrivateKey;
-use failure;
+use super::conf_file::Section;
+use super::diagnostics::Diagnostic;
+use super::{KeySource, PrivateKey};
 use ipnet::IpNet;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+// Every key an [Interface] section may have, as written by Interface::fields.
+pub const KEYS: &[&str] = &[
+    "PrivateKey",
+    "PrivateKeyFile",
+    "ListenPort",
+    "FwMark",
+    "Address",
+    "DNS",
+    "MTU",
+    "Table",
+    "PreUp",
+    "PostUp",
+    "PreDown",
+    "PostDown",
+    "SaveConfig",
+];
 
 pub struct Interface {
     // wg fields
     pub private_key: PrivateKey,
+    // Where the private key was read from, if it isn't in the config file itself. The key is never
+    // written back to the config file when it has a source.
+    pub private_key_source: Option<KeySource>,
     pub listen_port: Option<u16>,
+    pub fwmark: Option<u32>,
 
     // wg-quick fields
-    pub address: Vec<IpNet>,
-    // TODO: There are more wg-quick fields that we should support and add here.
     // https://github.com/WireGuard/WireGuard/blob/516f05862edd0bb135dcb9c93acaa67ff5b676ed/src/tools/man/wg-quick.8#L68
+    pub address: Vec<IpNet>,
+    pub dns: Vec<Dns>,
+    pub mtu: Option<u16>,
+    pub table: Option<Table>,
+    pub pre_up: Vec<String>,
+    pub post_up: Vec<String>,
+    pub pre_down: Vec<String>,
+    pub post_down: Vec<String>,
+    pub save_config: Option<bool>,
 }
 
 impl Interface {
     pub fn new() -> Result<Self, failure::Error> {
-        Ok(Self {
-            private_key: PrivateKey::new()?,
+        Ok(Self::with_private_key(PrivateKey::new()?))
+    }
+
+    // An interface that uses an existing key, such as one read back from the device.
+    pub fn with_private_key(private_key: PrivateKey) -> Self {
+        Self {
+            private_key,
+            private_key_source: None,
             listen_port: None,
+            fwmark: None,
             address: vec![],
+            dns: vec![],
+            mtu: None,
+            table: None,
+            pre_up: vec![],
+            post_up: vec![],
+            pre_down: vec![],
+            post_down: vec![],
+            save_config: None,
+        }
+    }
+
+    // Reads the [Interface] section, reporting every problem found rather than stopping at the
+    // first one. Returns None if any of them were errors.
+    pub fn from_section(section: &mut Section, diagnostics: &mut Vec<Diagnostic>) -> Option<Self> {
+        let private_key = section.require_secret("PrivateKey", "PrivateKeyFile", diagnostics);
+        let listen_port = section.parse("ListenPort", diagnostics);
+        let fwmark = section.parse::<FwMark>("FwMark", diagnostics);
+        let address = section.parse_list("Address", diagnostics);
+        let dns = section.parse_list("DNS", diagnostics);
+        let mtu = section.parse::<Mtu>("MTU", diagnostics);
+        let table = section.parse("Table", diagnostics);
+        let pre_up = take_commands(section, "PreUp");
+        let post_up = take_commands(section, "PostUp");
+        let pre_down = take_commands(section, "PreDown");
+        let post_down = take_commands(section, "PostDown");
+        let save_config = section.parse("SaveConfig", diagnostics);
+
+        section.warn_unknown(diagnostics);
+        if section.has_errors() {
+            return None;
+        }
+
+        let (private_key, private_key_source) = private_key?;
+        Some(Self {
+            private_key,
+            private_key_source,
+            listen_port,
+            fwmark: fwmark.map(|fwmark| fwmark.0),
+            address,
+            dns,
+            mtu: mtu.map(|mtu| mtu.0),
+            table,
+            pre_up,
+            post_up,
+            pre_down,
+            post_down,
+            save_config,
         })
     }
 }
+
+fn take_commands(section: &mut Section, key: &str) -> Vec<String> {
+    section
+        .take_all(key)
+        .into_iter()
+        .map(|field| field.value)
+        .collect()
+}
+
+impl Interface {
+    // The fields as they're written to a config file, in the order they're written. Keys that may
+    // be repeated appear once per value.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![match &self.private_key_source {
+            Some(source) => ("PrivateKeyFile", source.to_string()),
+            None => ("PrivateKey", self.private_key.expose_secret()),
+        }];
+
+        if let Some(listen_port) = &self.listen_port {
+            fields.push(("ListenPort", listen_port.to_string()));
+        }
+
+        if let Some(fwmark) = self.fwmark {
+            fields.push(("FwMark", FwMark(fwmark).to_string()));
+        }
+
+        if !self.address.is_empty() {
+            fields.push(("Address", join(&self.address)));
+        };
+
+        if !self.dns.is_empty() {
+            fields.push(("DNS", join(&self.dns)));
+        };
+
+        if let Some(mtu) = &self.mtu {
+            fields.push(("MTU", mtu.to_string()));
+        }
+
+        if let Some(table) = &self.table {
+            fields.push(("Table", table.to_string()));
+        }
+
+        for (key, commands) in &[
+            ("PreUp", &self.pre_up),
+            ("PostUp", &self.post_up),
+            ("PreDown", &self.pre_down),
+            ("PostDown", &self.post_down),
+        ] {
+            for command in commands.iter() {
+                fields.push((key, command.clone()));
+            }
+        }
+
+        if let Some(save_config) = &self.save_config {
+            fields.push(("SaveConfig", save_config.to_string()));
+        }
+
+        fields
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[Interface]")?;
+
+        for (key, value) in self.fields() {
+            writeln!(f, "{} = {}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// wg-quick treats entries that aren't IP addresses as DNS search domains.
+pub enum Dns {
+    Server(IpAddr),
+    SearchDomain(String),
+}
+
+impl FromStr for Dns {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Dns::Server(addr));
+        }
+
+        let is_valid_domain = !s.is_empty()
+            && s.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+
+        if !is_valid_domain {
+            return Err(InvalidDnsError.into());
+        }
+
+        Ok(Dns::SearchDomain(s.to_string()))
+    }
+}
+
+impl fmt::Display for Dns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dns::Server(addr) => write!(f, "{}", addr),
+            Dns::SearchDomain(domain) => write!(f, "{}", domain),
+        }
+    }
+}
+
+pub enum Table {
+    Off,
+    Auto,
+    Id(u32),
+    Name(String),
+}
+
+impl FromStr for Table {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Table::Off),
+            "auto" => Ok(Table::Auto),
+            _ => match s.parse() {
+                Ok(id) => Ok(Table::Id(id)),
+                // Names are looked up in /etc/iproute2/rt_tables by ip-route.
+                Err(_) if !s.is_empty() && !s.contains(char::is_whitespace) => {
+                    Ok(Table::Name(s.to_string()))
+                }
+                Err(_) => Err(InvalidTableError.into()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Table::Off => write!(f, "off"),
+            Table::Auto => write!(f, "auto"),
+            Table::Id(id) => write!(f, "{}", id),
+            Table::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// Follows the same rules as wg(8): "off" or 0 disables the fwmark, and a 0x prefix is read as
+// hexadecimal.
+struct FwMark(u32);
+
+impl FromStr for FwMark {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "off" {
+            return Ok(FwMark(0));
+        }
+
+        let fwmark = if s.starts_with("0x") {
+            u32::from_str_radix(&s[2..], 16)
+        } else {
+            s.parse()
+        };
+
+        fwmark.map(FwMark).map_err(|_| InvalidFwMarkError.into())
+    }
+}
+
+impl fmt::Display for FwMark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "off"),
+            fwmark => write!(f, "0x{:x}", fwmark),
+        }
+    }
+}
+
+// The kernel refuses MTUs below the IPv4 minimum of 68 bytes.
+const MIN_MTU: u16 = 68;
+
+struct Mtu(u16);
+
+impl FromStr for Mtu {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(mtu) if mtu >= MIN_MTU => Ok(Mtu(mtu)),
+            _ => Err(InvalidMtuError.into()),
+        }
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "DNS entries must be an IP address or a search domain")]
+struct InvalidDnsError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "tables must be \"off\", \"auto\", a table number or a table name")]
+struct InvalidTableError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "fwmarks must be \"off\" or a 32-bit number, optionally prefixed with 0x")]
+struct InvalidFwMarkError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "MTUs must be a number no smaller than 68")]
+struct InvalidMtuError;
+
+#[cfg(test)]
+mod tests {
+    use super::{Dns, Table};
+    use crate::config::diagnostics::Severity;
+    use crate::config::Config;
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+FwMark = 0x1f
+Address = 10.0.0.1/24
+DNS = 10.0.0.53, fd00::53, internal.example.com
+MTU = 1420
+Table = 1234
+PreUp = echo pre up
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+PostUp = ip6tables -A FORWARD -i %i -j ACCEPT
+PreDown = echo pre down
+PostDown = iptables -D FORWARD -i %i -j ACCEPT
+SaveConfig = false
+";
+
+    fn parse(source: &str) -> (Option<Config>, Vec<(usize, Severity, String)>) {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), source, None);
+        let items = diagnostics
+            .items
+            .iter()
+            .map(|item| (item.line_num, item.severity, item.message.clone()))
+            .collect();
+        (config, items)
+    }
+
+    #[test]
+    fn wg_quick_fields_are_read_and_written_back() -> Result<(), failure::Error> {
+        let (config, diagnostics) = parse(CONF);
+        assert!(diagnostics.is_empty());
+        let interface = config
+            .ok_or_else(|| failure::format_err!("config didn't parse"))?
+            .interface;
+
+        assert_eq!(interface.fwmark, Some(0x1f));
+        assert!(matches!(
+            interface.dns.as_slice(),
+            [Dns::Server(_), Dns::Server(_), Dns::SearchDomain(domain)]
+                if domain == "internal.example.com"
+        ));
+        assert_eq!(interface.mtu, Some(1420));
+        assert!(matches!(interface.table, Some(Table::Id(1234))));
+        assert_eq!(interface.pre_up, vec!["echo pre up"]);
+        assert_eq!(interface.post_up.len(), 2);
+        assert_eq!(interface.pre_down, vec!["echo pre down"]);
+        assert_eq!(interface.post_down.len(), 1);
+        assert_eq!(interface.save_config, Some(false));
+
+        // Lists are written without spaces after the commas.
+        assert_eq!(interface.to_string(), CONF.replace(", ", ","));
+        Ok(())
+    }
+
+    #[test]
+    fn tables_and_fwmarks_have_special_values() -> Result<(), failure::Error> {
+        for (input, output) in &[
+            ("off", "off"),
+            ("auto", "auto"),
+            ("wg", "wg"),
+            ("51820", "51820"),
+        ] {
+            assert_eq!(input.parse::<Table>()?.to_string(), *output);
+        }
+
+        for fwmark in &["off", "0"] {
+            let source = CONF.replace("FwMark = 0x1f", &format!("FwMark = {}", fwmark));
+            let (config, _) = parse(&source);
+            let interface = config
+                .ok_or_else(|| failure::format_err!("config didn't parse"))?
+                .interface;
+            assert_eq!(interface.fwmark, Some(0));
+            assert!(interface.to_string().contains("FwMark = off\n"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_values_are_all_reported() {
+        let source = CONF
+            .replace("FwMark = 0x1f", "FwMark = 0xzz")
+            .replace("internal.example.com", "internal..example.com")
+            .replace("MTU = 1420", "MTU = 20")
+            .replace("Table = 1234", "Table = main table")
+            .replace("SaveConfig = false", "SaveConfig = maybe");
+        let (config, diagnostics) = parse(&source);
+        assert!(config.is_none());
+
+        let errors: Vec<usize> = diagnostics
+            .iter()
+            .filter(|(_, severity, _)| *severity == Severity::Error)
+            .map(|(line_num, _, _)| *line_num)
+            .collect();
+        assert_eq!(errors, vec![3, 5, 6, 7, 13]);
+        assert!(diagnostics
+            .iter()
+            .any(|(_, _, message)| message.contains("MTUs must be a number no smaller than 68")));
+    }
+
+    #[test]
+    fn unknown_keys_are_only_warned_about() -> Result<(), failure::Error> {
+        let source = CONF.replace("MTU = 1420", "MTU = 1420\nMaxMTU = 1280");
+        let (config, diagnostics) = parse(&source);
+        assert!(config.is_some());
+        assert_eq!(
+            diagnostics,
+            vec![(
+                7,
+                Severity::Warning,
+                "unknown field MaxMTU will be ignored".to_string()
+            )]
+        );
+        Ok(())
+    }
+}
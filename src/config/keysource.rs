@@ -0,0 +1,174 @@
+use crate::impl_serde_with_fromstr;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Where a secret is read from when it's kept out of the config file. These are given in fields such
+// as PrivateKeyFile, and are written back to the config file in place of the secret itself.
+//
+//   /etc/wireguard/wg0.key    a file, which must be given as an absolute path
+//   env:WG0_PRIVATE_KEY       an environment variable
+//   credential:wg0.key        a systemd credential, read from $CREDENTIALS_DIRECTORY
+#[derive(Clone, PartialEq)]
+pub enum KeySource {
+    File(PathBuf),
+    Env(String),
+    Credential(String),
+}
+
+impl KeySource {
+    // Surrounding whitespace is ignored, since key files usually end with a newline.
+    pub fn read(&self) -> Result<String, failure::Error> {
+        let secret = match self {
+            KeySource::File(path) => fs::read_to_string(path)?,
+            KeySource::Env(name) => {
+                env::var(name).map_err(|_| MissingEnvError { name: name.clone() })?
+            }
+            KeySource::Credential(name) => {
+                let credentials_dir =
+                    env::var_os("CREDENTIALS_DIRECTORY").ok_or(NoCredentialsDirectoryError)?;
+                fs::read_to_string(Path::new(&credentials_dir).join(name))?
+            }
+        };
+        Ok(secret.trim().to_string())
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("env:") {
+            let name = &s[4..];
+            if name.is_empty() || name.contains('=') {
+                return Err(InvalidEnvNameError.into());
+            }
+            return Ok(KeySource::Env(name.to_string()));
+        }
+
+        // systemd credential names can't contain slashes, which also keeps lookups from
+        // escaping the credentials directory.
+        if s.starts_with("credential:") {
+            let name = &s[11..];
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                return Err(InvalidCredentialNameError.into());
+            }
+            return Ok(KeySource::Credential(name.to_string()));
+        }
+
+        // The server's working directory has nothing to do with where its config is, so relative
+        // paths would be read from somewhere unexpected.
+        let path = PathBuf::from(s);
+        if !path.is_absolute() {
+            return Err(RelativeKeyPathError.into());
+        }
+        Ok(KeySource::File(path))
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeySource::File(path) => write!(f, "{}", path.display()),
+            KeySource::Env(name) => write!(f, "env:{}", name),
+            KeySource::Credential(name) => write!(f, "credential:{}", name),
+        }
+    }
+}
+
+impl_serde_with_fromstr!(KeySource);
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "environment variable {} isn't set", name)]
+pub struct MissingEnvError {
+    name: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "$CREDENTIALS_DIRECTORY isn't set, so systemd credentials can't be read")]
+pub struct NoCredentialsDirectoryError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "environment variable names can't be empty or contain '='")]
+struct InvalidEnvNameError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "credential names can't be empty or contain '/'")]
+struct InvalidCredentialNameError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "key files must be given as an absolute path")]
+struct RelativeKeyPathError;
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, PresharedKey};
+    use mktemp::Temp;
+    use std::env;
+    use std::fs;
+
+    const PRIVATE_KEY: &str = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=";
+    const PRESHARED_KEY: &str = "FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=";
+
+    #[test]
+    fn secrets_are_read_from_their_source_and_never_written_back() -> Result<(), failure::Error> {
+        let key_file = Temp::new_file()?;
+        fs::write(key_file.to_path_buf(), format!("{}\n", PRIVATE_KEY))?;
+        env::set_var("WG_WEB_SERVER_TEST_PSK", PRESHARED_KEY);
+
+        let source = format!(
+            "[Interface]\nPrivateKeyFile = {}\n\n[Peer]\n\
+             PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+             PresharedKeyFile = env:WG_WEB_SERVER_TEST_PSK\n",
+            key_file.to_path_buf().display()
+        );
+        let (config, diagnostics) =
+            Config::parse_with_diagnostics("wg0".to_string(), &source, None);
+        let config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+
+        assert_eq!(config.interface.private_key.expose_secret(), PRIVATE_KEY);
+        let preshared_key = config.peers[0]
+            .preshared_key
+            .as_ref()
+            .map(PresharedKey::expose_secret);
+        assert_eq!(preshared_key.as_deref(), Some(PRESHARED_KEY));
+
+        let written = config.to_string();
+        assert!(written.contains("PresharedKeyFile = env:WG_WEB_SERVER_TEST_PSK"));
+        assert!(!written.contains(PRIVATE_KEY));
+        assert!(!written.contains(PRESHARED_KEY));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_sources_are_reported() {
+        let source = format!(
+            "[Interface]\nPrivateKey = {}\nPrivateKeyFile = /etc/wireguard/wg0.key\n\n\
+             [Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+             PresharedKeyFile = wg0.psk\n\n\
+             [Peer]\nPublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\n\
+             PresharedKeyFile = env:WG_WEB_SERVER_TEST_UNSET\n",
+            PRIVATE_KEY
+        );
+        let (config, diagnostics) =
+            Config::parse_with_diagnostics("wg0".to_string(), &source, None);
+        assert!(config.is_none());
+
+        let messages: Vec<&str> = diagnostics
+            .items
+            .iter()
+            .map(|item| item.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "PrivateKey and PrivateKeyFile can't both be given",
+                "invalid PresharedKeyFile value: key files must be given as an absolute path",
+                "couldn't read PresharedKey from env:WG_WEB_SERVER_TEST_UNSET: environment \
+                 variable WG_WEB_SERVER_TEST_UNSET isn't set",
+            ]
+        );
+    }
+}
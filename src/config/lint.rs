@@ -0,0 +1,350 @@
+use super::conf_file::Section;
+use super::diagnostics::{Diagnostic, Severity};
+use super::peer::AllowedIps;
+use super::{Config, PublicKey};
+use ipnet::IpNet;
+use std::fmt;
+
+impl Config {
+    // Looks for mistakes that WireGuard accepts but that are rarely what was intended. Positions
+    // point into the file this config was read from.
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        let locator = Locator {
+            sections: self.conf_file.sections(),
+        };
+        let mut lints = vec![];
+
+        // Peer sections directly follow the [Interface] section.
+        let peer_section = |i: usize| i + 1;
+
+        for (i, peer) in self.peers.iter().enumerate() {
+            let duplicate = self.peers[..i]
+                .iter()
+                .position(|other| other.public_key.as_bytes() == peer.public_key.as_bytes());
+            if let Some(j) = duplicate {
+                lints.push(locator.field(
+                    peer_section(i),
+                    "PublicKey",
+                    Severity::Error,
+                    format!(
+                        "this public key is already used by section {}",
+                        peer_section(j) + 1
+                    ),
+                ));
+            }
+
+            if peer.endpoint.is_none() && peer.persistent_keepalive.is_none() {
+                lints.push(
+                    locator.header(
+                        peer_section(i),
+                        Severity::Warning,
+                        "this peer has no Endpoint or PersistentKeepalive, so it can't be reached \
+                     until it sends traffic first"
+                            .to_string(),
+                    ),
+                );
+            }
+        }
+
+        // Every allowed IP along with the peer it belongs to and its position within the peer.
+        let allowed_ips: Vec<(usize, usize, IpNet)> = self
+            .peers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, peer)| {
+                peer.allowed_ips
+                    .0
+                    .iter()
+                    .enumerate()
+                    .map(move |(n, allowed_ip)| (i, n, allowed_ip.net()))
+            })
+            .collect();
+
+        for (k, &(i, n, net)) in allowed_ips.iter().enumerate() {
+            let lint = |severity, message| {
+                locator.entry(peer_section(i), "AllowedIPs", n, severity, message)
+            };
+
+            let allowed_ip = &self.peers[i].allowed_ips.0[n];
+            if allowed_ip.has_host_bits() {
+                lints.push(lint(
+                    Severity::Warning,
+                    format!(
+                        "{} has host bits set, which are ignored. Did you mean {}?",
+                        allowed_ip, net
+                    ),
+                ));
+            }
+
+            if self.interface.listen_port.is_some() && net.prefix_len() == 0 {
+                lints.push(lint(
+                    Severity::Warning,
+                    format!(
+                        "{} sends all traffic to this peer, which is unusual for a server",
+                        net
+                    ),
+                ));
+            }
+
+            let earlier = allowed_ips[..k]
+                .iter()
+                .filter(|(j, _, _)| *j != i)
+                .find(|(_, _, other)| overlaps(&net, other));
+            if let Some(&(j, _, other)) = earlier {
+                lints.push(if net == other {
+                    // WireGuard moves the route to whichever peer was added last.
+                    lint(
+                        Severity::Error,
+                        format!(
+                            "{} is also in the AllowedIPs of section {}, but can only be routed \
+                             to one peer",
+                            net,
+                            peer_section(j) + 1
+                        ),
+                    )
+                } else {
+                    lint(
+                        Severity::Warning,
+                        format!(
+                            "{} overlaps {} from section {}",
+                            net,
+                            other,
+                            peer_section(j) + 1
+                        ),
+                    )
+                });
+            }
+        }
+
+        if !allowed_ips.is_empty() {
+            for (n, address) in self.interface.address.iter().enumerate() {
+                let is_routed = allowed_ips
+                    .iter()
+                    .any(|(_, _, net)| overlaps(&address.trunc(), net));
+                if !is_routed {
+                    lints.push(locator.entry(
+                        0,
+                        "Address",
+                        n,
+                        Severity::Warning,
+                        format!(
+                            "{} isn't in any peer's AllowedIPs, so nothing on its network can be \
+                             reached through this interface",
+                            address
+                        ),
+                    ));
+                }
+            }
+        }
+
+        lints
+    }
+
+    // The routes a peer with these AllowedIPs would take from the peers that have them now.
+    // WireGuard sends each address to the peer with the most specific prefix containing it, and
+    // moves a prefix that two peers share to whichever was given it last. A wider prefix than an
+    // existing peer's doesn't take anything from it.
+    pub fn routes_taken_over(
+        &self,
+        public_key: &PublicKey,
+        allowed_ips: &AllowedIps,
+    ) -> Vec<TakenOverRoute> {
+        let existing: Vec<(&PublicKey, IpNet)> = self
+            .peers
+            .iter()
+            .filter(|peer| peer.public_key.as_bytes() != public_key.as_bytes())
+            .flat_map(|peer| {
+                peer.allowed_ips
+                    .nets()
+                    .into_iter()
+                    .map(move |net| (&peer.public_key, net))
+            })
+            .collect();
+
+        allowed_ips
+            .nets()
+            .into_iter()
+            .filter_map(|net| {
+                existing
+                    .iter()
+                    .filter(|(_, other)| other.contains(&net))
+                    .max_by_key(|(_, other)| other.prefix_len())
+                    .map(|(from, other)| TakenOverRoute {
+                        net,
+                        from: (*from).clone(),
+                        through: *other,
+                    })
+            })
+            .collect()
+    }
+}
+
+pub struct TakenOverRoute {
+    pub net: IpNet,
+    pub from: PublicKey,
+    // The prefix the traffic is currently routed through, which may be wider than the one taken.
+    pub through: IpNet,
+}
+
+impl fmt::Display for TakenOverRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.net == self.through {
+            write!(f, "{} would no longer be routed to {}", self.net, self.from)
+        } else {
+            write!(
+                f,
+                "{} would no longer be routed to {}, which has {}",
+                self.net, self.from, self.through
+            )
+        }
+    }
+}
+
+fn overlaps(a: &IpNet, b: &IpNet) -> bool {
+    let (a, b) = (a.trunc(), b.trunc());
+    a.contains(&b) || b.contains(&a)
+}
+
+// Finds where sections and fields are in the file. Configs that weren't read from a file, or that
+// have changed since, fall back to the closest position that still exists.
+struct Locator {
+    sections: Vec<Section>,
+}
+
+impl Locator {
+    fn header(&self, section: usize, severity: Severity, message: String) -> Diagnostic {
+        match self.sections.get(section) {
+            Some(section) => section.diagnostic(severity, message),
+            None => Diagnostic::new(severity, message, 1, 1, 0),
+        }
+    }
+
+    fn field(&self, section: usize, key: &str, severity: Severity, message: String) -> Diagnostic {
+        self.entry(section, key, 0, severity, message)
+    }
+
+    // The nth comma separated entry across every line for the key.
+    fn entry(
+        &self,
+        section: usize,
+        key: &str,
+        n: usize,
+        severity: Severity,
+        message: String,
+    ) -> Diagnostic {
+        let entry = self.sections.get(section).and_then(|section| {
+            section
+                .values
+                .iter()
+                .filter(|field| field.key.eq_ignore_ascii_case(key))
+                .flat_map(|field| {
+                    field
+                        .entries()
+                        .into_iter()
+                        .map(move |(column, entry)| (field.line_num, column, entry.len()))
+                })
+                .nth(n)
+                .map(|position| (section, position))
+        });
+
+        match entry {
+            Some((section, (line_num, column, len))) => {
+                Diagnostic::new(severity, message, line_num, column, len)
+                    .in_section(section.index, &section.name)
+            }
+            None => self.header(section, severity, message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::diagnostics::Severity;
+    use crate::config::Config;
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+Address = 10.0.0.1/24, 10.9.0.1/24
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32, 10.0.5.1/24
+PersistentKeepalive = 25
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32
+Endpoint = 192.0.2.1:51820
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.0.0.0/24, ::/0
+";
+
+    #[test]
+    fn flags_common_mistakes() -> Result<(), failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        let config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+
+        let mut lints: Vec<(usize, usize, Severity)> = config
+            .lint()
+            .iter()
+            .map(|lint| (lint.line_num, lint.column, lint.severity))
+            .collect();
+        lints.sort_by_key(|(line_num, column, _)| (*line_num, *column));
+
+        assert_eq!(
+            lints,
+            vec![
+                // 10.9.0.1/24 isn't routed to any peer.
+                (4, 24, Severity::Warning),
+                // 10.0.5.1/24 has host bits set.
+                (8, 27, Severity::Warning),
+                (12, 13, Severity::Error),
+                (13, 14, Severity::Error),
+                (16, 1, Severity::Warning),
+                (18, 14, Severity::Warning),
+                // A default route on a server.
+                (18, 27, Severity::Warning),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn finds_routes_a_new_peer_would_take_over() -> Result<(), failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        let config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+
+        let taken_over: Vec<String> = config
+            .routes_taken_over(
+                &"hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=".parse()?,
+                &"10.0.0.2/32, 10.0.0.128/25, 10.0.0.0/16, fd00::1/128".parse()?,
+            )
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            taken_over,
+            vec![
+                "10.0.0.2/32 would no longer be routed to \
+                 xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+                "10.0.0.128/25 would no longer be routed to \
+                 TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=, which has 10.0.0.0/24",
+                "fd00::1/128 would no longer be routed to \
+                 TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=, which has ::/0",
+            ]
+        );
+
+        // A peer keeps its own routes.
+        assert!(config
+            .routes_taken_over(
+                &"TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=".parse()?,
+                &"10.0.0.0/24".parse()?,
+            )
+            .is_empty());
+        Ok(())
+    }
+}
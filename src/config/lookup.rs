@@ -0,0 +1,133 @@
+use super::allowedips::AllowedIp;
+use super::{Config, PublicKey};
+use ipnet::IpNet;
+use std::fmt;
+use std::net::IpAddr;
+use wireguard_uapi::get;
+
+// A prefix in a peer's AllowedIPs.
+pub struct Route {
+    pub public_key: PublicKey,
+    pub net: IpNet,
+}
+
+// Which peer an address belongs to, found the way WireGuard's cryptokey routing finds it: the most
+// specific prefix containing the address wins. Other prefixes containing it are shadowed, most
+// specific first.
+pub struct Lookup {
+    pub address: IpAddr,
+    pub owner: Option<Route>,
+    pub shadowed: Vec<Route>,
+}
+
+impl Lookup {
+    // A prefix given to more than one peer belongs to whichever was given it last, so of two equal
+    // prefixes the later one wins.
+    pub fn in_routes(address: IpAddr, routes: impl IntoIterator<Item = Route>) -> Self {
+        let mut matching: Vec<Route> = routes
+            .into_iter()
+            .filter(|route| route.net.contains(&address))
+            .collect();
+        matching.reverse();
+        matching.sort_by_key(|route| std::cmp::Reverse(route.net.prefix_len()));
+
+        let mut matching = matching.into_iter();
+        Self {
+            address,
+            owner: matching.next(),
+            shadowed: matching.collect(),
+        }
+    }
+
+    pub fn in_config(config: &Config, address: IpAddr) -> Self {
+        Self::in_routes(
+            address,
+            config.peers.iter().flat_map(|peer| {
+                peer.allowed_ips.nets().into_iter().map(move |net| Route {
+                    public_key: peer.public_key.clone(),
+                    net,
+                })
+            }),
+        )
+    }
+
+    pub fn on_device(device: &get::Device, address: IpAddr) -> Self {
+        Self::in_routes(
+            address,
+            device.peers.iter().flat_map(|peer| {
+                peer.allowed_ips.iter().map(move |allowed_ip| Route {
+                    public_key: PublicKey::from(peer.public_key),
+                    net: AllowedIp::from(allowed_ip).net(),
+                })
+            }),
+        )
+    }
+}
+
+impl fmt::Display for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.owner {
+            Some(owner) => write!(
+                f,
+                "{} belongs to {} through {}",
+                self.address, owner.public_key, owner.net
+            )?,
+            None => write!(f, "{} isn't routed to any peer", self.address)?,
+        }
+        for route in &self.shadowed {
+            write!(f, "\n  shadowing {} of {}", route.net, route.public_key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lookup;
+    use crate::config::Config;
+
+    const ALICE: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+    const BOB: &str = "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=";
+
+    const CONF: &str = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.8.0.1/16
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.0/8, 10.8.3.0/24
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.8.0.0/16, 10.8.3.17/24
+";
+
+    #[test]
+    fn the_most_specific_prefix_wins() -> Result<(), failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        let config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+
+        // Both peers have 10.8.3.0/24, and the one given it last gets it.
+        assert_eq!(
+            Lookup::in_config(&config, "10.8.3.17".parse()?).to_string(),
+            format!(
+                "10.8.3.17 belongs to {bob} through 10.8.3.0/24\n  \
+                 shadowing 10.8.3.0/24 of {alice}\n  \
+                 shadowing 10.8.0.0/16 of {bob}\n  \
+                 shadowing 10.0.0.0/8 of {alice}",
+                alice = ALICE,
+                bob = BOB
+            )
+        );
+        assert_eq!(
+            Lookup::in_config(&config, "10.9.0.1".parse()?).to_string(),
+            format!("10.9.0.1 belongs to {} through 10.0.0.0/8", ALICE)
+        );
+        assert_eq!(
+            Lookup::in_config(&config, "192.0.2.1".parse()?).to_string(),
+            "192.0.2.1 isn't routed to any peer"
+        );
+        Ok(())
+    }
+}
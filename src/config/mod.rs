@@ -1,8 +1,27 @@
+pub mod allowedips;
+pub mod client;
 pub mod conf_file;
 
+pub mod diagnostics;
+pub use diagnostics::Diagnostics;
+
+pub mod document;
+pub use document::ConfigDocument;
+
+pub mod drift;
+pub use drift::Drift;
+
+pub mod endpoint;
+pub use endpoint::Endpoint;
+
 pub mod interface;
+pub mod lint;
+pub mod lookup;
 pub use interface::Interface;
 
+pub mod keysource;
+pub use keysource::KeySource;
+
 pub mod peer;
 pub use peer::Peer;
 
@@ -19,14 +38,22 @@ pub struct Config {
     pub name: String,
     pub interface: Interface,
     pub peers: Vec<Peer>,
+    // The file this config was read from. Changes are written back into it so that comments and
+    // formatting outside of the changed fields are preserved.
+    conf_file: ConfFile,
 }
 
-use failure;
+use crate::permissions;
+use conf_file::{ConfFile, Section};
+use diagnostics::{Diagnostic, Severity};
 use std::fmt;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use wireguard_uapi::get::Device;
+use wireguard_uapi::set;
+use wireguard_uapi::set::WgPeerF;
 
 impl Config {
     pub fn new(name: String) -> Result<Self, failure::Error> {
@@ -34,93 +61,398 @@ impl Config {
             name,
             interface: Interface::new()?,
             peers: vec![],
+            conf_file: ConfFile::default(),
         })
     }
 
-    pub fn init_from_path(name: String, path: &Path) -> Result<Self, failure::Error> {
-        match fs::File::open(path) {
-            Ok(file) => Self::read_from_file(name, file),
+    pub fn init_from_path(
+        name: String,
+        path: &Path,
+    ) -> Result<(Self, Diagnostics), failure::Error> {
+        match fs::read_to_string(path) {
+            Ok(source) => Self::from_source(name, &source, Some(path)),
             Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-                Self::create_new_at_path(name, path)
+                let config = Self::create_new_at_path(name, path)?;
+                let source = config.conf_file.to_string();
+                Ok((
+                    config,
+                    Diagnostics::new(Some(path.to_path_buf()), source, vec![]),
+                ))
             }
             Err(e) => Err(e.into()),
         }
     }
 
     pub fn create_new_at_path(name: String, path: &Path) -> Result<Self, failure::Error> {
-        let mut file = fs::File::create(path)?;
-        let config = Self::new(name)?;
-        file.write_all(config.to_string().as_bytes())?;
+        let mut config = Self::new(name)?;
+        config.write_to_path(path)?;
         Ok(config)
     }
 
-    pub fn read_from_file(name: String, file: fs::File) -> Result<Self, failure::Error> {
-        let mut conf = conf_file::parse(file)?;
+    // Writes are done to a temporary file in the same directory, which is then renamed over the
+    // original path. A crash or error midway through leaves the previous config file intact rather
+    // than a truncated one.
+    pub fn write_to_path(&mut self, path: &Path) -> Result<(), failure::Error> {
+        self.write_to_path_with(path, |_| ())
+    }
+
+    // The same as write_to_path, with a look at the finished file just before it takes the place of
+    // the old one. Anything watching the config only hears about the new file after that.
+    pub fn write_to_path_with(
+        &mut self,
+        path: &Path,
+        before_rename: impl FnOnce(&Path),
+    ) -> Result<(), failure::Error> {
+        self.update_conf_file()?;
+        let tmp_path = temp_path_for(path);
 
-        let mut sections = conf.drain(..);
+        let mut file = permissions::create_private_file(&tmp_path)?;
+        file.write_all(self.conf_file.to_string().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
 
-        let mut interface_section = sections
-            .next()
-            .filter(|section| section.name == "Interface")
-            .ok_or(ParseInvalidFirstSection)?;
+        before_rename(&tmp_path);
+        fs::rename(&tmp_path, path)?;
 
-        let interface = Interface {
-            private_key: interface_section
-                .values
-                .remove("PrivateKey")
-                .ok_or(ParseMissingPrivateKeyError)?
-                .parse()?,
-            listen_port: interface_section
-                .values
-                .remove("ListenPort")
-                .map(|x| x.parse())
-                .transpose()?,
-            address: interface_section
-                .values
-                .remove("Address")
-                .map(|val| val.split(',').map(str::parse).collect())
-                .unwrap_or_else(|| Ok(vec![]))?,
-        };
+        // The rename itself isn't durable until the directory entry is flushed.
+        if let Some(parent) = path.parent().filter(|parent| parent != &Path::new("")) {
+            fs::File::open(parent)?.sync_all()?;
+        }
 
-        let peers = sections
-            .map(|section| {
-                Some(section)
-                    .filter(|section| section.name == "Peer")
-                    .ok_or_else(|| ParseInvalidPeerSectionsError.into())
-                    .and_then(|mut section| Peer::from_hashmap(&mut section.values))
+        Ok(())
+    }
+
+    // Catches this interface's own keys pasted where a peer's public key belongs. Any other private
+    // key is just 32 random bytes, so it can't be told apart from a public key.
+    pub fn check_peer_public_key(&self, public_key: &PublicKey) -> Result<(), failure::Error> {
+        if public_key.as_bytes() == self.interface.private_key.public_key().as_bytes() {
+            return Err(OwnPublicKeyError.into());
+        }
+
+        // Compared without stopping at the first difference, so the time taken doesn't give away
+        // how much of the private key was guessed.
+        let difference = public_key
+            .as_bytes()
+            .iter()
+            .zip(self.interface.private_key.as_bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        if difference == 0 {
+            return Err(OwnPrivateKeyError.into());
+        }
+
+        Ok(())
+    }
+
+    // Adds the peer, or updates the existing peer with the same public key. Fields left unset on
+    // an existing peer keep their previous value, matching how WireGuard treats an update to a
+    // peer that's already on the device.
+    pub fn upsert_peer(&mut self, peer: Peer) {
+        match self
+            .peers
+            .iter_mut()
+            .find(|existing| existing.public_key.as_bytes() == peer.public_key.as_bytes())
+        {
+            Some(existing) => {
+                if peer.preshared_key.is_some() {
+                    existing.preshared_key = peer.preshared_key;
+                    existing.preshared_key_source = peer.preshared_key_source;
+                }
+                if peer.endpoint.is_some() {
+                    existing.endpoint = peer.endpoint;
+                }
+                if peer.persistent_keepalive.is_some() {
+                    existing.persistent_keepalive = peer.persistent_keepalive;
+                }
+                existing.allowed_ips = peer.allowed_ips;
+            }
+            None => self.peers.push(peer),
+        }
+    }
+
+    pub fn remove_peer(&mut self, public_key: &PublicKey) -> Option<Peer> {
+        let index = self
+            .peers
+            .iter()
+            .position(|peer| peer.public_key.as_bytes() == public_key.as_bytes())?;
+        Some(self.peers.remove(index))
+    }
+
+    // Files that keys are read from. These need the same protection as the config file itself.
+    pub fn key_files(&self) -> Vec<&Path> {
+        let interface_source = self.interface.private_key_source.as_ref();
+        let peer_sources = self.peers.iter().filter_map(|peer| {
+            peer.preshared_key
+                .as_ref()
+                .and(peer.preshared_key_source.as_ref())
+        });
+
+        interface_source
+            .into_iter()
+            .chain(peer_sources)
+            .filter_map(|source| match source {
+                KeySource::File(path) => Some(path.as_path()),
+                _ => None,
             })
-            .collect::<Result<Vec<Peer>, _>>()?;
+            .collect()
+    }
+
+    // Builds a config from an interface that's already up, such as one brought up by wg-quick.
+    // Only the fields WireGuard itself knows about can be recovered.
+    pub fn from_device(name: String, device: &Device) -> Result<Self, failure::Error> {
+        let private_key = device
+            .private_key
+            .ok_or_else(|| DeviceMissingPrivateKeyError { name: name.clone() })?;
+
+        let interface = Interface {
+            listen_port: Some(device.listen_port).filter(|port| *port != 0),
+            fwmark: Some(device.fwmark).filter(|fwmark| *fwmark != 0),
+            ..Interface::with_private_key(private_key.into())
+        };
 
         Ok(Self {
             name,
             interface,
-            peers,
+            peers: device.peers.iter().map(Peer::from).collect(),
+            conf_file: ConfFile::default(),
         })
     }
-}
 
-impl fmt::Display for Config {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "[Interface]")?;
+    // Any errors are returned together so they can all be fixed at once. Warnings come back with
+    // the config, for the caller to show or not.
+    pub fn read_from_file(
+        name: String,
+        path: &Path,
+    ) -> Result<(Self, Diagnostics), failure::Error> {
+        Self::from_source(name, &fs::read_to_string(path)?, Some(path))
+    }
 
-        writeln!(f, "PrivateKey = {}", &self.interface.private_key)?;
+    fn from_source(
+        name: String,
+        source: &str,
+        path: Option<&Path>,
+    ) -> Result<(Self, Diagnostics), failure::Error> {
+        let (config, diagnostics) = Self::parse_with_diagnostics(name, source, path);
 
-        if let Some(listen_port) = &self.interface.listen_port {
-            writeln!(f, "ListenPort = {}", &listen_port)?;
+        match config {
+            Some(config) => Ok((config, diagnostics)),
+            None => Err(diagnostics.into()),
         }
+    }
+
+    // Parses a config without stopping at the first problem. The config is only returned if none
+    // of the problems found were errors.
+    pub fn parse_with_diagnostics(
+        name: String,
+        source: &str,
+        path: Option<&Path>,
+    ) -> (Option<Self>, Diagnostics) {
+        let (conf_file, mut items) = ConfFile::parse_with_diagnostics(source);
+        let sections = parse_sections(&conf_file, &mut items);
+        let diagnostics = Diagnostics::new(path.map(Path::to_path_buf), source.to_string(), items);
+
+        let config = match sections {
+            Some((interface, peers)) if !diagnostics.has_errors() => Some(Self {
+                name,
+                interface,
+                peers,
+                conf_file,
+            }),
+            _ => None,
+        };
 
-        if !self.interface.address.is_empty() {
-            let address = &self
-                .interface
-                .address
+        (config, diagnostics)
+    }
+
+    // Brings the backing conf file in line with this config. Only fields whose values changed are
+    // touched, peers are matched up by public key, and new peers are appended to the end. The
+    // config is compared with the file as it's written, so secrets kept elsewhere aren't read
+    // again to do it.
+    fn update_conf_file(&mut self) -> Result<(), failure::Error> {
+        let conf_file = &mut self.conf_file;
+        let peers = &self.peers;
+
+        // Only a config that was never written to a file has no sections.
+        if conf_file.sections().is_empty() {
+            conf_file.push_section("Interface", &[]);
+        }
+        let mut sections = conf_file.sections().into_iter();
+        let old_interface = sections
+            .next()
+            .filter(|section| section.is("Interface"))
+            .ok_or(UnreconcilableFileError)?;
+        let old_peers = sections
+            .map(|section| {
+                let public_key = section
+                    .values
+                    .iter()
+                    .find(|field| field.key.eq_ignore_ascii_case("PublicKey"))
+                    .and_then(|field| field.value.parse::<PublicKey>().ok())
+                    .filter(|_| section.is("Peer"))
+                    .ok_or(UnreconcilableFileError)?;
+                Ok((public_key, section))
+            })
+            .collect::<Result<Vec<(PublicKey, Section)>, UnreconcilableFileError>>()?;
+
+        update_section(
+            conf_file,
+            0,
+            &old_interface,
+            interface::KEYS,
+            self.interface.fields(),
+        );
+
+        let is_current = |public_key: &PublicKey| {
+            peers
                 .iter()
-                .map(std::string::ToString::to_string)
-                .collect::<Vec<String>>()
-                .join(",");
-            writeln!(f, "Address = {}", &address)?;
+                .any(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
         };
 
-        // TODO: Serialize the remaining fields.
+        // Peer sections directly follow the [Interface] section, so the nth old peer is section
+        // n + 1. Removing from the back keeps the earlier indexes valid.
+        for (i, (public_key, _)) in old_peers.iter().enumerate().rev() {
+            if !is_current(public_key) {
+                conf_file.remove_section(i + 1);
+            }
+        }
+
+        let kept_peers = old_peers
+            .iter()
+            .filter(|(public_key, _)| is_current(public_key));
+        for (i, (public_key, old_section)) in kept_peers.enumerate() {
+            if let Some(peer) = peers
+                .iter()
+                .find(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+            {
+                update_section(conf_file, i + 1, old_section, peer::KEYS, peer.fields());
+            }
+        }
+
+        for peer in peers {
+            let is_new = !old_peers
+                .iter()
+                .any(|(public_key, _)| public_key.as_bytes() == peer.public_key.as_bytes());
+            if is_new {
+                conf_file.push_section("Peer", &peer.fields());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Returns None if any errors were found, after checking every section so that they're all
+// reported together.
+fn parse_sections(
+    conf_file: &ConfFile,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<(Interface, Vec<Peer>)> {
+    let mut sections = conf_file.sections().into_iter();
+
+    let interface = match sections.next() {
+        Some(ref mut section) if section.is("Interface") => {
+            Interface::from_section(section, diagnostics)
+        }
+        Some(section) => {
+            diagnostics.push(section.diagnostic(
+                Severity::Error,
+                "configuration files must start with an [Interface] section".to_string(),
+            ));
+            None
+        }
+        None => {
+            diagnostics.push(Diagnostic::error(
+                "configuration files must start with an [Interface] section".to_string(),
+                1,
+                1,
+                0,
+            ));
+            None
+        }
+    };
+
+    let mut peers = vec![];
+    let mut has_invalid_peers = false;
+
+    for mut section in sections {
+        if !section.is("Peer") {
+            diagnostics.push(section.diagnostic(
+                Severity::Error,
+                "only [Peer] sections are allowed after the [Interface] section".to_string(),
+            ));
+            has_invalid_peers = true;
+            continue;
+        }
+
+        match Peer::from_section(&mut section, diagnostics) {
+            Some(peer) => peers.push(peer),
+            None => has_invalid_peers = true,
+        }
+    }
+
+    if has_invalid_peers {
+        return None;
+    }
+    Some((interface?, peers))
+}
+
+// Compares each known key's values with the ones written in the section, ignoring spacing around
+// list entries, so that a value which only differs in formatting (such as spacing after a comma)
+// isn't rewritten. Values for a key that changed replace the existing lines. Keys that aren't known
+// are left alone, since they're only warned about when reading the config.
+fn update_section(
+    conf_file: &mut ConfFile,
+    section: usize,
+    old_section: &Section,
+    keys: &[&'static str],
+    new_fields: Vec<(&'static str, String)>,
+) {
+    let entries = |values: &[&str]| -> Vec<String> {
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let is_list = |key: &str| ["Address", "DNS", "AllowedIPs"].contains(&key);
+
+    for key in keys {
+        let old_values: Vec<&str> = old_section
+            .values
+            .iter()
+            .filter(|field| field.key.eq_ignore_ascii_case(key))
+            .map(|field| field.value.trim())
+            .collect();
+        let new_values: Vec<String> = new_fields
+            .iter()
+            .filter(|(new_key, _)| new_key == key)
+            .map(|(_, value)| value.clone())
+            .collect();
+        let new_refs: Vec<&str> = new_values.iter().map(String::as_str).collect();
+
+        let is_unchanged = if is_list(key) {
+            entries(&old_values) == entries(&new_refs)
+        } else {
+            old_values == new_refs
+        };
+        if !is_unchanged {
+            conf_file.set_values(section, key, &new_values);
+        }
+    }
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", &self.interface)?;
 
         for peer in &self.peers {
             writeln!(f)?;
@@ -131,6 +463,83 @@ impl fmt::Display for Config {
     }
 }
 
+// Written to a peer to clear a preshared key that was removed from the config.
+static NO_PRESHARED_KEY: [u8; 32] = [0u8; 32];
+
+impl Config {
+    // The changes needed to bring a device configured from old in line with this config, in the
+    // same way as `wg syncconf`. Peers that didn't change are left out so their sessions and
+    // counters are untouched. Only WireGuard's own fields are applied. The wg-quick fields, such as
+    // Address, need the interface to be brought up again.
+    pub fn changes_from<'a>(&'a self, old: &'a Config) -> Option<set::Device<'a>> {
+        let mut device = set::Device::from_ifname(&self.name);
+
+        if self.interface.private_key.as_bytes() != old.interface.private_key.as_bytes() {
+            device = device.private_key(self.interface.private_key.as_bytes());
+        }
+
+        // A port of 0 lets the kernel pick one, which is what happens without a ListenPort.
+        if self.interface.listen_port != old.interface.listen_port {
+            device = device.listen_port(self.interface.listen_port.unwrap_or(0));
+        }
+
+        if self.interface.fwmark != old.interface.fwmark {
+            device = device.fwmark(self.interface.fwmark.unwrap_or(0));
+        }
+
+        let find_peer = |peers: &'a [Peer], public_key: &PublicKey| {
+            peers
+                .iter()
+                .find(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+        };
+
+        let mut peers: Vec<set::Peer> = old
+            .peers
+            .iter()
+            .filter(|old_peer| find_peer(&self.peers, &old_peer.public_key).is_none())
+            .map(|old_peer| {
+                set::Peer::from_public_key(old_peer.public_key.as_bytes())
+                    .flags(vec![WgPeerF::RemoveMe])
+            })
+            .collect();
+
+        for peer in &self.peers {
+            let old_peer = find_peer(&old.peers, &peer.public_key);
+            // A preshared key read from a file can change while the field pointing at it doesn't.
+            let is_unchanged = old_peer.map_or(false, |old_peer| {
+                old_peer.fields() == peer.fields()
+                    && old_peer.preshared_key.as_ref().map(PresharedKey::as_bytes)
+                        == peer.preshared_key.as_ref().map(PresharedKey::as_bytes)
+            });
+            if is_unchanged {
+                continue;
+            }
+
+            let mut set_peer = set::Peer::from(peer).flags(vec![WgPeerF::ReplaceAllowedIps]);
+
+            // Fields missing from a peer are left as they were by WireGuard, so removing them
+            // from the config means explicitly clearing them.
+            if let Some(old_peer) = old_peer {
+                if peer.preshared_key.is_none() && old_peer.preshared_key.is_some() {
+                    set_peer = set_peer.preshared_key(&NO_PRESHARED_KEY);
+                }
+                if peer.persistent_keepalive.is_none() && old_peer.persistent_keepalive.is_some() {
+                    set_peer = set_peer.persistent_keepalive_interval(0);
+                }
+            }
+
+            peers.push(set_peer);
+        }
+
+        let interface_changed =
+            device.private_key.is_some() || device.listen_port.is_some() || device.fwmark.is_some();
+        if !interface_changed && peers.is_empty() {
+            return None;
+        }
+        Some(device.peers(peers))
+    }
+}
+
 impl<'a> From<&'a Config> for wireguard_uapi::set::Device<'a> {
     fn from(config: &'a Config) -> Self {
         let mut device =
@@ -140,6 +549,10 @@ impl<'a> From<&'a Config> for wireguard_uapi::set::Device<'a> {
             device = device.listen_port(listen_port);
         }
 
+        if let Some(fwmark) = config.interface.fwmark {
+            device = device.fwmark(fwmark);
+        }
+
         let peers = config.peers.iter().map(|peer| peer.into()).collect();
         device = device.peers(peers);
 
@@ -148,15 +561,349 @@ impl<'a> From<&'a Config> for wireguard_uapi::set::Device<'a> {
 }
 
 #[derive(Debug, failure::Fail)]
-#[fail(display = "Configuration files must start with an [Interface] section.")]
-pub struct ParseInvalidFirstSection;
+#[fail(display = "{} doesn't have a private key to import", name)]
+pub struct DeviceMissingPrivateKeyError {
+    name: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "this is the interface's own public key. Enter the peer's public key instead")]
+pub struct OwnPublicKeyError;
 
 #[derive(Debug, failure::Fail)]
 #[fail(
-    display = "Invalid configuration file. Only [Peer] sections are allowed after the first [Interface] section."
+    display = "this is the interface's private key, which should never leave the server. Enter \
+               the peer's public key instead, and consider generating a new private key"
 )]
-pub struct ParseInvalidPeerSectionsError;
+pub struct OwnPrivateKeyError;
 
 #[derive(Debug, failure::Fail)]
-#[fail(display = "The [Interface] section is missing a private key.")]
-pub struct ParseMissingPrivateKeyError;
+#[fail(display = "the config file's sections no longer match the config, so it can't be updated")]
+pub struct UnreconcilableFileError;
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfFile, Config, Peer, PrivateKey, PublicKey};
+    use std::env;
+    use std::time::Duration;
+    use wireguard_uapi::get;
+
+    const CONF: &str = "\
+# wg0, managed by wg-web-server
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+Address = 10.0.0.1/24, fd00::1/64 # v4 and v6
+
+# Alice's laptop
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32
+
+# Bob's phone
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.0.0.3/32
+";
+
+    fn config() -> Result<Config, failure::Error> {
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), CONF, None);
+        config.ok_or_else(|| diagnostics.into())
+    }
+
+    fn peer(public_key: &str, allowed_ips: &str) -> Result<Peer, failure::Error> {
+        Ok(Peer {
+            public_key: public_key.parse()?,
+            preshared_key: None,
+            preshared_key_source: None,
+            allowed_ips: allowed_ips.parse()?,
+            endpoint: None,
+            persistent_keepalive: None,
+        })
+    }
+
+    #[test]
+    fn unchanged_config_is_written_as_is() -> Result<(), failure::Error> {
+        let mut config = config()?;
+        config.update_conf_file()?;
+        assert_eq!(config.conf_file.to_string(), CONF);
+        Ok(())
+    }
+
+    #[test]
+    fn warnings_come_back_with_the_config() -> Result<(), failure::Error> {
+        let source = CONF.replace(
+            "ListenPort = 51820",
+            "ListenPort = 51820\nListenPrt = 51821",
+        );
+        let (config, diagnostics) = Config::from_source("wg0".to_string(), &source, None)?;
+        assert_eq!(config.interface.listen_port, Some(51820));
+        assert!(!diagnostics.has_errors());
+        assert!(diagnostics
+            .to_string()
+            .contains("unknown field ListenPrt will be ignored"));
+        Ok(())
+    }
+
+    #[test]
+    fn changed_peer_only_touches_its_lines() -> Result<(), failure::Error> {
+        let mut config = config()?;
+        config.upsert_peer(peer(
+            "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            "10.0.0.4/32",
+        )?);
+        config.update_conf_file()?;
+
+        assert_eq!(
+            config.conf_file.to_string(),
+            CONF.replace("AllowedIPs = 10.0.0.2/32", "AllowedIPs = 10.0.0.4/32")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn added_and_removed_peers() -> Result<(), failure::Error> {
+        let mut config = config()?;
+        let alice = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".parse()?;
+        assert!(config.remove_peer(&alice).is_some());
+        assert!(config.remove_peer(&alice).is_none());
+        config.upsert_peer(peer(
+            "SwgTyJpz0og0NH/1YagZ2pWuaR06b0nlVUUo0WFdbAY=",
+            "10.0.0.5/32",
+        )?);
+        config.update_conf_file()?;
+
+        let expected = CONF.replace(
+            "# Alice's laptop\n[Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\nAllowedIPs = 10.0.0.2/32\n\n",
+            "",
+        ) + "\n[Peer]\nPublicKey = SwgTyJpz0og0NH/1YagZ2pWuaR06b0nlVUUo0WFdbAY=\nAllowedIPs = 10.0.0.5/32\n";
+        assert_eq!(config.conf_file.to_string(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn secrets_are_not_read_again_to_write_the_file() -> Result<(), failure::Error> {
+        env::set_var(
+            "WG_WEB_SERVER_TEST_UNSET_PSK",
+            "FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=",
+        );
+        let source = format!(
+            "{}PresharedKeyFile = env:WG_WEB_SERVER_TEST_UNSET_PSK\n",
+            CONF
+        );
+        let (config, diagnostics) =
+            Config::parse_with_diagnostics("wg0".to_string(), &source, None);
+        let mut config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+        env::remove_var("WG_WEB_SERVER_TEST_UNSET_PSK");
+
+        config.upsert_peer(peer(
+            "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            "10.0.0.4/32",
+        )?);
+        config.update_conf_file()?;
+
+        assert_eq!(
+            config.conf_file.to_string(),
+            source.replace("AllowedIPs = 10.0.0.2/32", "AllowedIPs = 10.0.0.4/32")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unreconcilable_files_are_left_alone() -> Result<(), failure::Error> {
+        let mut config = config()?;
+        let (conf_file, _) = ConfFile::parse_with_diagnostics("# no sections\n[Peer]\n");
+        config.conf_file = conf_file;
+
+        assert!(config.update_conf_file().is_err());
+        assert_eq!(config.conf_file.to_string(), "# no sections\n[Peer]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn every_error_is_reported_with_its_position() {
+        let source = "\
+[Interface]
+ListenPort = eighty
+Address = 10.0.0.1/24, 10.0.0.300/24
+
+[Peer]
+AllowedIPs = 10.0.0.2/32
+";
+        let (config, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), source, None);
+        assert!(config.is_none());
+
+        let positions: Vec<(usize, usize)> = diagnostics
+            .items
+            .iter()
+            .map(|item| (item.line_num, item.column))
+            .collect();
+        // The missing PrivateKey and PublicKey point at their section headers.
+        assert_eq!(positions, vec![(1, 1), (2, 14), (3, 24), (5, 1)]);
+
+        let rendered = diagnostics.to_string();
+        assert!(rendered.contains(" --> <config>:3:24\n"));
+        assert!(rendered.contains("3 | Address = 10.0.0.1/24, 10.0.0.300/24\n"));
+        assert!(rendered.contains("  |                        ^^^^^^^^^^^^^\n"));
+        assert!(rendered.contains("= note: in section 2 ([Peer])"));
+        assert!(rendered.ends_with("4 errors and 0 warnings found"));
+    }
+
+    #[test]
+    fn secrets_are_never_shown_in_diagnostics() {
+        let source = "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3f # rotated
+ListenPort = eighty
+";
+        let (_, diagnostics) = Config::parse_with_diagnostics("wg0".to_string(), source, None);
+
+        let rendered = diagnostics.to_string();
+        assert!(rendered.contains("invalid PrivateKey value: private keys must"));
+        assert!(rendered
+            .contains("2 | PrivateKey = **************************************** # rotated\n"));
+        assert!(!rendered.contains("yAnz5TF"));
+        assert!(!format!("{:?}", diagnostics).contains("yAnz5TF"));
+    }
+
+    #[test]
+    fn keys_are_case_insensitive_and_unknown_keys_warn() -> Result<(), failure::Error> {
+        let source = CONF.replace("ListenPort", "listenport").replace(
+            "AllowedIPs = 10.0.0.3/32",
+            "allowedips = 10.0.0.3/32\nColour = blue",
+        );
+        let (config, diagnostics) =
+            Config::parse_with_diagnostics("wg0".to_string(), &source, None);
+
+        let config = config.ok_or_else(|| failure::format_err!("{}", diagnostics))?;
+        assert_eq!(config.interface.listen_port, Some(51820));
+        assert_eq!(config.peers[1].allowed_ips.to_string(), "10.0.0.3/32");
+
+        assert!(!diagnostics.has_errors());
+        assert_eq!(diagnostics.items.len(), 1);
+        assert_eq!(
+            diagnostics.items[0].message,
+            "unknown field Colour will be ignored"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn imports_a_live_device() -> Result<(), failure::Error> {
+        let peer = |public_key: &str, allowed_ip: &str, cidr_mask| -> Result<_, failure::Error> {
+            Ok(get::Peer {
+                public_key: *public_key.parse::<PublicKey>()?.as_bytes(),
+                preshared_key: [0u8; 32],
+                endpoint: None,
+                persistent_keepalive_interval: 0,
+                last_handshake_time: Duration::from_secs(0),
+                rx_bytes: 0,
+                tx_bytes: 0,
+                allowed_ips: vec![get::AllowedIp {
+                    family: libc::AF_INET as u16,
+                    ipaddr: allowed_ip.parse()?,
+                    cidr_mask,
+                }],
+                protocol_version: 1,
+            })
+        };
+
+        let mut alice = peer(
+            "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            "10.0.0.2",
+            32,
+        )?;
+        alice.endpoint = Some("192.0.2.1:51820".parse()?);
+        alice.persistent_keepalive_interval = 25;
+        let bob = peer(
+            "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=",
+            "10.0.1.0",
+            24,
+        )?;
+
+        let private_key: PrivateKey = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=".parse()?;
+        let device = get::Device {
+            ifindex: 4,
+            ifname: "wg0".to_string(),
+            private_key: Some(*private_key.as_bytes()),
+            public_key: None,
+            listen_port: 51820,
+            fwmark: 0,
+            peers: vec![alice, bob],
+        };
+
+        let config = Config::from_device("wg0".to_string(), &device)?;
+        assert_eq!(
+            config.to_string(),
+            "\
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+ListenPort = 51820
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.0.0.2/32
+Endpoint = 192.0.2.1:51820
+PersistentKeepalive = 25
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.0.1.0/24
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn changes_only_include_what_changed() -> Result<(), failure::Error> {
+        let old = config()?;
+        assert!(config()?.changes_from(&old).is_none());
+
+        let mut new = config()?;
+        new.peers.remove(1);
+        new.upsert_peer(peer(
+            "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            "10.0.0.4/32",
+        )?);
+
+        let device = new.changes_from(&old).expect("peers were changed");
+        assert!(device.private_key.is_none());
+        assert!(device.listen_port.is_none());
+
+        let public_keys: Vec<String> = device
+            .peers
+            .iter()
+            .map(|peer| base64::encode(peer.public_key))
+            .collect();
+        assert_eq!(
+            public_keys,
+            vec![
+                "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=",
+                "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn own_keys_are_not_accepted_as_peer_keys() -> Result<(), failure::Error> {
+        let config = config()?;
+        let own_public_key = config.interface.private_key.public_key();
+        let own_private_key = PublicKey::from(*config.interface.private_key.as_bytes());
+
+        assert!(config
+            .check_peer_public_key(&own_public_key)
+            .unwrap_err()
+            .to_string()
+            .starts_with("this is the interface's own public key"));
+        assert!(config
+            .check_peer_public_key(&own_private_key)
+            .unwrap_err()
+            .to_string()
+            .starts_with("this is the interface's private key"));
+        assert!(config
+            .check_peer_public_key(&"xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".parse()?)
+            .is_ok());
+        Ok(())
+    }
+}
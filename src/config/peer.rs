@@ -1,110 +1,83 @@
-use super::{PresharedKey, PublicKey};
-use crate::impl_with_fromstr_with_error;
-use core::str::FromStr;
-use failure;
-use libc;
-use std::collections::HashMap;
+pub use super::allowedips::{AllowedIp, AllowedIps};
+use super::conf_file::Section;
+use super::diagnostics::Diagnostic;
+use super::{Endpoint, KeySource, PresharedKey, PublicKey};
 use std::fmt;
-use std::net::IpAddr;
-use std::net::SocketAddr;
 
-pub struct AllowedIp {
-    pub addr: IpAddr,
-    pub cidr: Option<u8>,
-}
+// Every key a [Peer] section may have, as written by Peer::fields.
+pub const KEYS: &[&str] = &[
+    "PublicKey",
+    "PresharedKey",
+    "PresharedKeyFile",
+    "AllowedIPs",
+    "Endpoint",
+    "PersistentKeepalive",
+];
 
-impl<'a> From<&'a AllowedIp> for wireguard_uapi::set::AllowedIp<'a> {
-    fn from(allowed_ip: &'a AllowedIp) -> Self {
-        Self {
-            ipaddr: &allowed_ip.addr,
-            cidr_mask: allowed_ip.cidr,
-        }
-    }
+pub struct Peer {
+    pub public_key: PublicKey,
+    pub preshared_key: Option<PresharedKey>,
+    // Where the preshared key was read from, if it isn't in the config file itself.
+    pub preshared_key_source: Option<KeySource>,
+    pub allowed_ips: AllowedIps,
+    pub endpoint: Option<Endpoint>,
+    pub persistent_keepalive: Option<u16>,
 }
 
-impl<'a> From<&AllowedIp> for wireguard_uapi::get::AllowedIp {
-    fn from(allowed_ip: &AllowedIp) -> Self {
-        match allowed_ip.addr {
-            IpAddr::V4(_) => Self {
-                family: libc::AF_INET as u16,
-                ipaddr: allowed_ip.addr,
-                cidr_mask: allowed_ip.cidr.unwrap_or(32),
-            },
-            IpAddr::V6(_) => Self {
-                family: libc::AF_INET6 as u16,
-                ipaddr: allowed_ip.addr,
-                cidr_mask: allowed_ip.cidr.unwrap_or(128),
-            },
+impl Peer {
+    pub fn from_section(section: &mut Section, diagnostics: &mut Vec<Diagnostic>) -> Option<Self> {
+        let public_key = section.require("PublicKey", diagnostics);
+        let preshared_key = section.parse_secret("PresharedKey", "PresharedKeyFile", diagnostics);
+        let allowed_ips = section.parse_list("AllowedIPs", diagnostics);
+        let endpoint = section.parse("Endpoint", diagnostics);
+        let persistent_keepalive = section.parse("PersistentKeepalive", diagnostics);
+
+        section.warn_unknown(diagnostics);
+        if section.has_errors() {
+            return None;
         }
-    }
-}
-
-// Wrapper type needed until https://github.com/SergioBenitez/Rocket/issues/205
-pub struct AllowedIps(pub Vec<AllowedIp>);
-
-impl AllowedIps {
-    pub fn new() -> Self {
-        Self(vec![])
-    }
-}
 
-impl FromStr for AllowedIp {
-    type Err = failure::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let tokens: Vec<&str> = s.trim().split('/').collect();
-        Ok(Self {
-            addr: tokens[0].parse()?,
-            cidr: tokens.get(1).map(|x| x.parse()).transpose()?,
+        let (preshared_key, preshared_key_source) = match preshared_key {
+            Some((preshared_key, source)) => (Some(preshared_key), source),
+            None => (None, None),
+        };
+        Some(Self {
+            public_key: public_key?,
+            preshared_key,
+            preshared_key_source,
+            allowed_ips: AllowedIps(allowed_ips),
+            endpoint,
+            persistent_keepalive,
         })
     }
 }
 
-impl FromStr for AllowedIps {
-    type Err = failure::Error;
+impl Peer {
+    // The fields as they're written to a config file, in the order they're written.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("PublicKey", self.public_key.to_string())];
+
+        match (&self.preshared_key, &self.preshared_key_source) {
+            (Some(_), Some(source)) => fields.push(("PresharedKeyFile", source.to_string())),
+            (Some(preshared_key), None) => {
+                fields.push(("PresharedKey", preshared_key.expose_secret()))
+            }
+            (None, _) => {}
+        }
 
-    fn from_str(str: &str) -> Result<Self, Self::Err> {
-        Ok(Self(
-            str.split(',')
-                .map(str::trim)
-                .map(AllowedIp::from_str)
-                .collect::<Result<Vec<_>, _>>()?,
-        ))
-    }
-}
+        if !self.allowed_ips.0.is_empty() {
+            fields.push(("AllowedIPs", self.allowed_ips.to_string()));
+        }
 
-impl_with_fromstr_with_error!(AllowedIp, AllowedIps);
+        if let Some(endpoint) = &self.endpoint {
+            fields.push(("Endpoint", endpoint.to_string()));
+        }
 
-pub struct Peer {
-    pub public_key: PublicKey,
-    pub preshared_key: Option<PresharedKey>,
-    pub allowed_ips: AllowedIps,
-    // TODO: Allow endpoint to also be a hostname
-    pub endpoint: Option<SocketAddr>,
-    pub persistent_keepalive: Option<u16>,
-}
+        if let Some(persistent_keepalive) = &self.persistent_keepalive {
+            fields.push(("PersistentKeepalive", persistent_keepalive.to_string()));
+        }
 
-impl Peer {
-    pub fn from_hashmap(values: &mut HashMap<String, String>) -> Result<Self, failure::Error> {
-        Ok(Self {
-            public_key: values
-                .remove("PublicKey")
-                .ok_or(ParseMissingPublicKeyError)?
-                .parse()?,
-            preshared_key: values
-                .remove("PresharedKey")
-                .map(|x| x.parse())
-                .transpose()?,
-            allowed_ips: values
-                .remove("AllowedIPs")
-                .unwrap_or_else(|| "".to_string())
-                .parse()?,
-            endpoint: None,
-            persistent_keepalive: values
-                .remove("PersistentKeepalive")
-                .map(|x| x.parse())
-                .transpose()?,
-        })
+        fields
     }
 }
 
@@ -112,16 +85,37 @@ impl fmt::Display for Peer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "[Peer]")?;
 
-        writeln!(f, "PublicKey = {}", &self.public_key)?;
-
-        if let Some(preshared_key) = &self.preshared_key {
-            writeln!(f, "PresharedKey = {}", &preshared_key)?;
+        for (key, value) in self.fields() {
+            writeln!(f, "{} = {}", key, value)?;
         }
 
         Ok(())
     }
 }
 
+// WireGuard reports unset optional fields as zero, so those are mapped back to None.
+impl From<&wireguard_uapi::get::Peer> for Peer {
+    fn from(device_peer: &wireguard_uapi::get::Peer) -> Self {
+        Self {
+            public_key: device_peer.public_key.into(),
+            preshared_key: Some(device_peer.preshared_key)
+                .filter(|preshared_key| preshared_key != &[0u8; 32])
+                .map(PresharedKey::from),
+            preshared_key_source: None,
+            allowed_ips: AllowedIps(
+                device_peer
+                    .allowed_ips
+                    .iter()
+                    .map(AllowedIp::from)
+                    .collect(),
+            ),
+            endpoint: device_peer.endpoint.map(Endpoint::from),
+            persistent_keepalive: Some(device_peer.persistent_keepalive_interval)
+                .filter(|interval| *interval != 0),
+        }
+    }
+}
+
 impl<'a> From<&'a Peer> for wireguard_uapi::set::Peer<'a> {
     fn from(config_peer: &'a Peer) -> Self {
         let mut peer = Self::from_public_key(config_peer.public_key.as_bytes());
@@ -130,7 +124,8 @@ impl<'a> From<&'a Peer> for wireguard_uapi::set::Peer<'a> {
             peer = peer.preshared_key(preshared_key.as_bytes());
         }
 
-        if let Some(endpoint) = &config_peer.endpoint {
+        // Hostnames that couldn't be resolved are left off until they can be.
+        if let Some(endpoint) = config_peer.endpoint.as_ref().and_then(Endpoint::resolved) {
             peer = peer.endpoint(endpoint)
         }
 
@@ -138,19 +133,115 @@ impl<'a> From<&'a Peer> for wireguard_uapi::set::Peer<'a> {
             peer = peer.persistent_keepalive_interval(persistent_keepalive)
         }
 
-        let allowed_ips = (&config_peer.allowed_ips.0)
-            .iter()
-            .map(|ip| wireguard_uapi::set::AllowedIp {
-                ipaddr: &ip.addr,
-                cidr_mask: ip.cidr,
-            })
-            .collect();
+        let allowed_ips = config_peer.allowed_ips.0.iter().map(Into::into).collect();
         peer = peer.allowed_ips(allowed_ips);
 
         peer
     }
 }
 
-#[derive(Debug, failure::Fail)]
-#[fail(display = "[Peer] section is missing required PublicKey field.")]
-pub struct ParseMissingPublicKeyError;
+impl Peer {
+    // Only the fields that differ from old, so an edit doesn't rewrite the rest of the peer on the
+    // device. WireGuard has no way to clear an endpoint, so one that was removed is left as it is
+    // until the peer roams.
+    pub fn changes_from<'a>(&'a self, old: &Peer) -> Option<wireguard_uapi::set::Peer<'a>> {
+        let mut peer = wireguard_uapi::set::Peer::from_public_key(self.public_key.as_bytes());
+        let mut changed = false;
+
+        let preshared_key = self.preshared_key.as_ref().map(PresharedKey::as_bytes);
+        if preshared_key != old.preshared_key.as_ref().map(PresharedKey::as_bytes) {
+            peer = peer.preshared_key(preshared_key.unwrap_or(&super::NO_PRESHARED_KEY));
+            changed = true;
+        }
+
+        if self.allowed_ips.nets() != old.allowed_ips.nets() {
+            let allowed_ips = self.allowed_ips.0.iter().map(Into::into).collect();
+            peer = peer
+                .flags(vec![wireguard_uapi::set::WgPeerF::ReplaceAllowedIps])
+                .allowed_ips(allowed_ips);
+            changed = true;
+        }
+
+        let endpoint = self.endpoint.as_ref().and_then(Endpoint::resolved);
+        if let Some(endpoint) = endpoint {
+            if Some(endpoint) != old.endpoint.as_ref().and_then(Endpoint::resolved) {
+                peer = peer.endpoint(endpoint);
+                changed = true;
+            }
+        }
+
+        if self.persistent_keepalive != old.persistent_keepalive {
+            peer = peer.persistent_keepalive_interval(self.persistent_keepalive.unwrap_or(0));
+            changed = true;
+        }
+
+        if changed {
+            Some(peer)
+        } else {
+            None
+        }
+    }
+}
+
+// What's entered when editing a peer. The preshared key is never shown, so the current one is kept
+// unless it's replaced or cleared. The AllowedIPs and the endpoint are kept when they're None.
+pub struct PeerEdit {
+    pub preshared_key: PresharedKeyEdit,
+    pub allowed_ips: Option<AllowedIps>,
+    pub endpoint: Option<Endpoint>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+pub enum PresharedKeyEdit {
+    Keep,
+    Replace(PresharedKey),
+    Clear,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Peer, PresharedKey};
+    use wireguard_uapi::set::WgPeerF;
+
+    fn peer(public_key: &str, allowed_ips: &str) -> Result<Peer, failure::Error> {
+        Ok(Peer {
+            public_key: public_key.parse()?,
+            preshared_key: None,
+            preshared_key_source: None,
+            allowed_ips: allowed_ips.parse()?,
+            endpoint: None,
+            persistent_keepalive: None,
+        })
+    }
+
+    #[test]
+    fn peer_changes_only_set_what_changed() -> Result<(), failure::Error> {
+        let public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+        let mut old = peer(public_key, "10.0.0.2/32")?;
+        old.preshared_key = Some(PresharedKey::from([1u8; 32]));
+        old.persistent_keepalive = Some(25);
+
+        let mut new = peer(public_key, "10.0.0.2/32")?;
+        new.preshared_key = Some(PresharedKey::from([1u8; 32]));
+        new.persistent_keepalive = Some(25);
+        assert!(new.changes_from(&old).is_none());
+
+        // Clearing the preshared key and keepalive means setting them to zero.
+        new.preshared_key = None;
+        new.persistent_keepalive = None;
+        let set_peer = new.changes_from(&old).expect("peer was changed");
+        assert_eq!(set_peer.preshared_key, Some(&[0u8; 32]));
+        assert_eq!(set_peer.persistent_keepalive_interval, Some(0));
+        assert!(set_peer.flags.is_empty());
+        assert!(set_peer.allowed_ips.is_empty());
+
+        let new = peer(public_key, "10.0.0.2/32, 10.0.1.0/24")?;
+        let set_peer = new.changes_from(&old).expect("peer was changed");
+        assert!(matches!(
+            set_peer.flags.as_slice(),
+            [WgPeerF::ReplaceAllowedIps]
+        ));
+        assert_eq!(set_peer.allowed_ips.len(), 2);
+        Ok(())
+    }
+}
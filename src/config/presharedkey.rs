@@ -1,41 +1,114 @@
 use crate::impl_with_fromstr_with_error;
-use base64;
-use failure;
+use rand_os::rand_core::RngCore;
+use rand_os::OsRng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
+use zeroize::Zeroize;
 
+// Handled the same way as PrivateKey. It's wiped on drop and only formatted through
+// expose_secret.
 pub struct PresharedKey([u8; 32]);
 
 impl PresharedKey {
+    // Random bytes straight from the OS, like `wg genpsk`.
+    pub fn new() -> Result<Self, failure::Error> {
+        let mut os_rng = OsRng::new()?;
+        let mut bytes = [0u8; 32];
+        os_rng.try_fill_bytes(&mut bytes)?;
+        Ok(Self(bytes))
+    }
+
     #[inline]
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
+
+    // The base64 encoded key, as written in a config file.
+    pub fn expose_secret(&self) -> String {
+        base64::encode(&self.0)
+    }
+}
+
+impl Drop for PresharedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
 }
 
 impl FromStr for PresharedKey {
     type Err = failure::Error;
 
+    // Errors never include the input, since it's most likely a slightly mangled key.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut decoded = [0u8; 32];
-        let written = base64::decode_config_slice(&s, base64::STANDARD, &mut decoded)?;
-
-        if written != 32 {
+        // A 32 byte key is 44 characters of base64. Longer input would overrun the buffer it's
+        // decoded into.
+        if s.len() != 44 {
             return Err(InvalidLengthError.into());
         }
-
-        Ok(Self(decoded))
+        let mut decoded = [0u8; 32];
+        let key = match base64::decode_config_slice(&s, base64::STANDARD, &mut decoded) {
+            Ok(32) => Ok(Self(decoded)),
+            Ok(_) => Err(InvalidLengthError.into()),
+            Err(_) => Err(InvalidEncodingError.into()),
+        };
+        decoded.zeroize();
+        key
     }
 }
 
 impl_with_fromstr_with_error!(PresharedKey);
 
-impl fmt::Display for PresharedKey {
+impl fmt::Debug for PresharedKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", base64::encode(&self.0))
+        write!(f, "PresharedKey(redacted)")
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        PresharedKey::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
+#[derive(Debug, failure::Fail)]
+#[fail(display = "preshared keys must be base64 encoded")]
+struct InvalidEncodingError;
+
 #[derive(Debug, failure::Fail)]
 #[fail(display = "preshared keys must be exactly 32 bytes long")]
 struct InvalidLengthError;
+
+#[cfg(test)]
+mod tests {
+    use super::PresharedKey;
+
+    #[test]
+    fn over_long_keys_are_rejected() {
+        let over_long = "CJizCOvSz4+S+PqG9XenDsBxRivLFPK3Hec9tQ3wEEU".repeat(2) + "=";
+        let error = over_long
+            .parse::<PresharedKey>()
+            .err()
+            .map(|err| err.to_string());
+        assert_eq!(
+            error,
+            Some("preshared keys must be exactly 32 bytes long".to_string())
+        );
+        assert!("CJizCOvSz4+S+PqG9XenDsBxRivLFPK3Hec9tQ3wEEU="
+            .parse::<PresharedKey>()
+            .is_ok());
+    }
+}
@@ -1,10 +1,13 @@
-use base64;
-use failure;
+use super::PublicKey;
 use rand_os::OsRng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
 use x25519_dalek::StaticSecret;
+use zeroize::Zeroize;
 
+// The key is wiped from memory when dropped. It has no Display implementation so that it can't end
+// up in a log or error message by accident, and has to be asked for with expose_secret instead.
 pub struct PrivateKey([u8; 32]);
 
 impl PrivateKey {
@@ -19,31 +22,104 @@ impl PrivateKey {
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
+
+    // The base64 encoded key, as written in a config file.
+    pub fn expose_secret(&self) -> String {
+        base64::encode(self.as_bytes())
+    }
+
+    // The key peers use to refer to this one, the same as `wg pubkey` gives.
+    pub fn public_key(&self) -> PublicKey {
+        let static_secret = StaticSecret::from(self.0);
+        PublicKey::from(*x25519_dalek::PublicKey::from(&static_secret).as_bytes())
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl From<[u8; 32]> for PrivateKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
 }
 
 impl FromStr for PrivateKey {
     type Err = failure::Error;
 
+    // Errors never include the input, since it's most likely a slightly mangled key.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut decoded = [0u8; 32];
-        let written = base64::decode_config_slice(&s, base64::STANDARD, &mut decoded)?;
-
-        if written != 32 {
+        // A 32 byte key is 44 characters of base64. Longer input would overrun the buffer it's
+        // decoded into.
+        if s.len() != 44 {
             return Err(InvalidLengthError.into());
         }
-
-        let static_secret = x25519_dalek::StaticSecret::from(decoded);
-        let bytes = static_secret.to_bytes();
-        Ok(Self(bytes))
+        let mut decoded = [0u8; 32];
+        let key = match base64::decode_config_slice(&s, base64::STANDARD, &mut decoded) {
+            Ok(32) => Ok(Self(StaticSecret::from(decoded).to_bytes())),
+            Ok(_) => Err(InvalidLengthError.into()),
+            Err(_) => Err(InvalidEncodingError.into()),
+        };
+        decoded.zeroize();
+        key
     }
 }
 
-impl fmt::Display for PrivateKey {
+impl fmt::Debug for PrivateKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", base64::encode(self.as_bytes()))
+        write!(f, "PrivateKey(redacted)")
+    }
+}
+
+impl Serialize for PrivateKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for PrivateKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        PrivateKey::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
+#[derive(Debug, failure::Fail)]
+#[fail(display = "private keys must be base64 encoded")]
+struct InvalidEncodingError;
+
 #[derive(Debug, failure::Fail)]
 #[fail(display = "private keys must be exactly 32 bytes long")]
 struct InvalidLengthError;
+
+#[cfg(test)]
+mod tests {
+    use super::PrivateKey;
+
+    // Alice's keys from RFC 7748, section 6.1.
+    #[test]
+    fn derives_the_public_key() -> Result<(), failure::Error> {
+        let private_key: PrivateKey = "dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=".parse()?;
+        assert_eq!(
+            private_key.public_key().to_string(),
+            "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo="
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn over_long_keys_are_rejected() {
+        let over_long = "dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo".repeat(2) + "=";
+        let error = over_long
+            .parse::<PrivateKey>()
+            .err()
+            .map(|err| err.to_string());
+        assert_eq!(
+            error,
+            Some("private keys must be exactly 32 bytes long".to_string())
+        );
+    }
+}
@@ -1,9 +1,55 @@
+use crate::impl_serde_with_fromstr;
 use crate::impl_with_fromstr_with_error;
-use base64;
-use failure;
 use std::fmt;
 use std::str::FromStr;
-use x25519_dalek;
+
+// Curve25519 points of order 1, 2, 4 or 8, as little-endian u-coordinates. Any private key
+// multiplied with one of these gives all zeros, so a peer with such a key can never complete a
+// handshake. The top bit is ignored by X25519, so it's masked off before comparing.
+const LOW_ORDER_POINTS: [[u8; 32]; 7] = [
+    // 0
+    [0; 32],
+    // 1
+    [
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ],
+    // The two points of order 8
+    [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4,
+        0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49,
+        0xb8, 0x00,
+    ],
+    [
+        0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef,
+        0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f,
+        0x11, 0x57,
+    ],
+    // p - 1
+    [
+        0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    // p and p + 1, which are 0 and 1 written the long way
+    [
+        0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    [
+        0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+];
+
+fn is_low_order(bytes: &[u8; 32]) -> bool {
+    LOW_ORDER_POINTS
+        .iter()
+        .any(|point| point[..31] == bytes[..31] && point[31] == bytes[31] & 0x7f)
+}
 
 #[derive(Clone)]
 pub struct PublicKey(x25519_dalek::PublicKey);
@@ -15,10 +61,21 @@ impl PublicKey {
     }
 }
 
+impl From<[u8; 32]> for PublicKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(x25519_dalek::PublicKey::from(bytes))
+    }
+}
+
 impl FromStr for PublicKey {
     type Err = failure::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A 32 byte key is 44 characters of base64. Longer input would overrun the buffer it's
+        // decoded into.
+        if s.len() != 44 {
+            return Err(InvalidLengthError.into());
+        }
         let mut decoded = [0u8; 32];
         let written = base64::decode_config_slice(&s, base64::STANDARD, &mut decoded)?;
 
@@ -26,6 +83,13 @@ impl FromStr for PublicKey {
             return Err(InvalidLengthError.into());
         }
 
+        if decoded == [0; 32] {
+            return Err(ZeroKeyError.into());
+        }
+        if is_low_order(&decoded) {
+            return Err(LowOrderKeyError.into());
+        }
+
         Ok(Self(x25519_dalek::PublicKey::from(decoded)))
     }
 }
@@ -37,7 +101,61 @@ impl fmt::Display for PublicKey {
 }
 
 impl_with_fromstr_with_error!(PublicKey);
+impl_serde_with_fromstr!(PublicKey);
 
 #[derive(Debug, failure::Fail)]
 #[fail(display = "public keys must be exactly 32 bytes long")]
 struct InvalidLengthError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "this key is all zeros, which isn't a real public key")]
+struct ZeroKeyError;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "this key is a low-order point, which no peer can complete a handshake with")]
+struct LowOrderKeyError;
+
+#[cfg(test)]
+mod tests {
+    use super::PublicKey;
+
+    #[test]
+    fn weak_keys_are_rejected() {
+        let error = |s: &str| s.parse::<PublicKey>().err().map(|err| err.to_string());
+
+        assert_eq!(
+            error("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            Some("this key is all zeros, which isn't a real public key".to_string())
+        );
+        // 1, an order-8 point, and p - 1 with the ignored top bit set.
+        for low_order in &[
+            "AQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+            "4Ot6fDtBuK4WVuP68Z/EatoJjeucMrH9hmIFFl9JuAA=",
+            "7P////////////////////////////////////////8=",
+        ] {
+            assert_eq!(
+                error(low_order),
+                Some(
+                    "this key is a low-order point, which no peer can complete a handshake with"
+                        .to_string()
+                )
+            );
+        }
+
+        assert_eq!(error("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="), None);
+    }
+
+    #[test]
+    fn over_long_keys_are_rejected() {
+        // Twice as long as a key, which used to overrun the buffer it was decoded into.
+        let over_long = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg".repeat(2) + "=";
+        let error = over_long
+            .parse::<PublicKey>()
+            .err()
+            .map(|err| err.to_string());
+        assert_eq!(
+            error,
+            Some("public keys must be exactly 32 bytes long".to_string())
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::PublicKey;
+use crate::fairings::Database;
+use crate::lang;
+use crate::models::allocation;
+use crate::models::allocation::NewReservation;
+use crate::models::rotation;
+use crate::models::{Allocation, Reservation};
+use crate::states::Interfaces;
+use askama::Template;
+use ipnet::IpNet;
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket::{get, post, FromForm};
+use std::net::IpAddr;
+
+pub struct AllocationEntry {
+    address: String,
+    public_key: String,
+    created: String,
+}
+
+pub struct ReservationEntry {
+    id: i32,
+    network: String,
+    public_key: String,
+    description: String,
+}
+
+#[derive(Template)]
+#[template(path = "network/addresses.html")]
+pub struct AddressesTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    networks: Vec<String>,
+    allocations: Vec<AllocationEntry>,
+    reservations: Vec<ReservationEntry>,
+}
+
+#[get("/<interface>/addresses")]
+pub fn addresses(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    flash: Option<FlashMessage>,
+) -> Result<Option<AddressesTemplate>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+
+    // Pruned with the config locked, the same as when a peer is added or edited, so an allocation
+    // made in the meantime isn't taken for one that's gone.
+    let interface_config = wg.lock_config();
+    allocation::prune(&conn, &interface, &interface_config.peer_public_keys())?;
+    drop(interface_config);
+    let allocations = Allocation::all(&conn, &interface)?
+        .into_iter()
+        .map(|allocation| AllocationEntry {
+            address: allocation.address,
+            public_key: allocation.public_key,
+            created: rotation::format_time(allocation.created_at),
+        })
+        .collect();
+    let reservations = Reservation::all(&conn, &interface)?
+        .into_iter()
+        .map(|reservation| ReservationEntry {
+            id: reservation.id,
+            network: reservation.network,
+            public_key: reservation.public_key.unwrap_or_default(),
+            description: reservation.description,
+        })
+        .collect();
+
+    Ok(Some(AddressesTemplate {
+        interfaces: interfaces.names(),
+        status: flash.map(|flash| flash.msg().to_string()),
+        networks: wg.networks().iter().map(ToString::to_string).collect(),
+        allocations,
+        reservations,
+        interface,
+    }))
+}
+
+#[derive(FromForm)]
+pub struct Reserve {
+    network: String,
+    // Left empty to keep the whole network from being handed out.
+    public_key: String,
+    description: String,
+}
+
+// A single address can be given without a prefix length.
+fn parse_network(s: &str) -> Result<IpNet, failure::Error> {
+    match s.parse::<IpAddr>() {
+        Ok(addr) => Ok(IpNet::from(addr)),
+        Err(_) => Ok(s.parse::<IpNet>()?.trunc()),
+    }
+}
+
+#[post("/<interface>/addresses/reservations", data = "<form>")]
+pub fn post_reserve(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    form: Form<Reserve>,
+) -> Option<Flash<Redirect>> {
+    let wg = interfaces.get(&interface)?;
+    let redirect = Redirect::to(format!("/network/{}/addresses", interface));
+
+    let network = match parse_network(form.network.trim()) {
+        Ok(network) => network,
+        Err(err) => return Some(Flash::error(redirect, format!("{}", err))),
+    };
+    if !wg.networks().iter().any(|net| net.contains(&network)) {
+        return Some(Flash::error(redirect, lang::RESERVATION_OUTSIDE));
+    }
+
+    let public_key = match form.public_key.trim() {
+        "" => None,
+        public_key => match public_key.parse::<PublicKey>() {
+            Ok(public_key) => Some(public_key.to_string()),
+            Err(err) => return Some(Flash::error(redirect, format!("{}", err))),
+        },
+    };
+
+    let reservation = NewReservation {
+        interface: &interface,
+        network: &network.to_string(),
+        public_key: public_key.as_deref(),
+        description: form.description.trim(),
+    };
+    Some(match allocation::reserve(&conn, &reservation) {
+        Ok(_) => Flash::success(redirect, lang::RESERVATION_SAVED),
+        Err(_) => Flash::error(redirect, lang::RESERVATION_ERROR),
+    })
+}
+
+#[post("/<interface>/addresses/reservations/<id>/delete")]
+pub fn post_unreserve(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    id: i32,
+) -> Option<Flash<Redirect>> {
+    interfaces.get(&interface)?;
+    let redirect = Redirect::to(format!("/network/{}/addresses", interface));
+
+    Some(match allocation::unreserve(&conn, &interface, id) {
+        Ok(_) => Flash::success(redirect, lang::RESERVATION_REMOVED),
+        Err(_) => Flash::error(redirect, lang::RESERVATION_ERROR),
+    })
+}
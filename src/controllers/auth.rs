@@ -2,12 +2,33 @@ use crate::controllers::index::*;
 use crate::fairings::Database;
 use crate::models::User;
 use askama::Template;
-use failure;
-use rocket::http::{Cookie, Cookies};
-use rocket::request::Form;
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, Form, FromRequest, Request};
 use rocket::response::{Flash, Redirect};
 use rocket::{get, post, uri, FromForm, Responder};
 
+// The logged in user, if there is one.
+pub fn current_user_id(cookies: &mut Cookies) -> Option<i32> {
+    cookies
+        .get_private("user_id")
+        .and_then(|cookie| cookie.value().parse().ok())
+}
+
+// Guards routes that hand out keys, which only a logged in user may see.
+pub struct LoggedInUser(pub i32);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LoggedInUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match current_user_id(&mut request.cookies()) {
+            Some(user_id) => Outcome::Success(LoggedInUser(user_id)),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "auth/login.html")]
 pub struct LoginTemplate {}
@@ -1,5 +1,7 @@
+pub mod addresses;
 pub mod auth;
 pub mod index;
 pub mod network;
 pub mod peers;
+pub mod rotation;
 pub mod users;
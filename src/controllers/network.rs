@@ -1,27 +1,249 @@
-use crate::states::WgState;
+use crate::config::document::{Format, Secrets};
+use crate::config::lookup::Lookup;
+use crate::config::{Drift, PublicKey};
+use crate::fairings::Database;
+use crate::lang;
+use crate::models;
+use crate::models::rotation;
+use crate::models::{Rotation, User};
+use crate::states::Interfaces;
 use askama::Template;
-use failure;
-use rocket::get;
+use rocket::http::uri::Uri;
+use rocket::request::FlashMessage;
+use rocket::response::content::Json;
+use rocket::response::{Flash, Redirect};
 use rocket::State;
-use wireguard_uapi::get::Device;
+use rocket::{get, post};
+use std::net::IpAddr;
+use wireguard_uapi::get;
 
 #[derive(Template)]
 #[template(path = "network/index.html")]
 pub struct IndexTemplate {
-    device: Device,
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    public_key: String,
+    peers: Vec<PeerRow>,
+    missing_peers: Vec<MissingPeer>,
+    pending_rotations: Vec<PendingRotation>,
+}
+
+pub struct PeerRow {
+    peer: get::Peer,
+    // None for a peer that isn't in the database, such as one added with `wg set`.
+    details: Option<PeerDetails>,
+}
+
+pub struct PeerDetails {
+    name: String,
+    description: String,
+    owner: String,
+    created: String,
+    updated: String,
+}
+
+// In the database, but not on the device.
+pub struct MissingPeer {
+    public_key: String,
+    name: String,
+}
+
+pub struct PendingRotation {
+    public_key: String,
+    public_key_param: String,
+    cutover: String,
 }
 
 #[get("/")]
-pub fn index(wg: State<WgState>) -> Result<IndexTemplate, failure::Error> {
+pub fn default(interfaces: State<Interfaces>) -> Option<Redirect> {
+    let interface = interfaces.names().into_iter().next()?;
+    Some(Redirect::to(format!("/network/{}", interface)))
+}
+
+#[get("/<interface>")]
+pub fn index(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    flash: Option<FlashMessage>,
+) -> Result<Option<IndexTemplate>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+
     let device = wg.get_device()?;
-    Ok(IndexTemplate { device })
+    let emails = User::emails(&conn)?;
+    let email = |user_id: Option<i32>| {
+        user_id
+            .and_then(|user_id| emails.iter().find(|(id, _)| *id == user_id))
+            .map(|(_, email)| email.clone())
+            .unwrap_or_default()
+    };
+
+    let mut known = models::Peer::all(&conn, &interface)?;
+    let peers = device
+        .peers
+        .into_iter()
+        .map(|peer| {
+            let public_key = PublicKey::from(peer.public_key).to_string();
+            let details = known
+                .iter()
+                .position(|details| details.public_key == public_key)
+                .map(|index| known.remove(index))
+                .map(|details| PeerDetails {
+                    owner: email(details.owner_id),
+                    created: rotation::format_time(details.created_at),
+                    updated: rotation::format_time(details.updated_at),
+                    name: details.name,
+                    description: details.description,
+                });
+            PeerRow { peer, details }
+        })
+        .collect();
+    let missing_peers = known
+        .into_iter()
+        .map(|details| MissingPeer {
+            public_key: details.public_key,
+            name: details.name,
+        })
+        .collect();
+
+    let pending_rotations = Rotation::pending(&conn, &interface)?
+        .into_iter()
+        .map(|pending| PendingRotation {
+            public_key_param: Uri::percent_encode(&pending.public_key).into_owned(),
+            cutover: rotation::format_time(pending.cutover_at),
+            public_key: pending.public_key,
+        })
+        .collect();
+    Ok(Some(IndexTemplate {
+        interface,
+        interfaces: interfaces.names(),
+        status: flash.map(|flash| flash.msg().to_string()),
+        public_key: wg.public_key().to_string(),
+        peers,
+        missing_peers,
+        pending_rotations,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "network/drift.html")]
+pub struct DriftTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    drift: Drift,
+}
+
+#[get("/<interface>/drift")]
+pub fn drift(
+    interfaces: State<Interfaces>,
+    interface: String,
+    flash: Option<FlashMessage>,
+) -> Result<Option<DriftTemplate>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+
+    let drift = wg.drift()?;
+    Ok(Some(DriftTemplate {
+        interface,
+        interfaces: interfaces.names(),
+        status: flash.map(|flash| flash.msg().to_string()),
+        drift,
+    }))
+}
+
+#[post("/<interface>/drift/reconcile")]
+pub fn post_reconcile(interfaces: State<Interfaces>, interface: String) -> Option<Flash<Redirect>> {
+    let wg = interfaces.get(&interface)?;
+    let redirect = Redirect::to(format!("/network/{}/drift", interface));
+
+    Some(match wg.reconcile() {
+        Ok(_) => Flash::success(redirect, lang::RECONCILE_SUCCESS),
+        Err(_) => Flash::error(redirect, lang::RECONCILE_ERROR),
+    })
+}
+
+// Keys are always left out. Anyone who can reach the web interface can read this.
+#[get("/<interface>/config.json")]
+pub fn config_json(
+    interfaces: State<Interfaces>,
+    interface: String,
+) -> Result<Option<Json<String>>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+
+    let document = wg.export(Secrets::Redact);
+    Ok(Some(Json(Format::Json.serialize(&document)?)))
+}
+
+pub struct InterfaceLookup {
+    name: String,
+    device: Lookup,
+    config: Lookup,
+}
+
+#[derive(Template)]
+#[template(path = "network/lookup.html")]
+pub struct LookupTemplate {
+    // Not scoped to an interface, so none is highlighted.
+    interface: String,
+    interfaces: Vec<String>,
+    ip: String,
+    ip_err: Option<String>,
+    lookups: Vec<InterfaceLookup>,
+}
+
+// The device and the config are both looked up, since a peer changed outside of the config may
+// have taken the address.
+#[get("/lookup?<ip>")]
+pub fn lookup(
+    interfaces: State<Interfaces>,
+    ip: Option<String>,
+) -> Result<LookupTemplate, failure::Error> {
+    let ip = ip.unwrap_or_default();
+    let mut template = LookupTemplate {
+        interface: String::new(),
+        interfaces: interfaces.names(),
+        ip: ip.trim().to_string(),
+        ip_err: None,
+        lookups: vec![],
+    };
+    if template.ip.is_empty() {
+        return Ok(template);
+    }
+
+    let address = match template.ip.parse::<IpAddr>() {
+        Ok(address) => address,
+        Err(ip_err) => {
+            template.ip_err = Some(format!("{}", ip_err));
+            return Ok(template);
+        }
+    };
+    for name in interfaces.names() {
+        let wg = match interfaces.get(&name) {
+            Some(wg) => wg,
+            None => continue,
+        };
+        template.lookups.push(InterfaceLookup {
+            device: wg.lookup(address)?,
+            config: wg.lookup_in_config(address),
+            name,
+        });
+    }
+    Ok(template)
 }
 
 mod filters {
     use askama::Error;
-    use base64;
-    use humantime;
-    use pretty_bytes;
+    use rocket::http::uri::Uri;
     use std::net::SocketAddr;
     use std::time::{Duration, SystemTime};
     use wireguard_uapi::get::AllowedIp;
@@ -30,6 +252,11 @@ mod filters {
         Ok(base64::encode(input))
     }
 
+    // A key as it appears in a URL path, where base64's slashes have to be escaped.
+    pub fn url_param<T: ?Sized + AsRef<[u8]>>(input: &T) -> Result<String, Error> {
+        Ok(Uri::percent_encode(&base64::encode(input)).into_owned())
+    }
+
     pub fn endpoint(endpoint: &SocketAddr) -> Result<String, Error> {
         Ok(format!("{}:{}", endpoint.ip(), endpoint.port()))
     }
@@ -1,25 +1,55 @@
 use crate::config;
-use crate::config::peer::AllowedIps;
-use crate::config::{PresharedKey, PublicKey};
+use crate::config::client::ClientOptions;
+use crate::config::interface::Dns;
+use crate::config::peer::{AllowedIp, AllowedIps, PeerEdit, PresharedKeyEdit};
+use crate::config::{Endpoint, PresharedKey, PublicKey};
+use crate::controllers::auth;
+use crate::controllers::auth::LoggedInUser;
+use crate::fairings::Database;
 use crate::lang;
-use crate::states::WgState;
+use crate::models;
+use crate::models::allocation;
+use crate::models::peer;
+use crate::models::peer::PeerDetails;
+use crate::models::rotation;
+use crate::models::User;
+use crate::qr;
+use crate::states::wgstate::LockedConfig;
+use crate::states::{Interfaces, ProvisionedClients, PublicHost, WgState};
 use crate::utils::FormInputResult;
 use crate::utils::FormOption;
 use askama::Template;
+use diesel::{Connection, SqliteConnection};
+use ipnet::IpNet;
+use rocket::http::uri::Uri;
+use rocket::http::Cookies;
 use rocket::http::RawStr;
-use rocket::http::Status;
-use rocket::request::Form;
-use rocket::response::status;
+use rocket::http::{ContentType, Status};
+use rocket::request::{FlashMessage, Form, Request};
+use rocket::response::{self, status, Flash, Redirect, Responder, Response};
 use rocket::{get, State};
 use rocket::{post, FromForm};
 use std::borrow::Cow;
 use std::default::Default;
-use std::net::SocketAddr;
+use std::io::Cursor;
+use std::str::FromStr;
 
 #[derive(Default, Template)]
 #[template(path = "peers/add.html")]
 pub struct AddPeerTemplate<'a> {
+    interface: String,
+    interfaces: Vec<String>,
     status: Option<Cow<'a, str>>,
+    // Filled in when the form is shown again, so the peer can be added without retyping it. The
+    // preshared key is left out, since it's a secret.
+    name: String,
+    description: String,
+    public_key: String,
+    allowed_ips: String,
+    endpoint: String,
+    persistent_keepalive: String,
+    // Routes that adding the peer would move from existing peers, which have to be confirmed.
+    taken_over_routes: Vec<String>,
     public_key_err: Option<String>,
     preshared_key_err: Option<String>,
     allowed_ips_err: Option<String>,
@@ -27,9 +57,69 @@ pub struct AddPeerTemplate<'a> {
     persistent_keepalive_err: Option<String>,
 }
 
-#[get("/add")]
-pub fn add() -> AddPeerTemplate<'static> {
-    AddPeerTemplate::default()
+impl AddPeerTemplate<'_> {
+    fn new(interfaces: &Interfaces, interface: String) -> Self {
+        Self {
+            interface,
+            interfaces: interfaces.names(),
+            ..Default::default()
+        }
+    }
+}
+
+// Addresses that are allocated or reserved, and so can't be handed out. Peers that were removed
+// from the config file have their addresses released first.
+fn taken_addresses(
+    conn: &SqliteConnection,
+    interface_config: &LockedConfig,
+    public_key: Option<&PublicKey>,
+) -> Result<Vec<IpNet>, failure::Error> {
+    allocation::prune(
+        conn,
+        interface_config.name(),
+        &interface_config.peer_public_keys(),
+    )?;
+    allocation::taken(conn, interface_config.name(), public_key)
+}
+
+// A peer with static reservations is given those, and otherwise the next free addresses.
+fn allocate(
+    conn: &SqliteConnection,
+    interface_config: &LockedConfig,
+    public_key: &PublicKey,
+) -> Result<AllowedIps, failure::Error> {
+    let mut addresses = allocation::reserved_for(conn, interface_config.name(), public_key)?;
+    if addresses.is_empty() {
+        let taken = taken_addresses(conn, interface_config, Some(public_key))?;
+        addresses = interface_config.next_free_addresses(&taken)?;
+    }
+    Ok(AllowedIps(addresses.iter().map(AllowedIp::from).collect()))
+}
+
+// The allowed IPs are prefilled with the next free addresses, if there are any.
+#[get("/<interface>/add")]
+pub fn add(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+) -> Option<AddPeerTemplate<'static>> {
+    let interface_config = interfaces.get(&interface)?.lock_config();
+    let allowed_ips = taken_addresses(&conn, &interface_config, None)
+        .and_then(|taken| interface_config.next_free_addresses(&taken))
+        .map(|addresses| {
+            addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    drop(interface_config);
+
+    Some(AddPeerTemplate {
+        allowed_ips,
+        ..AddPeerTemplate::new(&interfaces, interface)
+    })
 }
 
 #[derive(FromForm)]
@@ -37,20 +127,23 @@ pub struct AddPeer<'v> {
     public_key: FormInputResult<'v, PublicKey>,
     preshared_key: FormOption<FormInputResult<'v, PresharedKey>>,
     allowed_ips: FormOption<FormInputResult<'v, AllowedIps>>,
-    // TODO: Allow endpoint to also be a hostname
-    endpoint: FormOption<Result<SocketAddr, &'v RawStr>>,
+    endpoint: FormOption<FormInputResult<'v, Endpoint>>,
     persistent_keepalive: FormOption<Result<u16, &'v RawStr>>,
+    take_over_routes: bool,
+    name: Option<String>,
+    description: Option<String>,
 }
 
-#[post("/add", data = "<form>")]
+#[post("/<interface>/add", data = "<form>")]
 pub fn post_add(
-    wg: State<WgState>,
+    conn: Database,
+    mut cookies: Cookies,
+    interfaces: State<Interfaces>,
+    interface: String,
     form: Form<AddPeer>,
-) -> status::Custom<AddPeerTemplate<'static>> {
-    // TODO:
-    //   - Record this new peer in the database.
-    //   - Calculate the next available IP and give it to this peer.
-
+) -> Option<status::Custom<AddPeerTemplate<'static>>> {
+    let wg = interfaces.get(&interface)?;
+    let defaults = AddPeerTemplate::new(&interfaces, interface.clone());
     let add_peer = form.into_inner();
 
     let public_key = match add_peer.public_key {
@@ -58,44 +151,70 @@ pub fn post_add(
         Err(public_key_err) => {
             let template = AddPeerTemplate {
                 public_key_err: Some(format!("{}", public_key_err.error)),
-                ..Default::default()
+                ..defaults
             };
-            return status::Custom(Status::BadRequest, template);
+            return Some(status::Custom(Status::BadRequest, template));
         }
     };
 
+    if let Err(public_key_err) = wg.check_peer_public_key(&public_key) {
+        let template = AddPeerTemplate {
+            public_key_err: Some(format!("{}", public_key_err)),
+            ..defaults
+        };
+        return Some(status::Custom(Status::BadRequest, template));
+    }
+
     let preshared_key = match add_peer.preshared_key.into() {
         Some(Ok(preshared_key)) => Some(preshared_key),
         Some(Err(preshared_key_err)) => {
             let template = AddPeerTemplate {
                 preshared_key_err: Some(format!("{}", preshared_key_err.error)),
-                ..Default::default()
+                ..defaults
             };
-            return status::Custom(Status::BadRequest, template);
+            return Some(status::Custom(Status::BadRequest, template));
         }
         None => None,
     };
 
     let allowed_ips = match add_peer.allowed_ips.into() {
-        Some(Ok(allowed_ips)) => allowed_ips,
+        Some(Ok(allowed_ips)) => match allowed_ips.check_host_bits() {
+            Ok(_) => Some(allowed_ips),
+            Err(host_bits_err) => {
+                let template = AddPeerTemplate {
+                    allowed_ips_err: Some(format!("{}", host_bits_err)),
+                    ..defaults
+                };
+                return Some(status::Custom(Status::BadRequest, template));
+            }
+        },
         Some(Err(allowed_ips_err)) => {
             let template = AddPeerTemplate {
                 allowed_ips_err: Some(format!("{}", allowed_ips_err.error)),
-                ..Default::default()
+                ..defaults
             };
-            return status::Custom(Status::BadRequest, template);
+            return Some(status::Custom(Status::BadRequest, template));
         }
-        None => AllowedIps::new(),
+        None => None,
     };
 
     let endpoint = match add_peer.endpoint.into() {
-        Some(Ok(endpoint)) => Some(endpoint),
-        Some(Err(_)) => {
+        Some(Ok(mut endpoint)) => match endpoint.resolve() {
+            Ok(_) => Some(endpoint),
+            Err(resolve_err) => {
+                let template = AddPeerTemplate {
+                    endpoint_err: Some(format!("{}", resolve_err)),
+                    ..defaults
+                };
+                return Some(status::Custom(Status::BadRequest, template));
+            }
+        },
+        Some(Err(endpoint_err)) => {
             let template = AddPeerTemplate {
-                endpoint_err: Some("huh".to_string()),
-                ..Default::default()
+                endpoint_err: Some(format!("{}", endpoint_err.error)),
+                ..defaults
             };
-            return status::Custom(Status::BadRequest, template);
+            return Some(status::Custom(Status::BadRequest, template));
         }
         None => None,
     };
@@ -104,31 +223,867 @@ pub fn post_add(
         Some(Ok(persistent_keepalive)) => Some(persistent_keepalive),
         Some(Err(_)) => {
             let template = AddPeerTemplate {
-                persistent_keepalive_err: Some("huh".to_string()),
-                ..Default::default()
+                persistent_keepalive_err: Some(lang::INVALID_PERSISTENT_KEEPALIVE.to_string()),
+                ..defaults
             };
-            return status::Custom(Status::BadRequest, template);
+            return Some(status::Custom(Status::BadRequest, template));
         }
         None => None,
     };
 
+    // Everything from picking the peer's addresses to recording them happens with the config
+    // locked, so a peer added at the same time can't be given the same ones.
+    let mut interface_config = wg.lock_config();
+    let networks = interface_config.networks();
+    let allowed_ips = match allowed_ips {
+        Some(allowed_ips) => allowed_ips,
+        // An interface without an Address has nothing to allocate from, so the peer is added
+        // without any AllowedIPs.
+        None if networks.is_empty() => AllowedIps::new(),
+        None => match allocate(&conn, &interface_config, &public_key) {
+            Ok(allowed_ips) => allowed_ips,
+            Err(allocate_err) => {
+                let template = AddPeerTemplate {
+                    allowed_ips_err: Some(format!("{}", allocate_err)),
+                    ..defaults
+                };
+                return Some(status::Custom(Status::BadRequest, template));
+            }
+        },
+    };
+
+    // Peers removed from the config file since their addresses were allocated don't hold them up.
+    let addresses = allocation::host_addresses(&allowed_ips, &networks);
+    let available = allocation::prune(&conn, &interface, &interface_config.peer_public_keys())
+        .map_err(failure::Error::from)
+        .and_then(|_| allocation::check_available(&conn, &interface, &public_key, &addresses));
+    if let Err(allowed_ips_err) = available {
+        let template = AddPeerTemplate {
+            allowed_ips_err: Some(format!("{}", allowed_ips_err)),
+            ..defaults
+        };
+        return Some(status::Custom(Status::BadRequest, template));
+    }
+
+    let taken_over_routes = interface_config.routes_taken_over(&public_key, &allowed_ips);
+    if !taken_over_routes.is_empty() && !add_peer.take_over_routes {
+        let template = AddPeerTemplate {
+            public_key: public_key.to_string(),
+            // The key isn't sent back to the browser, so it has to be entered again.
+            preshared_key_err: preshared_key
+                .as_ref()
+                .map(|_| lang::REENTER_PRESHARED_KEY.to_string()),
+            allowed_ips: allowed_ips.to_string(),
+            endpoint: endpoint
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            persistent_keepalive: persistent_keepalive
+                .map(|secs| secs.to_string())
+                .unwrap_or_default(),
+            taken_over_routes: taken_over_routes.iter().map(ToString::to_string).collect(),
+            name: add_peer.name.unwrap_or_default(),
+            description: add_peer.description.unwrap_or_default(),
+            ..defaults
+        };
+        return Some(status::Custom(Status::Conflict, template));
+    }
+
     let config_peer = config::Peer {
         public_key: public_key.clone(),
         preshared_key,
+        preshared_key_source: None,
         allowed_ips,
         endpoint,
         persistent_keepalive,
     };
 
-    let add_peer_result = wg.add_peer(config_peer);
+    let details = PeerDetails {
+        name: add_peer.name.as_deref().unwrap_or_default().trim(),
+        description: add_peer.description.as_deref().unwrap_or_default().trim(),
+        owner_id: None,
+    };
+    let user_id = auth::current_user_id(&mut cookies);
+
+    // The addresses are recorded first, so a failure to record them leaves the peer off the
+    // interface, and a failure to add the peer takes the record back out.
+    let now = rotation::now();
+    let added = conn.transaction::<_, failure::Error, _>(|| {
+        allocation::record(&conn, &interface, &public_key, &addresses, now)?;
+        interface_config.add_peer(config_peer)
+    });
+    drop(interface_config);
+
+    let status = match added {
+        Ok(_) => {
+            if peer::save(&conn, &interface, &public_key, &details, user_id, now).is_err() {
+                lang::PEER_DETAILS_ERROR.into()
+            } else {
+                format!("{} {}", lang::ADD_PEER_SUCCESS, public_key).into()
+            }
+        }
+        Err(_) => lang::ADD_PEER_ERROR.into(),
+    };
     let template = AddPeerTemplate {
-        status: Some(match add_peer_result {
-            Ok(_) => format!("{} {}", lang::ADD_PEER_SUCCESS, public_key).into(),
-            Err(_) => lang::ADD_PEER_ERROR.into(),
-        }),
-        ..Default::default()
+        status: Some(status),
+        ..defaults
     };
-    status::Custom(Status::Ok, template)
+    Some(status::Custom(Status::Ok, template))
+}
+
+#[derive(Template)]
+#[template(path = "peers/provision.html")]
+pub struct ProvisionTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    endpoint: String,
+    allowed_ips: String,
+    endpoint_err: Option<String>,
+    allowed_ips_err: Option<String>,
+    except_err: Option<String>,
+    dns_err: Option<String>,
+    persistent_keepalive_err: Option<String>,
+}
+
+impl ProvisionTemplate {
+    // The endpoint is only filled in if --public-host was given and the interface has a fixed
+    // ListenPort. Clients route the interface's networks through the tunnel unless told otherwise.
+    fn new(interfaces: &Interfaces, wg: &WgState, public_host: &PublicHost) -> Self {
+        Self {
+            interface: wg.name().to_string(),
+            interfaces: interfaces.names(),
+            status: None,
+            endpoint: wg
+                .listen_port()
+                .and_then(|port| public_host.endpoint(port))
+                .unwrap_or_default(),
+            allowed_ips: wg
+                .networks()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", "),
+            endpoint_err: None,
+            allowed_ips_err: None,
+            except_err: None,
+            dns_err: None,
+            persistent_keepalive_err: None,
+        }
+    }
+}
+
+#[get("/<interface>/provision")]
+pub fn provision(
+    interfaces: State<Interfaces>,
+    public_host: State<PublicHost>,
+    interface: String,
+) -> Option<ProvisionTemplate> {
+    let wg = interfaces.get(&interface)?;
+    Some(ProvisionTemplate::new(&interfaces, wg, &public_host))
+}
+
+#[derive(FromForm)]
+pub struct Provision<'v> {
+    endpoint: FormInputResult<'v, Endpoint>,
+    allowed_ips: FormInputResult<'v, AllowedIps>,
+    except: FormOption<FormInputResult<'v, AllowedIps>>,
+    dns: String,
+    preshared_key: bool,
+    persistent_keepalive: FormOption<Result<u16, &'v RawStr>>,
+    name: Option<String>,
+    description: Option<String>,
+}
+
+pub enum ProvisionResponse {
+    Created(Flash<Redirect>),
+    Form(status::Custom<ProvisionTemplate>),
+}
+
+impl<'r> Responder<'r> for ProvisionResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            ProvisionResponse::Created(redirect) => redirect.respond_to(request),
+            ProvisionResponse::Form(template) => template.respond_to(request),
+        }
+    }
+}
+
+#[post("/<interface>/provision", data = "<form>")]
+pub fn post_provision(
+    conn: Database,
+    user: LoggedInUser,
+    interfaces: State<Interfaces>,
+    public_host: State<PublicHost>,
+    provisioned_clients: State<ProvisionedClients>,
+    interface: String,
+    form: Form<Provision>,
+) -> Option<ProvisionResponse> {
+    let wg = interfaces.get(&interface)?;
+    let defaults = ProvisionTemplate::new(&interfaces, wg, &public_host);
+    let provision = form.into_inner();
+    let invalid = |template| {
+        Some(ProvisionResponse::Form(status::Custom(
+            Status::BadRequest,
+            template,
+        )))
+    };
+
+    let endpoint = match provision.endpoint {
+        Ok(endpoint) => endpoint,
+        Err(endpoint_err) => {
+            return invalid(ProvisionTemplate {
+                endpoint_err: Some(format!("{}", endpoint_err.error)),
+                ..defaults
+            });
+        }
+    };
+
+    let allowed_ips = match provision.allowed_ips {
+        Ok(allowed_ips) => match allowed_ips.check_host_bits() {
+            Ok(_) => allowed_ips,
+            Err(host_bits_err) => {
+                return invalid(ProvisionTemplate {
+                    allowed_ips_err: Some(format!("{}", host_bits_err)),
+                    ..defaults
+                });
+            }
+        },
+        Err(allowed_ips_err) => {
+            return invalid(ProvisionTemplate {
+                allowed_ips_err: Some(format!("{}", allowed_ips_err.error)),
+                ..defaults
+            });
+        }
+    };
+
+    // Networks the client should keep reaching directly, such as its own LAN, are cut out of the
+    // routes through the tunnel.
+    let allowed_ips = match provision.except.into() {
+        Some(Ok(except)) => match except.check_host_bits() {
+            Ok(_) => allowed_ips.difference(&except),
+            Err(except_err) => {
+                return invalid(ProvisionTemplate {
+                    except_err: Some(format!("{}", except_err)),
+                    ..defaults
+                });
+            }
+        },
+        Some(Err(except_err)) => {
+            return invalid(ProvisionTemplate {
+                except_err: Some(format!("{}", except_err.error)),
+                ..defaults
+            });
+        }
+        None => allowed_ips,
+    };
+
+    let dns = match provision
+        .dns
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Dns::from_str)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(dns) => dns,
+        Err(dns_err) => {
+            return invalid(ProvisionTemplate {
+                dns_err: Some(format!("{}", dns_err)),
+                ..defaults
+            });
+        }
+    };
+
+    let persistent_keepalive = match provision.persistent_keepalive.into() {
+        Some(Ok(persistent_keepalive)) => Some(persistent_keepalive),
+        Some(Err(_)) => {
+            return invalid(ProvisionTemplate {
+                persistent_keepalive_err: Some(lang::INVALID_PERSISTENT_KEEPALIVE.to_string()),
+                ..defaults
+            });
+        }
+        None => None,
+    };
+
+    let options = ClientOptions {
+        endpoint,
+        allowed_ips,
+        dns,
+        preshared_key: provision.preshared_key,
+        persistent_keepalive,
+    };
+    // As when adding a peer, its addresses are picked and recorded with the config locked, and
+    // only kept if the peer is added.
+    let now = rotation::now();
+    let mut interface_config = wg.lock_config();
+    let add_client_result = taken_addresses(&conn, &interface_config, None)
+        .and_then(|taken| interface_config.new_client(options, &taken))
+        .and_then(|(config_peer, client_config)| {
+            let public_key = config_peer.public_key.clone();
+            let addresses = &client_config.interface.address;
+            conn.transaction::<_, failure::Error, _>(|| {
+                allocation::record(&conn, &interface, &public_key, addresses, now)?;
+                interface_config.add_peer(config_peer)
+            })?;
+            Ok(client_config)
+        });
+    drop(interface_config);
+
+    Some(match add_client_result {
+        Ok(client_config) => {
+            let public_key = client_config.interface.private_key.public_key();
+            let details = PeerDetails {
+                name: provision.name.as_deref().unwrap_or_default().trim(),
+                description: provision.description.as_deref().unwrap_or_default().trim(),
+                owner_id: None,
+            };
+            let saved = peer::save(&conn, &interface, &public_key, &details, Some(user.0), now);
+            let redirect = Redirect::to(format!(
+                "/peers/{}/{}/client",
+                interface,
+                Uri::percent_encode(&public_key.to_string())
+            ));
+            let flash = match saved {
+                Ok(_) => Flash::success(
+                    redirect,
+                    format!("{} {}", lang::ADD_PEER_SUCCESS, public_key),
+                ),
+                Err(_) => Flash::error(redirect, lang::PEER_DETAILS_ERROR),
+            };
+            provisioned_clients.insert(&interface, &public_key, user.0, client_config);
+            ProvisionResponse::Created(flash)
+        }
+        Err(err) => ProvisionResponse::Form(status::Custom(
+            Status::Ok,
+            ProvisionTemplate {
+                status: Some(format!("{}: {}", lang::PROVISION_ERROR, err)),
+                ..defaults
+            },
+        )),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "peers/client.html")]
+pub struct ClientTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    public_key: String,
+    // The public key as it appears in URLs, where base64's slashes have to be escaped.
+    public_key_param: String,
+}
+
+// Only found while the client's config is waiting to be picked up, and only by whoever
+// provisioned it.
+#[get("/<interface>/<public_key>/client")]
+pub fn client(
+    user: LoggedInUser,
+    flash: Option<FlashMessage>,
+    interfaces: State<Interfaces>,
+    provisioned_clients: State<ProvisionedClients>,
+    interface: String,
+    public_key: String,
+) -> Option<ClientTemplate> {
+    let parsed_public_key: PublicKey = public_key.parse().ok()?;
+    provisioned_clients.get(&interface, &parsed_public_key, user.0)?;
+
+    Some(ClientTemplate {
+        interface,
+        interfaces: interfaces.names(),
+        status: flash.map(|flash| flash.msg().to_string()),
+        public_key_param: Uri::percent_encode(&public_key).into_owned(),
+        public_key,
+    })
+}
+
+#[get("/<interface>/<public_key>/qr")]
+pub fn client_qr(
+    user: LoggedInUser,
+    provisioned_clients: State<ProvisionedClients>,
+    interface: String,
+    public_key: String,
+) -> Result<Option<ClientConfigResponse>, failure::Error> {
+    let config = match public_key
+        .parse::<PublicKey>()
+        .ok()
+        .and_then(|public_key| provisioned_clients.get(&interface, &public_key, user.0))
+    {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    Ok(Some(ClientConfigResponse::qr_code(qr::to_svg(&config)?)))
+}
+
+// The config can only be downloaded once. The QR code stops working afterwards too.
+#[get("/<interface>/<public_key>/client.conf")]
+pub fn client_conf(
+    user: LoggedInUser,
+    provisioned_clients: State<ProvisionedClients>,
+    interface: String,
+    public_key: String,
+) -> Option<ClientConfigResponse> {
+    let public_key: PublicKey = public_key.parse().ok()?;
+    let config = provisioned_clients.take(&interface, &public_key, user.0)?;
+
+    Some(ClientConfigResponse::download(
+        format!("{}.conf", interface),
+        config,
+    ))
+}
+
+// A client's config, or a QR code of it, either of which holds the client's keys. These are kept
+// out of the browser's cache.
+pub struct ClientConfigResponse {
+    content_type: ContentType,
+    filename: Option<String>,
+    body: String,
+}
+
+impl ClientConfigResponse {
+    pub fn download(filename: String, body: String) -> Self {
+        Self {
+            content_type: ContentType::Plain,
+            filename: Some(filename),
+            body,
+        }
+    }
+
+    pub fn qr_code(svg: String) -> Self {
+        Self {
+            content_type: ContentType::SVG,
+            filename: None,
+            body: svg,
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ClientConfigResponse {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+        response
+            .header(self.content_type)
+            .raw_header("Cache-Control", "no-store")
+            .sized_body(Cursor::new(self.body));
+
+        if let Some(filename) = self.filename {
+            response.raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename),
+            );
+        }
+
+        response.ok()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "peers/edit.html")]
+pub struct EditPeerTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    public_key: String,
+    // The public key as it appears in URLs, where base64's slashes have to be escaped.
+    public_key_param: String,
+    name: String,
+    description: String,
+    // The owner's email address.
+    owner: String,
+    allowed_ips: String,
+    endpoint: String,
+    persistent_keepalive: String,
+    has_preshared_key: bool,
+    clear_preshared_key: bool,
+    // Where the preshared key is read from, in which case it can't be changed here.
+    preshared_key_source: Option<String>,
+    // Routes that the new AllowedIPs would move from other peers, which have to be confirmed.
+    taken_over_routes: Vec<String>,
+    owner_err: Option<String>,
+    preshared_key_err: Option<String>,
+    allowed_ips_err: Option<String>,
+    endpoint_err: Option<String>,
+    persistent_keepalive_err: Option<String>,
+}
+
+impl EditPeerTemplate {
+    // Filled in with the peer as it is now. None if the interface doesn't have the peer.
+    fn new(
+        conn: &SqliteConnection,
+        interfaces: &Interfaces,
+        wg: &WgState,
+        public_key: &PublicKey,
+    ) -> Result<Option<Self>, failure::Error> {
+        let details = models::Peer::find(conn, wg.name(), public_key)?;
+        let owner = match details.as_ref().and_then(|details| details.owner_id) {
+            Some(owner_id) => User::emails(conn)?
+                .into_iter()
+                .find(|(id, _)| *id == owner_id)
+                .map(|(_, email)| email)
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+        let (name, description) = details
+            .map(|details| (details.name, details.description))
+            .unwrap_or_default();
+
+        wg.peer(public_key, |peer| Self {
+            interface: wg.name().to_string(),
+            interfaces: interfaces.names(),
+            status: None,
+            public_key: public_key.to_string(),
+            public_key_param: Uri::percent_encode(&public_key.to_string()).into_owned(),
+            name,
+            description,
+            owner,
+            allowed_ips: peer.allowed_ips.to_string(),
+            endpoint: peer
+                .endpoint
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            persistent_keepalive: peer
+                .persistent_keepalive
+                .map(|secs| secs.to_string())
+                .unwrap_or_default(),
+            has_preshared_key: peer.preshared_key.is_some(),
+            clear_preshared_key: false,
+            preshared_key_source: peer.preshared_key_source.as_ref().map(ToString::to_string),
+            taken_over_routes: vec![],
+            owner_err: None,
+            preshared_key_err: None,
+            allowed_ips_err: None,
+            endpoint_err: None,
+            persistent_keepalive_err: None,
+        })
+    }
+}
+
+#[get("/<interface>/<public_key>/edit")]
+pub fn edit(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+) -> Result<Option<EditPeerTemplate>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+    let public_key = match public_key.parse::<PublicKey>() {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(None),
+    };
+    EditPeerTemplate::new(&conn, &interfaces, wg, &public_key)
+}
+
+#[derive(FromForm)]
+pub struct EditPeer<'v> {
+    name: Option<String>,
+    description: Option<String>,
+    // An email address, or empty for a peer nobody owns.
+    owner: Option<String>,
+    // Left empty to keep the current key.
+    preshared_key: FormOption<FormInputResult<'v, PresharedKey>>,
+    clear_preshared_key: bool,
+    // Left empty to keep the current AllowedIPs.
+    allowed_ips: FormOption<FormInputResult<'v, AllowedIps>>,
+    clear_allowed_ips: bool,
+    endpoint: FormOption<FormInputResult<'v, Endpoint>>,
+    persistent_keepalive: FormOption<Result<u16, &'v RawStr>>,
+    take_over_routes: bool,
+}
+
+pub enum EditPeerResponse {
+    Saved(Flash<Redirect>),
+    Form(status::Custom<EditPeerTemplate>),
+}
+
+impl<'r> Responder<'r> for EditPeerResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            EditPeerResponse::Saved(redirect) => redirect.respond_to(request),
+            EditPeerResponse::Form(template) => template.respond_to(request),
+        }
+    }
+}
+
+#[post("/<interface>/<public_key>/edit", data = "<form>")]
+pub fn post_edit(
+    conn: Database,
+    mut cookies: Cookies,
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+    form: Form<EditPeer>,
+) -> Result<Option<EditPeerResponse>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+    let public_key = match public_key.parse::<PublicKey>() {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(None),
+    };
+    let defaults = match EditPeerTemplate::new(&conn, &interfaces, wg, &public_key)? {
+        Some(defaults) => defaults,
+        None => return Ok(None),
+    };
+    let edit_peer = form.into_inner();
+    let invalid = |template| -> Result<Option<EditPeerResponse>, failure::Error> {
+        Ok(Some(EditPeerResponse::Form(status::Custom(
+            Status::BadRequest,
+            template,
+        ))))
+    };
+
+    let owner_id = match edit_peer.owner.as_deref().unwrap_or_default().trim() {
+        "" => None,
+        email => match User::by_email(&conn, email)? {
+            Some(user) => Some(user.id),
+            None => {
+                return invalid(EditPeerTemplate {
+                    owner_err: Some(format!("{} {}", lang::UNKNOWN_USER, email)),
+                    ..defaults
+                });
+            }
+        },
+    };
+
+    let preshared_key: Option<FormInputResult<PresharedKey>> = edit_peer.preshared_key.into();
+    let preshared_key = match preshared_key {
+        _ if edit_peer.clear_preshared_key => PresharedKeyEdit::Clear,
+        Some(Ok(preshared_key)) => PresharedKeyEdit::Replace(preshared_key),
+        Some(Err(preshared_key_err)) => {
+            return invalid(EditPeerTemplate {
+                preshared_key_err: Some(format!("{}", preshared_key_err.error)),
+                ..defaults
+            });
+        }
+        None => PresharedKeyEdit::Keep,
+    };
+
+    // Left empty, the peer keeps the AllowedIPs it has, so routes are only ever taken away on purpose.
+    let allowed_ips: Option<FormInputResult<AllowedIps>> = edit_peer.allowed_ips.into();
+    let allowed_ips = match allowed_ips {
+        _ if edit_peer.clear_allowed_ips => Some(AllowedIps::new()),
+        Some(Ok(allowed_ips)) => Some(allowed_ips),
+        Some(Err(allowed_ips_err)) => {
+            return invalid(EditPeerTemplate {
+                allowed_ips_err: Some(format!("{}", allowed_ips_err.error)),
+                ..defaults
+            });
+        }
+        None => None,
+    };
+    let endpoint = match edit_peer.endpoint.into() {
+        Some(Ok(mut endpoint)) => match endpoint.resolve() {
+            Ok(_) => Some(endpoint),
+            Err(resolve_err) => {
+                return invalid(EditPeerTemplate {
+                    endpoint_err: Some(format!("{}", resolve_err)),
+                    ..defaults
+                });
+            }
+        },
+        Some(Err(endpoint_err)) => {
+            return invalid(EditPeerTemplate {
+                endpoint_err: Some(format!("{}", endpoint_err.error)),
+                ..defaults
+            });
+        }
+        None => None,
+    };
+
+    let persistent_keepalive = match edit_peer.persistent_keepalive.into() {
+        Some(Ok(persistent_keepalive)) => Some(persistent_keepalive),
+        Some(Err(_)) => {
+            return invalid(EditPeerTemplate {
+                persistent_keepalive_err: Some(lang::INVALID_PERSISTENT_KEEPALIVE.to_string()),
+                ..defaults
+            });
+        }
+        None => None,
+    };
+
+    // From checking the peer's addresses to recording them, the config stays locked, as it does
+    // when a peer is added, so no other peer can be given them in between.
+    let mut interface_config = wg.lock_config();
+    let addresses = allowed_ips
+        .as_ref()
+        .map(|allowed_ips| allocation::host_addresses(allowed_ips, &interface_config.networks()));
+    if let (Some(allowed_ips), Some(addresses)) = (&allowed_ips, &addresses) {
+        let available = allowed_ips.check_host_bits().and_then(|_| {
+            allocation::prune(&conn, &interface, &interface_config.peer_public_keys())?;
+            allocation::check_available(&conn, &interface, &public_key, addresses)
+        });
+        if let Err(allowed_ips_err) = available {
+            return invalid(EditPeerTemplate {
+                allowed_ips_err: Some(format!("{}", allowed_ips_err)),
+                ..defaults
+            });
+        }
+    }
+
+    let taken_over_routes = allowed_ips
+        .as_ref()
+        .map(|allowed_ips| interface_config.routes_taken_over(&public_key, allowed_ips))
+        .unwrap_or_default();
+    if !taken_over_routes.is_empty() && !edit_peer.take_over_routes {
+        let template = EditPeerTemplate {
+            name: edit_peer.name.unwrap_or_default(),
+            description: edit_peer.description.unwrap_or_default(),
+            owner: edit_peer.owner.unwrap_or_default(),
+            // The key isn't sent back to the browser, so it has to be entered again.
+            preshared_key_err: match preshared_key {
+                PresharedKeyEdit::Replace(_) => Some(lang::REENTER_PRESHARED_KEY.to_string()),
+                _ => None,
+            },
+            clear_preshared_key: edit_peer.clear_preshared_key,
+            allowed_ips: allowed_ips
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            endpoint: endpoint
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            persistent_keepalive: persistent_keepalive
+                .map(|secs| secs.to_string())
+                .unwrap_or_default(),
+            taken_over_routes: taken_over_routes.iter().map(ToString::to_string).collect(),
+            ..defaults
+        };
+        return Ok(Some(EditPeerResponse::Form(status::Custom(
+            Status::Conflict,
+            template,
+        ))));
+    }
+
+    let edit = PeerEdit {
+        preshared_key,
+        allowed_ips,
+        endpoint,
+        persistent_keepalive,
+    };
+    if let Err(err) = interface_config.edit_peer(&public_key, edit) {
+        return Ok(Some(EditPeerResponse::Form(status::Custom(
+            Status::Ok,
+            EditPeerTemplate {
+                status: Some(format!("{}: {}", lang::EDIT_PEER_ERROR, err)),
+                ..defaults
+            },
+        ))));
+    }
+
+    // The peer's addresses are already in its AllowedIPs, which keeps them from being handed out
+    // again even if they can't be recorded.
+    let now = rotation::now();
+    let recorded = match &addresses {
+        Some(addresses) => allocation::record(&conn, &interface, &public_key, addresses, now),
+        None => Ok(()),
+    };
+    drop(interface_config);
+
+    let redirect = Redirect::to(format!("/network/{}", interface));
+    let details = PeerDetails {
+        name: edit_peer.name.as_deref().unwrap_or_default().trim(),
+        description: edit_peer.description.as_deref().unwrap_or_default().trim(),
+        owner_id,
+    };
+    let user_id = auth::current_user_id(&mut cookies);
+    let saved = peer::save(&conn, &interface, &public_key, &details, user_id, now);
+    Ok(Some(EditPeerResponse::Saved(match (recorded, saved) {
+        (Err(_), _) => Flash::error(redirect, lang::PEER_ADDRESSES_ERROR),
+        (_, Err(_)) => Flash::error(redirect, lang::PEER_DETAILS_ERROR),
+        _ => Flash::success(
+            redirect,
+            format!("{} {}", lang::EDIT_PEER_SUCCESS, public_key),
+        ),
+    })))
+}
+
+#[derive(Template)]
+#[template(path = "peers/remove.html")]
+pub struct RemovePeerTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    public_key: String,
+    // The public key as it appears in URLs, where base64's slashes have to be escaped.
+    public_key_param: String,
+    allowed_ips: String,
+}
+
+// Asks for confirmation, naming the peer and the routes that go with it.
+#[get("/<interface>/<public_key>/remove")]
+pub fn remove(
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+) -> Result<Option<RemovePeerTemplate>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+    let parsed_public_key = match public_key.parse::<PublicKey>() {
+        Ok(parsed_public_key) => parsed_public_key,
+        Err(_) => return Ok(None),
+    };
+    let allowed_ips = match wg.peer(&parsed_public_key, |peer| peer.allowed_ips.to_string())? {
+        Some(allowed_ips) => allowed_ips,
+        None => return Ok(None),
+    };
+
+    Ok(Some(RemovePeerTemplate {
+        interfaces: interfaces.names(),
+        public_key_param: Uri::percent_encode(&public_key).into_owned(),
+        public_key,
+        allowed_ips,
+        interface,
+    }))
+}
+
+// The peer's addresses go back to the pool and its key rotation is stopped. Static reservations
+// are kept, since they were made by hand and may be meant for the peer's replacement.
+#[post("/<interface>/<public_key>/remove")]
+pub fn post_remove(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    provisioned_clients: State<ProvisionedClients>,
+    interface: String,
+    public_key: String,
+) -> Result<Option<Flash<Redirect>>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+    let public_key = match public_key.parse::<PublicKey>() {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(None),
+    };
+    if wg.peer(&public_key, |_| ())?.is_none() {
+        return Ok(None);
+    }
+    let redirect = Redirect::to(format!("/network/{}", interface));
+
+    if wg.remove_peer(&public_key).is_err() {
+        return Ok(Some(Flash::error(redirect, lang::REMOVE_PEER_ERROR)));
+    }
+    provisioned_clients.forget(&interface, &public_key);
+
+    let cleanup = allocation::release(&conn, &interface, &public_key)
+        .and_then(|_| peer::remove(&conn, &interface, &public_key))
+        .and_then(|_| rotation::remove_policy(&conn, &interface, &public_key))
+        .and_then(|_| rotation::cancel_pending(&conn, &interface, &public_key, rotation::now()));
+    Ok(Some(match cleanup {
+        Ok(_) => Flash::success(
+            redirect,
+            format!("{} {}", lang::REMOVE_PEER_SUCCESS, public_key),
+        ),
+        Err(_) => Flash::error(redirect, lang::REMOVE_PEER_CLEANUP_ERROR),
+    }))
 }
 
 #[cfg(test)]
@@ -136,50 +1091,54 @@ mod tests {
     use crate::config::peer::AllowedIps;
     use crate::config::{PresharedKey, PublicKey};
     use crate::db::make_rocket_database_config;
+    use crate::lang;
     use crate::launchpad;
-    use crate::states::WgState;
-    use failure;
+    use crate::states::{Interfaces, WgState};
     use failure::format_err;
     use rocket::config::{Config, Environment};
     use rocket::http::uri::Uri;
     use rocket::http::{ContentType, Status};
     use rocket::local::Client;
-    use rocket::Rocket;
     use std::net::SocketAddr;
-    use std::path::PathBuf;
     use std::str::FromStr;
+    use std::sync::Arc;
     use wireguard_uapi::{get, DeviceInterface, WgSocket};
 
-    fn get_test_rocket(db_path_buf: PathBuf) -> Result<Rocket, failure::Error> {
-        let interface_config = crate::config::Config {
-            name: "wgtest".to_owned(),
-            interface: crate::config::Interface::new()?,
-            peers: vec![],
-        };
-        let wgstate = WgState::init(interface_config)?;
+    // A client for a server with a fresh database and interface config, which are deleted along
+    // with the returned files. The wgtest device is shared by every test, so each test should use
+    // its own keys and addresses.
+    fn test_client() -> Result<(Client, mktemp::Temp, mktemp::Temp), failure::Error> {
+        let db_file = mktemp::Temp::new_file()?;
+        let interface_config_file = mktemp::Temp::new_file()?;
+
+        let interface_config = crate::config::Config::new("wgtest".to_owned())?;
+        let wgstate = WgState::init(interface_config, interface_config_file.to_path_buf())?;
         wgstate.apply_config()?;
+        let mut interfaces = Interfaces::new();
+        interfaces.insert(Arc::new(wgstate));
 
-        let db_path = db_path_buf
+        let db_path = db_file
+            .to_path_buf()
             .into_os_string()
             .into_string()
             .map_err(|os_string| format_err!("Failed to convert OsString: {:?}", os_string))?;
+        crate::db::run_migrations(&db_path, &mut std::io::sink())?;
         let config = Config::build(Environment::Development)
             .extra("databases", make_rocket_database_config(&db_path))
             .finalize()?;
 
-        Ok(launchpad::get_rocket(config, wgstate))
+        let client = Client::new(launchpad::get_rocket(config, interfaces))?;
+        Ok((client, interface_config_file, db_file))
     }
 
     #[test]
     fn add_peer_with_only_public_key() -> Result<(), failure::Error> {
-        let db_file = mktemp::Temp::new_file()?;
-        let rocket = get_test_rocket(db_file.to_path_buf())?;
-        let client = Client::new(rocket)?;
+        let (client, _interface_config_file, _db_file) = test_client()?;
 
         let public_key_input = "SwgTyJpz0og0NH/1YagZ2pWuaR06b0nlVUUo0WFdbAY=";
 
         let response = client
-            .post("/peers/add")
+            .post("/peers/wgtest/add")
             .header(ContentType::Form)
             .body(format!(
                 "public_key={}",
@@ -208,14 +1167,12 @@ mod tests {
 
     #[test]
     fn add_peer_with_whitespace_preshared_key() -> Result<(), failure::Error> {
-        let db_file = mktemp::Temp::new_file()?;
-        let rocket = get_test_rocket(db_file.to_path_buf())?;
-        let client = Client::new(rocket)?;
+        let (client, _interface_config_file, _db_file) = test_client()?;
 
         let public_key_input = "8h7VPAMcU7MsDEdq2lvjYhsHOHxx2sM5L4GM4xZT5hQ=";
 
         let response = client
-            .post("/peers/add")
+            .post("/peers/wgtest/add")
             .header(ContentType::Form)
             .body(format!(
                 "public_key={}&preshared_key={}",
@@ -240,11 +1197,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn out_of_range_keepalive_is_explained() -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let mut response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&persistent_keepalive=70000",
+                Uri::percent_encode("e9wytTITRXu/PNOCKKzTYRhYd/oBCJGj8E3srp3nYRU="),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response
+            .body_string()
+            .unwrap_or_default()
+            .contains("from 0 to 65535"));
+        Ok(())
+    }
+
+    #[test]
+    fn client_configs_need_a_logged_in_user() -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let peer_uri = format!(
+            "/peers/wgtest/{}",
+            Uri::percent_encode("O7ztSwQri3Pvrm7uPfP+B/I4Z9dDr5/KVyZDQEkOFV4=")
+        );
+        for page in &["client", "qr", "client.conf", "psk.conf"] {
+            let uri = format!("{}/{}", peer_uri, page);
+            let response = client.get(uri.as_str()).dispatch();
+            assert_eq!(response.status(), Status::Unauthorized, "{}", uri);
+        }
+
+        let response = client
+            .post("/peers/wgtest/provision")
+            .header(ContentType::Form)
+            .body("endpoint=vpn.example.com:51820&allowed_ips=0.0.0.0/0&dns=&preshared_key=true")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        Ok(())
+    }
+
     #[test]
     fn add_peer_all_fields() -> Result<(), failure::Error> {
-        let db_file = mktemp::Temp::new_file()?;
-        let rocket = get_test_rocket(db_file.to_path_buf())?;
-        let client = Client::new(rocket)?;
+        let (client, _interface_config_file, _db_file) = test_client()?;
 
         let public_key_input = "uQlHszU0iBTXja3UyIzt+lDVSPkDrmeeWWuEytox6jU=";
         let preshared_key_input = "CJizCOvSz4+S+PqG9XenDsBxRivLFPK3Hec9tQ3wEEU=";
@@ -253,7 +1251,7 @@ mod tests {
         let persistent_keepalive_input = "10";
 
         let response = client
-            .post("/peers/add")
+            .post("/peers/wgtest/add")
             .header(ContentType::Form)
             .body(format!(
                 "public_key={}&preshared_key={}&allowed_ips={}&endpoint={}&persistent_keepalive={}",
@@ -294,4 +1292,478 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn taken_over_routes_are_confirmed_without_echoing_the_preshared_key(
+    ) -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&allowed_ips={}",
+                Uri::percent_encode("0KzAuS8m+Z8/LJ+jPbHAWVGNq/kR3PsMT2yZ7sZ9PWk="),
+                Uri::percent_encode("10.77.0.0/24"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let preshared_key_input = "3K2Q3WJ1o+Xg5Ohv0Ge3kYHkJh7b8r0bIfvwcxmiNf8=";
+        let mut response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&preshared_key={}&allowed_ips={}",
+                Uri::percent_encode("6Mo+YmrbDuS0lCRFqjVbJLnsW9T4sPkr3JxyG6Rb4mY="),
+                Uri::percent_encode(preshared_key_input),
+                Uri::percent_encode("10.77.0.5/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let body = response.body_string().unwrap_or_default();
+        assert!(body.contains(lang::REENTER_PRESHARED_KEY));
+        assert!(!body.contains(preshared_key_input));
+        Ok(())
+    }
+
+    #[test]
+    fn add_peer_persists_to_interface_config() -> Result<(), failure::Error> {
+        let (client, interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "QnY+8+OUzXfz7d6ML2A3W2EChSm9cZgbP+Gmz0ygShI=";
+        let allowed_ips_input = "10.0.0.2/32";
+        let endpoint_input = "192.168.1.102:51820";
+
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&allowed_ips={}&endpoint={}",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode(allowed_ips_input),
+                endpoint_input,
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let config = crate::config::Config::read_from_file(
+            "wgtest".to_owned(),
+            &interface_config_file.to_path_buf(),
+        )?
+        .0;
+        let peer = config
+            .peers
+            .iter()
+            .find(|peer| peer.public_key.to_string() == public_key_input)
+            .expect("Newly added peer not written to the interface config");
+
+        assert_eq!(peer.allowed_ips.to_string(), allowed_ips_input);
+        assert_eq!(
+            peer.endpoint.as_ref().map(ToString::to_string),
+            Some(endpoint_input.to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_peer_with_hostname_endpoint() -> Result<(), failure::Error> {
+        let (client, interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "kLkiDmXh8mvzCYcBp6s5Qc9KhF96rYMrMa2tBk6Tmmo=";
+        let endpoint_input = "localhost:51820";
+
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&endpoint={}",
+                Uri::percent_encode(public_key_input),
+                endpoint_input,
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let expected_public_key = PublicKey::from_str(public_key_input)?;
+
+        let mut wg = WgSocket::connect()?;
+        let device = wg.get_device(DeviceInterface::from_name("wgtest"))?;
+        let peer = device
+            .peers
+            .iter()
+            .find(|peer| &peer.public_key == expected_public_key.as_bytes())
+            .expect("Newly added peer not found");
+        let endpoint = peer.endpoint.expect("Hostname endpoint was not resolved");
+        assert!(endpoint.ip().is_loopback());
+        assert_eq!(endpoint.port(), 51820);
+
+        // The hostname, rather than the address it resolved to, should be persisted.
+        let config = crate::config::Config::read_from_file(
+            "wgtest".to_owned(),
+            &interface_config_file.to_path_buf(),
+        )?
+        .0;
+        let config_peer = config
+            .peers
+            .iter()
+            .find(|peer| peer.public_key.to_string() == public_key_input)
+            .expect("Newly added peer not written to the interface config");
+        assert_eq!(
+            config_peer.endpoint.as_ref().map(ToString::to_string),
+            Some(endpoint_input.to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pages_never_show_a_preshared_key() -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "b2pO29yWQXbds7J49wWpOPMNW8jDWwe5QsGLG99knp4=";
+        let preshared_key_input = "6NIyHPaayE3vyLJMg0lwi7jnlnZV5WGr1WRr/5s2MWw=";
+        let peer_uri = format!("/peers/wgtest/{}", Uri::percent_encode(public_key_input));
+        let mut bodies = vec![];
+
+        let mut response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&preshared_key={}&allowed_ips={}&endpoint=nowhere",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode(preshared_key_input),
+                Uri::percent_encode("10.78.0.2/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        bodies.push(response.body_string());
+
+        let mut response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&preshared_key={}&allowed_ips={}",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode(preshared_key_input),
+                Uri::percent_encode("10.78.0.2/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        bodies.push(response.body_string());
+
+        let mut response = client
+            .post(format!("{}/edit", peer_uri))
+            .header(ContentType::Form)
+            .body(format!(
+                "preshared_key={}&allowed_ips=&endpoint=nowhere",
+                Uri::percent_encode(preshared_key_input),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        bodies.push(response.body_string());
+
+        for uri in &[
+            format!("{}/edit", peer_uri),
+            format!("{}/remove", peer_uri),
+            format!("{}/rotation", peer_uri),
+            "/network/wgtest".to_string(),
+            "/network/wgtest/config.json".to_string(),
+            "/network/wgtest/drift".to_string(),
+        ] {
+            let mut response = client.get(uri.as_str()).dispatch();
+            assert_eq!(response.status(), Status::Ok, "{}", uri);
+            bodies.push(response.body_string());
+        }
+
+        for body in bodies {
+            assert!(!body.unwrap_or_default().contains(preshared_key_input));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn edit_peer_clears_preshared_key() -> Result<(), failure::Error> {
+        let (client, interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "yLp26xLVFU0wDi1FSOO4saSKdiDDRpA40nUl3t6YHj0=";
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&preshared_key={}&allowed_ips={}&persistent_keepalive=25",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode("6cKD6kUxWdgXyGMzg6Ea/63n+4hGtDuPaJDG8nLmQWs="),
+                Uri::percent_encode("10.79.0.2/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let edit_uri = format!(
+            "/peers/wgtest/{}/edit",
+            Uri::percent_encode(public_key_input)
+        );
+        let mut response = client.get(edit_uri.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.body_string().unwrap_or_default();
+        assert!(body.contains("value=\"10.79.0.2/32\""));
+        assert!(body.contains("value=\"25\""));
+
+        // The keepalive is left empty, so it's cleared along with the key.
+        let response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body(format!(
+                "name=Alice%27s+laptop&preshared_key=&clear_preshared_key=on&allowed_ips={}&endpoint=&persistent_keepalive=",
+                Uri::percent_encode("10.79.0.3/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let edited_public_key = PublicKey::from_str(public_key_input)?;
+        let mut wg = WgSocket::connect()?;
+        let device = wg.get_device(DeviceInterface::from_name("wgtest"))?;
+        let peer = device
+            .peers
+            .iter()
+            .find(|peer| &peer.public_key == edited_public_key.as_bytes())
+            .expect("Edited peer not found");
+        assert_eq!(peer.preshared_key, [0u8; 32]);
+        assert_eq!(peer.persistent_keepalive_interval, 0);
+        assert_eq!(
+            AllowedIps(peer.allowed_ips.iter().map(Into::into).collect()).to_string(),
+            "10.79.0.3/32"
+        );
+
+        let config = crate::config::Config::read_from_file(
+            "wgtest".to_owned(),
+            &interface_config_file.to_path_buf(),
+        )?
+        .0;
+        let config_peer = &config.peers[0];
+        assert!(config_peer.preshared_key.is_none());
+        assert!(config_peer.persistent_keepalive.is_none());
+        assert_eq!(config_peer.allowed_ips.to_string(), "10.79.0.3/32");
+
+        let mut response = client.get("/network/wgtest").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response
+            .body_string()
+            .unwrap_or_default()
+            .contains("Alice&#x27;s laptop"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn edit_peer_keeps_routes_unless_taken_over() -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let first_public_key = "jc3Omg1aYEwwI68pTPHeIxRf1EesMxC+9X8eO7nWJW0=";
+        let second_public_key = "UwPYy429Knaur53Siqjfx8NIhxE8OSbj7GTs5uPPZpU=";
+        for (public_key, allowed_ips) in &[
+            (first_public_key, "10.81.0.2/32"),
+            (second_public_key, "10.81.0.3/32"),
+        ] {
+            let response = client
+                .post("/peers/wgtest/add")
+                .header(ContentType::Form)
+                .body(format!(
+                    "public_key={}&allowed_ips={}",
+                    Uri::percent_encode(public_key),
+                    Uri::percent_encode(allowed_ips),
+                ))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        let allowed_ips_of = |public_key: &str| -> Result<String, failure::Error> {
+            let public_key = PublicKey::from_str(public_key)?;
+            let mut wg = WgSocket::connect()?;
+            let device = wg.get_device(DeviceInterface::from_name("wgtest"))?;
+            let peer = device
+                .peers
+                .iter()
+                .find(|peer| &peer.public_key == public_key.as_bytes())
+                .expect("Peer not found");
+            Ok(AllowedIps(peer.allowed_ips.iter().map(Into::into).collect()).to_string())
+        };
+        let edit_uri = format!(
+            "/peers/wgtest/{}/edit",
+            Uri::percent_encode(second_public_key)
+        );
+
+        // An empty field leaves the routes alone.
+        let response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body("allowed_ips=&persistent_keepalive=")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(allowed_ips_of(second_public_key)?, "10.81.0.3/32");
+
+        let allowed_ips_input = Uri::percent_encode("10.81.0.2/32, 10.81.0.3/32");
+        let mut response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body(format!("allowed_ips={}", allowed_ips_input))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        assert!(response
+            .body_string()
+            .unwrap_or_default()
+            .contains("take_over_routes"));
+        assert_eq!(allowed_ips_of(first_public_key)?, "10.81.0.2/32");
+
+        let response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body(format!(
+                "allowed_ips={}&take_over_routes=on",
+                allowed_ips_input
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(allowed_ips_of(first_public_key)?, "");
+        assert_eq!(
+            allowed_ips_of(second_public_key)?,
+            "10.81.0.2/32, 10.81.0.3/32"
+        );
+
+        let response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body("allowed_ips=&clear_allowed_ips=on")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(allowed_ips_of(second_public_key)?, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn edit_peer_keeps_endpoint_when_left_empty() -> Result<(), failure::Error> {
+        let (client, interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "WGUFuXJvbvi+neWmB85nKCIV6/2yT3aST8GTO4byR5g=";
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&allowed_ips={}&endpoint={}",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode("10.83.0.2/32"),
+                Uri::percent_encode("192.0.2.1:51820"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let edit_uri = format!(
+            "/peers/wgtest/{}/edit",
+            Uri::percent_encode(public_key_input)
+        );
+        let response = client
+            .post(edit_uri.as_str())
+            .header(ContentType::Form)
+            .body("allowed_ips=&endpoint=&persistent_keepalive=25")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let edited_public_key = PublicKey::from_str(public_key_input)?;
+        let mut wg = WgSocket::connect()?;
+        let device = wg.get_device(DeviceInterface::from_name("wgtest"))?;
+        let peer = device
+            .peers
+            .iter()
+            .find(|peer| &peer.public_key == edited_public_key.as_bytes())
+            .expect("Edited peer not found");
+        assert_eq!(peer.endpoint, Some("192.0.2.1:51820".parse()?));
+        assert_eq!(peer.persistent_keepalive_interval, 25);
+
+        // The config still agrees with the device.
+        let config = crate::config::Config::read_from_file(
+            "wgtest".to_owned(),
+            &interface_config_file.to_path_buf(),
+        )?
+        .0;
+        let config_peer = &config.peers[0];
+        assert_eq!(
+            config_peer.endpoint.as_ref().map(ToString::to_string),
+            Some("192.0.2.1:51820".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_peer_from_device_and_config() -> Result<(), failure::Error> {
+        let (client, interface_config_file, _db_file) = test_client()?;
+
+        let public_key_input = "YCU1VyC13PfxVa+gn1vPyES6Vo7LUdlo4ZlkFeCUtw0=";
+        let response = client
+            .post("/peers/wgtest/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}&allowed_ips={}",
+                Uri::percent_encode(public_key_input),
+                Uri::percent_encode("10.80.0.2/32"),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let remove_uri = format!(
+            "/peers/wgtest/{}/remove",
+            Uri::percent_encode(public_key_input)
+        );
+        let mut response = client.get(remove_uri.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.body_string().unwrap_or_default();
+        assert!(body.contains(public_key_input));
+        assert!(body.contains("10.80.0.2/32"));
+
+        let response = client.post(remove_uri.as_str()).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let removed_public_key = PublicKey::from_str(public_key_input)?;
+        let mut wg = WgSocket::connect()?;
+        let device = wg.get_device(DeviceInterface::from_name("wgtest"))?;
+        assert!(device
+            .peers
+            .iter()
+            .all(|peer| &peer.public_key != removed_public_key.as_bytes()));
+
+        let config = crate::config::Config::read_from_file(
+            "wgtest".to_owned(),
+            &interface_config_file.to_path_buf(),
+        )?
+        .0;
+        assert!(config.peers.is_empty());
+
+        // Once it's gone there's nothing left to remove.
+        let response = client.post(remove_uri.as_str()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_interface_is_not_found() -> Result<(), failure::Error> {
+        let (client, _interface_config_file, _db_file) = test_client()?;
+
+        let response = client
+            .post("/peers/wgmissing/add")
+            .header(ContentType::Form)
+            .body(format!(
+                "public_key={}",
+                Uri::percent_encode("GrYjV2AGWdoDN8tG3wYqFqkwzc3hIQ0Mk2CSHVG2k2Q="),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.get("/network/wgmissing").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,327 @@
+use crate::config::PublicKey;
+use crate::controllers::auth::LoggedInUser;
+use crate::controllers::peers::ClientConfigResponse;
+use crate::fairings::Database;
+use crate::lang;
+use crate::models::rotation;
+use crate::models::rotation::NewRotationPolicy;
+use crate::models::{Rotation, RotationPolicy};
+use crate::states::Interfaces;
+use askama::Template;
+use diesel::SqliteConnection;
+use rocket::http::uri::Uri;
+use rocket::http::{RawStr, Status};
+use rocket::request::{FlashMessage, Form, Request};
+use rocket::response::{self, status, Flash, Redirect, Responder};
+use rocket::State;
+use rocket::{get, post, FromForm};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+// Well beyond any sensible interval, and it keeps the times worked out from it within range.
+const MAX_INTERVAL_DAYS: i64 = 10 * 365;
+
+// Picked to meet the usual 90 day compliance requirement, with a week to get the new key onto the
+// client.
+const DEFAULT_INTERVAL_DAYS: i64 = 90;
+const DEFAULT_CUTOVER_DELAY_DAYS: i64 = 7;
+
+pub struct HistoryEntry {
+    created: String,
+    cutover: String,
+    status: String,
+    applied: String,
+}
+
+#[derive(Template)]
+#[template(path = "peers/rotation.html")]
+pub struct RotationTemplate {
+    interface: String,
+    interfaces: Vec<String>,
+    status: Option<String>,
+    public_key: String,
+    // The public key as it appears in URLs, where base64's slashes have to be escaped.
+    public_key_param: String,
+    enabled: bool,
+    interval_days: i64,
+    cutover_delay_days: i64,
+    next_rotation: Option<String>,
+    pending_cutover: Option<String>,
+    history: Vec<HistoryEntry>,
+}
+
+impl RotationTemplate {
+    fn new(
+        conn: &SqliteConnection,
+        interfaces: &Interfaces,
+        interface: String,
+        public_key: String,
+        parsed_public_key: &PublicKey,
+    ) -> Result<Self, failure::Error> {
+        let policy = RotationPolicy::find(conn, &interface, parsed_public_key)?;
+        let pending = Rotation::pending_for(conn, &interface, parsed_public_key)?;
+        let history = Rotation::history(conn, &interface, parsed_public_key)?
+            .into_iter()
+            .map(|entry| HistoryEntry {
+                created: rotation::format_time(entry.created_at),
+                cutover: rotation::format_time(entry.cutover_at),
+                applied: entry
+                    .applied_at
+                    .map(rotation::format_time)
+                    .unwrap_or_default(),
+                status: entry.status,
+            })
+            .collect();
+
+        Ok(Self {
+            interfaces: interfaces.names(),
+            status: None,
+            public_key_param: Uri::percent_encode(&public_key).into_owned(),
+            public_key,
+            enabled: policy.is_some(),
+            interval_days: policy.as_ref().map_or(DEFAULT_INTERVAL_DAYS, |policy| {
+                policy.interval_secs / DAY_SECS
+            }),
+            cutover_delay_days: policy
+                .as_ref()
+                .map_or(DEFAULT_CUTOVER_DELAY_DAYS, |policy| {
+                    policy.cutover_delay_secs / DAY_SECS
+                }),
+            next_rotation: policy.map(|policy| rotation::format_time(policy.next_rotation_at)),
+            pending_cutover: pending.map(|pending| rotation::format_time(pending.cutover_at)),
+            history,
+            interface,
+        })
+    }
+}
+
+#[get("/<interface>/<public_key>/rotation")]
+pub fn rotation(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+    flash: Option<FlashMessage>,
+) -> Result<Option<RotationTemplate>, failure::Error> {
+    let parsed_public_key = match public_key.parse::<PublicKey>() {
+        Ok(parsed_public_key) => parsed_public_key,
+        Err(_) => return Ok(None),
+    };
+    match interfaces.get(&interface) {
+        Some(wg) if wg.has_peer(&parsed_public_key) => {}
+        _ => return Ok(None),
+    }
+
+    let template = RotationTemplate::new(
+        &conn,
+        &interfaces,
+        interface,
+        public_key,
+        &parsed_public_key,
+    )?;
+    Ok(Some(RotationTemplate {
+        status: flash.map(|flash| flash.msg().to_string()),
+        ..template
+    }))
+}
+
+#[derive(FromForm)]
+pub struct SetRotation<'v> {
+    enabled: bool,
+    interval_days: Result<i64, &'v RawStr>,
+    cutover_delay_days: Result<i64, &'v RawStr>,
+    // Otherwise the first rotation happens a full interval from now.
+    rotate_now: bool,
+}
+
+// The policy the form asks for, or None if its interval and cutover delay are out of range.
+fn new_policy<'a>(
+    interface: &'a str,
+    public_key: &'a str,
+    form: &SetRotation,
+    now: i64,
+) -> Option<NewRotationPolicy<'a>> {
+    let interval_days = *form.interval_days.as_ref().ok()?;
+    let cutover_delay_days = *form.cutover_delay_days.as_ref().ok()?;
+    if interval_days < 1
+        || interval_days > MAX_INTERVAL_DAYS
+        || cutover_delay_days < 0
+        || cutover_delay_days >= interval_days
+    {
+        return None;
+    }
+
+    let interval_secs = interval_days.checked_mul(DAY_SECS)?;
+    Some(NewRotationPolicy {
+        interface,
+        public_key,
+        interval_secs,
+        cutover_delay_secs: cutover_delay_days.checked_mul(DAY_SECS)?,
+        next_rotation_at: if form.rotate_now {
+            now
+        } else {
+            now.checked_add(interval_secs)?
+        },
+    })
+}
+
+pub enum RotationResponse {
+    Saved(Flash<Redirect>),
+    Form(status::Custom<RotationTemplate>),
+}
+
+impl<'r> Responder<'r> for RotationResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            RotationResponse::Saved(redirect) => redirect.respond_to(request),
+            RotationResponse::Form(template) => template.respond_to(request),
+        }
+    }
+}
+
+#[post("/<interface>/<public_key>/rotation", data = "<form>")]
+pub fn post_rotation(
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+    form: Form<SetRotation>,
+) -> Result<Option<RotationResponse>, failure::Error> {
+    let parsed_public_key = match public_key.parse::<PublicKey>() {
+        Ok(parsed_public_key) => parsed_public_key,
+        Err(_) => return Ok(None),
+    };
+    let wg = match interfaces.get(&interface) {
+        Some(wg) if wg.has_peer(&parsed_public_key) => wg,
+        _ => return Ok(None),
+    };
+
+    let redirect = Redirect::to(format!(
+        "/peers/{}/{}/rotation",
+        interface,
+        Uri::percent_encode(&public_key)
+    ));
+
+    if !form.enabled {
+        return Ok(Some(RotationResponse::Saved(
+            match rotation::remove_policy(&conn, &interface, &parsed_public_key) {
+                Ok(_) => Flash::success(redirect, lang::ROTATION_DISABLED),
+                Err(_) => Flash::error(redirect, lang::ROTATION_ERROR),
+            },
+        )));
+    }
+
+    // The server can't switch to a new key that has to be written back to wherever the current
+    // one is read from, so the client would be left with a key the server never uses.
+    let public_key_string = parsed_public_key.to_string();
+    let policy = match new_policy(&interface, &public_key_string, &form, rotation::now()) {
+        _ if wg.has_external_preshared_key(&parsed_public_key) => Err(lang::ROTATION_EXTERNAL_KEY),
+        Some(policy) => Ok(policy),
+        None => Err(lang::ROTATION_INVALID),
+    };
+    let policy = match policy {
+        Ok(policy) => policy,
+        Err(invalid) => {
+            let template = RotationTemplate::new(
+                &conn,
+                &interfaces,
+                interface.clone(),
+                public_key,
+                &parsed_public_key,
+            )?;
+            return Ok(Some(RotationResponse::Form(status::Custom(
+                Status::BadRequest,
+                RotationTemplate {
+                    status: Some(invalid.to_string()),
+                    ..template
+                },
+            ))));
+        }
+    };
+
+    Ok(Some(RotationResponse::Saved(
+        match rotation::set_policy(&conn, &policy) {
+            Ok(_) => Flash::success(redirect, lang::ROTATION_SAVED),
+            Err(_) => Flash::error(redirect, lang::ROTATION_ERROR),
+        },
+    )))
+}
+
+// What the client needs to change in its own config. The rest of the server's [Peer] section is up
+// to the client, so only the key is given. The key is a secret, so it's only given to a logged in
+// user.
+#[get("/<interface>/<public_key>/psk.conf")]
+pub fn psk_conf(
+    _user: LoggedInUser,
+    conn: Database,
+    interfaces: State<Interfaces>,
+    interface: String,
+    public_key: String,
+) -> Result<Option<ClientConfigResponse>, failure::Error> {
+    let wg = match interfaces.get(&interface) {
+        Some(wg) => wg,
+        None => return Ok(None),
+    };
+    let public_key = match public_key.parse::<PublicKey>() {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(None),
+    };
+    let pending = match Rotation::pending_for(&conn, &interface, &public_key)? {
+        Some(pending) => pending,
+        None => return Ok(None),
+    };
+    let preshared_key = match pending.preshared_key()? {
+        Some(preshared_key) => preshared_key,
+        None => return Ok(None),
+    };
+
+    let body = format!(
+        "# The new preshared key for {}, which the server switches to at {}.\n\
+         # Set it as the PresharedKey of the server's [Peer] section before then.\n\
+         [Peer]\n\
+         PublicKey = {}\n\
+         PresharedKey = {}\n",
+        interface,
+        rotation::format_time(pending.cutover_at),
+        wg.public_key(),
+        preshared_key.expose_secret()
+    );
+    Ok(Some(ClientConfigResponse::download(
+        format!("{}-psk.conf", interface),
+        body,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(interval_days: i64, cutover_delay_days: i64, rotate_now: bool) -> SetRotation<'static> {
+        SetRotation {
+            enabled: true,
+            interval_days: Ok(interval_days),
+            cutover_delay_days: Ok(cutover_delay_days),
+            rotate_now,
+        }
+    }
+
+    #[test]
+    fn intervals_are_kept_within_range() {
+        let now = 1_600_000_000;
+
+        let policy = new_policy("wg0", "key", &form(90, 7, false), now).unwrap();
+        assert_eq!(policy.interval_secs, 90 * DAY_SECS);
+        assert_eq!(policy.cutover_delay_secs, 7 * DAY_SECS);
+        assert_eq!(policy.next_rotation_at, now + 90 * DAY_SECS);
+
+        let policy = new_policy("wg0", "key", &form(MAX_INTERVAL_DAYS, 7, true), now).unwrap();
+        assert_eq!(policy.next_rotation_at, now);
+
+        assert!(new_policy("wg0", "key", &form(0, 0, false), now).is_none());
+        assert!(new_policy("wg0", "key", &form(30, 30, false), now).is_none());
+        assert!(new_policy("wg0", "key", &form(30, -1, false), now).is_none());
+        assert!(new_policy("wg0", "key", &form(MAX_INTERVAL_DAYS + 1, 7, false), now).is_none());
+        assert!(new_policy("wg0", "key", &form(i64::MAX, 7, false), now).is_none());
+        assert!(new_policy("wg0", "key", &form(90, 7, false), i64::MAX).is_none());
+    }
+}
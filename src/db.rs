@@ -1,8 +1,11 @@
+use crate::permissions;
 use diesel::connection::Connection;
 use diesel::SqliteConnection;
 use failure::{format_err, Error};
 use rocket::config::Value;
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
 
 // It'd be better to do "use diesel_migrations::embed_migrations" to be in line with the Rust 2018
 // module changes, but something about this macro requires it to be imported using the old method
@@ -11,13 +14,17 @@ use std::collections::HashMap;
 // https://github.com/diesel-rs/diesel/issues/1894
 embed_migrations!();
 
-fn connect(path: &str) -> Result<SqliteConnection, Error> {
+pub fn connect(path: &str) -> Result<SqliteConnection, Error> {
     SqliteConnection::establish(&path).map_err(|_| format_err!("Unable to open db file: {}", path))
 }
 
-pub fn run_migrations(path: &str) -> Result<(), Error> {
+// Each migration that's run is named on out.
+pub fn run_migrations(path: &str, out: &mut impl Write) -> Result<(), Error> {
+    // SQLite would otherwise create the database using the umask, and it holds password hashes.
+    permissions::create_private_file_if_missing(Path::new(path))?;
+    permissions::restrict_sqlite_sidecars(Path::new(path))?;
     let db_conn = connect(path)?;
-    embedded_migrations::run_with_output(&db_conn, &mut std::io::stdout())?;
+    embedded_migrations::run_with_output(&db_conn, out)?;
     Ok(())
 }
 
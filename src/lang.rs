@@ -1,2 +1,31 @@
-pub const ADD_PEER_SUCCESS: &'static str = "Successfully added peer";
-pub const ADD_PEER_ERROR: &'static str = "Unable to add the new peer. Please try again later.";
+pub const ADD_PEER_SUCCESS: &str = "Successfully added peer";
+pub const ADD_PEER_ERROR: &str = "Unable to add the new peer. Please try again later.";
+pub const REENTER_PRESHARED_KEY: &str = "Enter the preshared key again to save the peer.";
+pub const PEER_DETAILS_ERROR: &str =
+    "The peer was saved, but its name and description couldn't be.";
+pub const PEER_ADDRESSES_ERROR: &str =
+    "The peer was saved, but its addresses couldn't be recorded.";
+pub const INVALID_PERSISTENT_KEEPALIVE: &str =
+    "The persistent keepalive must be a whole number of seconds from 0 to 65535.";
+pub const UNKNOWN_USER: &str = "There's no user with the email address";
+pub const PROVISION_ERROR: &str = "Unable to add the new client";
+pub const EDIT_PEER_SUCCESS: &str = "Successfully updated peer";
+pub const EDIT_PEER_ERROR: &str = "Unable to update the peer";
+pub const REMOVE_PEER_SUCCESS: &str = "Successfully removed peer";
+pub const REMOVE_PEER_ERROR: &str = "Unable to remove the peer. Please try again later.";
+pub const REMOVE_PEER_CLEANUP_ERROR: &str =
+    "The peer was removed, but its addresses and key rotation couldn't all be cleared up.";
+pub const RECONCILE_SUCCESS: &str = "The device now matches the config.";
+pub const RECONCILE_ERROR: &str = "Unable to reconcile the device. Please try again later.";
+pub const ROTATION_SAVED: &str = "The rotation policy was saved.";
+pub const ROTATION_DISABLED: &str = "Rotation is now off for this peer.";
+pub const ROTATION_INVALID: &str =
+    "The interval must be from 1 to 3650 days, and the cutover delay shorter than the interval.";
+pub const ROTATION_EXTERNAL_KEY: &str =
+    "This peer's preshared key is read from a file or command, so it can't be rotated here.";
+pub const ROTATION_ERROR: &str = "Unable to save the rotation policy. Please try again later.";
+pub const RESERVATION_SAVED: &str = "The reservation was saved.";
+pub const RESERVATION_REMOVED: &str = "The reservation was removed.";
+pub const RESERVATION_OUTSIDE: &str =
+    "Only addresses within the interface's networks can be reserved.";
+pub const RESERVATION_ERROR: &str = "Unable to save the reservation. Please try again later.";
@@ -8,17 +8,22 @@ use rocket::config::{Config, ConfigError, Environment};
 use rocket::{routes, Rocket};
 
 pub fn get_config_from_args(args: &cli::Args) -> Result<Config, ConfigError> {
-    Config::build(Environment::active()?)
+    let mut config = Config::build(Environment::active()?)
         .address(&args.bind_ip)
         .port(args.port)
-        .extra("databases", db::make_rocket_database_config(&args.db_path))
-        .finalize()
+        .extra("databases", db::make_rocket_database_config(&args.db_path));
+    if let Some(public_host) = &args.public_host {
+        config = config.extra("public_host", public_host.as_str());
+    }
+    config.finalize()
 }
 
-pub fn get_rocket(config: Config, wgstate: states::WgState) -> Rocket {
+pub fn get_rocket(config: Config, interfaces: states::Interfaces) -> Rocket {
     rocket::custom(config)
         .attach(fairings::Database::fairing())
-        .manage(wgstate)
+        .attach(states::PublicHost::fairing())
+        .manage(interfaces)
+        .manage(states::ProvisionedClients::new())
         .mount("/", asset::Asset)
         .mount("/", routes![controllers::index::index])
         .mount(
@@ -29,10 +34,38 @@ pub fn get_rocket(config: Config, wgstate: states::WgState) -> Rocket {
                 controllers::auth::logout,
             ],
         )
-        .mount("/network", routes![controllers::network::index,])
+        .mount(
+            "/network",
+            routes![
+                controllers::network::default,
+                controllers::network::index,
+                controllers::network::drift,
+                controllers::network::post_reconcile,
+                controllers::network::config_json,
+                controllers::network::lookup,
+                controllers::addresses::addresses,
+                controllers::addresses::post_reserve,
+                controllers::addresses::post_unreserve,
+            ],
+        )
         .mount(
             "/peers",
-            routes![controllers::peers::add, controllers::peers::post_add],
+            routes![
+                controllers::peers::add,
+                controllers::peers::post_add,
+                controllers::peers::provision,
+                controllers::peers::post_provision,
+                controllers::peers::client,
+                controllers::peers::client_qr,
+                controllers::peers::client_conf,
+                controllers::peers::edit,
+                controllers::peers::post_edit,
+                controllers::peers::remove,
+                controllers::peers::post_remove,
+                controllers::rotation::rotation,
+                controllers::rotation::post_rotation,
+                controllers::rotation::psk_conf,
+            ],
         )
         .mount("/users", routes![controllers::users::create,])
 }
@@ -2,6 +2,10 @@
 #![feature(never_type)]
 
 use exitfailure::ExitFailure;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+use zeroize::Zeroizing;
 
 // https://github.com/diesel-rs/diesel/issues/1894#issuecomment-433178841
 #[macro_use]
@@ -18,27 +22,265 @@ mod fairings;
 mod lang;
 mod launchpad;
 mod models;
+mod permissions;
+mod qr;
 mod schema;
 mod states;
+mod tasks;
 mod utils;
 
 fn main() -> Result<(), ExitFailure> {
     let args = cli::Args::get_from_clap()?;
-    let interface_config =
-        config::Config::init_from_path(args.interface.clone(), &args.interface_config)?;
+
+    if let Some(command) = &args.command {
+        match command {
+            cli::Command::Check(interface) => check(interface)?,
+            cli::Command::Export {
+                interface,
+                format,
+                secrets,
+            } => export(interface, *format, *secrets)?,
+            cli::Command::Import { interface, format } => import(interface, *format)?,
+            cli::Command::GenKey => genkey()?,
+            cli::Command::PubKey => pubkey()?,
+            cli::Command::GenPsk => genpsk()?,
+            cli::Command::AllowedIps { networks, except } => {
+                println!("{}", networks.difference(except))
+            }
+        }
+        return Ok(());
+    }
+
+    if args.drift {
+        print_drift(&args)?;
+        return Ok(());
+    }
+
+    if let Some(address) = args.lookup {
+        print_lookup(&args, address)?;
+        return Ok(());
+    }
 
     let should_daemonize = !args.foreground && !cfg!(debug_assertions);
     if should_daemonize {
         println!("Daemonizing will be supported in a later release.")
     }
 
-    db::run_migrations(&args.db_path)?;
+    check_permissions(&args, std::path::Path::new(&args.db_path))?;
+    permissions::restrict_umask();
+    db::run_migrations(&args.db_path, &mut std::io::stdout())?;
+
+    // Must happen before any threads are spawned.
+    tasks::reload::block_sighup()?;
+
+    let mut interfaces = states::Interfaces::new();
+    let mut reloadable = vec![];
+    for interface in &args.interfaces {
+        check_permissions(&args, &interface.config_path)?;
+
+        let wgstate = if args.import {
+            states::WgState::import(interface.name.clone(), interface.config_path.clone())?
+        } else {
+            let (interface_config, diagnostics) =
+                config::Config::init_from_path(interface.name.clone(), &interface.config_path)?;
+            print_warnings(&diagnostics);
+            for key_file in interface_config.key_files() {
+                check_permissions(&args, key_file)?;
+            }
 
-    let wgstate = states::WgState::init(interface_config)?;
-    wgstate.apply_config()?;
+            let wgstate = states::WgState::init(interface_config, interface.config_path.clone())?;
+            print_messages(&wgstate.apply_config()?);
+            wgstate
+        };
+        let wgstate = Arc::new(wgstate);
+
+        if let Some(interval) = args.reresolve_interval {
+            tasks::reresolve::spawn(Arc::clone(&wgstate), interval);
+        }
+
+        tasks::rotation::spawn(Arc::clone(&wgstate), args.db_path.clone());
+
+        if args.watch_config {
+            tasks::reload::spawn_watch(
+                Arc::clone(&wgstate),
+                &interface.config_path,
+                args.allow_insecure_permissions,
+            )?;
+        }
+
+        reloadable.push(Arc::clone(&wgstate));
+        interfaces.insert(wgstate);
+    }
+    tasks::reload::spawn_on_sighup(reloadable, args.allow_insecure_permissions);
 
     let config = launchpad::get_config_from_args(&args)?;
-    launchpad::get_rocket(config, wgstate).launch();
+    launchpad::get_rocket(config, interfaces).launch();
+
+    Ok(())
+}
+
+// Problems are reported as warnings instead when --allow-insecure-permissions is given.
+fn check_permissions(args: &cli::Args, path: &std::path::Path) -> Result<(), failure::Error> {
+    let mut warnings = vec![];
+    permissions::check_or_warn(path, args.allow_insecure_permissions, &mut warnings)?;
+    print_messages(&warnings);
+    Ok(())
+}
+
+// Warnings don't stop the config from being used, so they're only shown.
+fn print_warnings(diagnostics: &config::Diagnostics) {
+    if !diagnostics.is_empty() {
+        eprintln!("{}", diagnostics);
+    }
+}
+
+fn print_messages(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+fn read_config(interface: &cli::InterfaceArgs) -> Result<config::Config, failure::Error> {
+    let (interface_config, diagnostics) =
+        config::Config::read_from_file(interface.name.clone(), &interface.config_path)?;
+    print_warnings(&diagnostics);
+    Ok(interface_config)
+}
+
+fn print_drift(args: &cli::Args) -> Result<(), failure::Error> {
+    let mut drifted = 0;
+
+    for interface in &args.interfaces {
+        let interface_config = read_config(interface)?;
+        let wgstate = states::WgState::init(interface_config, interface.config_path.clone())?;
+
+        let drift = wgstate.drift()?;
+        if !drift.is_empty() {
+            drifted += 1;
+        }
+        print!("{}:\n{}", interface.name, drift);
+    }
+
+    if drifted > 0 {
+        return Err(failure::format_err!(
+            "{} interface(s) differ from their config",
+            drifted
+        ));
+    }
+    Ok(())
+}
 
+// The device can differ from the config if peers were changed outside of this server, so both are
+// shown.
+fn print_lookup(args: &cli::Args, address: std::net::IpAddr) -> Result<(), failure::Error> {
+    for interface in &args.interfaces {
+        let interface_config = read_config(interface)?;
+        let wgstate = states::WgState::init(interface_config, interface.config_path.clone())?;
+
+        println!("{}:", interface.name);
+        println!("On the device: {}", wgstate.lookup(address)?);
+        println!("In the config: {}", wgstate.lookup_in_config(address));
+    }
     Ok(())
 }
+
+// Reads the config the same way the server does, without touching the device or the database.
+fn check(interface: &cli::InterfaceArgs) -> Result<(), failure::Error> {
+    let source = std::fs::read_to_string(&interface.config_path)?;
+    let (interface_config, mut diagnostics) = config::Config::parse_with_diagnostics(
+        interface.name.clone(),
+        &source,
+        Some(&interface.config_path),
+    );
+
+    if let Some(interface_config) = &interface_config {
+        diagnostics.items.extend(interface_config.lint());
+    }
+    diagnostics
+        .items
+        .sort_by_key(|item| (item.line_num, item.column));
+
+    if diagnostics.has_errors() {
+        return Err(diagnostics.into());
+    }
+    if !diagnostics.is_empty() {
+        println!("{}", diagnostics);
+    }
+    println!("{} is valid", interface.config_path.display());
+    Ok(())
+}
+
+fn export(
+    interface: &cli::InterfaceArgs,
+    format: config::document::Format,
+    secrets: config::document::Secrets,
+) -> Result<(), failure::Error> {
+    let interface_config = read_config(interface)?;
+    let document = config::ConfigDocument::from_config(&interface_config, secrets);
+    print!("{}", format.serialize(&document)?);
+    Ok(())
+}
+
+// An existing config file is updated in place rather than replaced, so comments are kept for
+// everything that didn't change. A running server picks the change up on its next reload.
+fn import(
+    interface: &cli::InterfaceArgs,
+    format: config::document::Format,
+) -> Result<(), failure::Error> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let (imported, diagnostics) = format
+        .deserialize(&input)?
+        .into_config(interface.name.clone())?;
+    print_warnings(&diagnostics);
+
+    let mut interface_config = if interface.config_path.exists() {
+        let mut existing = read_config(interface)?;
+        existing.interface = imported.interface;
+        existing.peers = imported.peers;
+        existing
+    } else {
+        imported
+    };
+    interface_config.write_to_path(&interface.config_path)?;
+
+    println!(
+        "Imported {} peer(s) into {}",
+        interface_config.peers.len(),
+        interface.config_path.display()
+    );
+    Ok(())
+}
+
+// genkey, pubkey and genpsk print exactly what wg(8) does, so they can be used in its place.
+fn genkey() -> Result<(), failure::Error> {
+    warn_if_stdout_is_world_accessible();
+    println!("{}", config::PrivateKey::new()?.expose_secret());
+    Ok(())
+}
+
+fn pubkey() -> Result<(), failure::Error> {
+    let mut input = Zeroizing::new(String::new());
+    std::io::stdin().read_to_string(&mut input)?;
+    let private_key: config::PrivateKey = input.trim().parse()?;
+    println!("{}", private_key.public_key());
+    Ok(())
+}
+
+fn genpsk() -> Result<(), failure::Error> {
+    warn_if_stdout_is_world_accessible();
+    println!("{}", config::PresharedKey::new()?.expose_secret());
+    Ok(())
+}
+
+// The same warning wg gives when a new key is redirected to a file anyone can read.
+fn warn_if_stdout_is_world_accessible() {
+    if let Ok(metadata) = std::fs::metadata("/dev/stdout") {
+        if metadata.is_file() && metadata.mode() & 0o007 != 0 {
+            eprintln!(
+                "Warning: writing to world accessible file.\n\
+                 Consider setting the umask to 077 and trying again."
+            );
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::config::peer::{AllowedIp, AllowedIps};
+use crate::config::PublicKey;
+use crate::diesel;
+use crate::schema::{ip_allocations, ip_reservations};
+use diesel::prelude::*;
+use failure::Error;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+#[derive(diesel::Queryable)]
+pub struct Allocation {
+    pub id: i32,
+    pub interface: String,
+    pub address: String,
+    pub public_key: String,
+    pub created_at: i64,
+}
+
+#[derive(diesel::Insertable)]
+#[table_name = "ip_allocations"]
+struct NewAllocation<'a> {
+    interface: &'a str,
+    address: &'a str,
+    public_key: &'a str,
+    created_at: i64,
+}
+
+impl Allocation {
+    pub fn all(conn: &SqliteConnection, interface: &str) -> QueryResult<Vec<Self>> {
+        ip_allocations::table
+            .filter(ip_allocations::interface.eq(interface))
+            .order(ip_allocations::id)
+            .load(conn)
+    }
+
+    pub fn address(&self) -> Result<IpNet, Error> {
+        Ok(self.address.parse()?)
+    }
+}
+
+#[derive(diesel::Queryable)]
+pub struct Reservation {
+    pub id: i32,
+    pub interface: String,
+    pub network: String,
+    // Only set for a static reservation.
+    pub public_key: Option<String>,
+    pub description: String,
+}
+
+#[derive(diesel::Insertable)]
+#[table_name = "ip_reservations"]
+pub struct NewReservation<'a> {
+    pub interface: &'a str,
+    pub network: &'a str,
+    pub public_key: Option<&'a str>,
+    pub description: &'a str,
+}
+
+impl Reservation {
+    pub fn all(conn: &SqliteConnection, interface: &str) -> QueryResult<Vec<Self>> {
+        ip_reservations::table
+            .filter(ip_reservations::interface.eq(interface))
+            .order(ip_reservations::id)
+            .load(conn)
+    }
+
+    pub fn network(&self) -> Result<IpNet, Error> {
+        Ok(self.network.parse()?)
+    }
+}
+
+pub fn reserve(conn: &SqliteConnection, reservation: &NewReservation) -> QueryResult<()> {
+    diesel::insert_into(ip_reservations::table)
+        .values(reservation)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn unreserve(conn: &SqliteConnection, interface: &str, id: i32) -> QueryResult<()> {
+    diesel::delete(
+        ip_reservations::table
+            .filter(ip_reservations::interface.eq(interface))
+            .filter(ip_reservations::id.eq(id)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+// The addresses in a peer's AllowedIPs that are allocated to it: single addresses within one of the
+// interface's networks. Wider routes, such as to a network behind the peer, aren't tracked.
+pub fn host_addresses(allowed_ips: &AllowedIps, networks: &[IpNet]) -> Vec<IpNet> {
+    allowed_ips
+        .0
+        .iter()
+        .map(AllowedIp::net)
+        .filter(|net| {
+            net.prefix_len() == net.max_prefix_len()
+                && networks.iter().any(|network| network.contains(net))
+        })
+        .collect()
+}
+
+// Everything that can't be handed out to the peer, or to a new peer if there's no key yet: other
+// peers' allocations and static reservations, and reserved ranges.
+pub fn taken(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: Option<&PublicKey>,
+) -> Result<Vec<IpNet>, Error> {
+    let public_key = public_key.map(ToString::to_string);
+    let is_own = |other: &str| public_key.as_deref() == Some(other);
+
+    let mut taken = vec![];
+    for allocation in Allocation::all(conn, interface)? {
+        if !is_own(&allocation.public_key) {
+            taken.push(allocation.address()?);
+        }
+    }
+    for reservation in Reservation::all(conn, interface)? {
+        match &reservation.public_key {
+            Some(reserved_for) if is_own(reserved_for) => {}
+            _ => taken.push(reservation.network()?),
+        }
+    }
+    Ok(taken)
+}
+
+// A peer with static reservations is given those in place of the next free addresses.
+pub fn reserved_for(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+) -> Result<Vec<IpNet>, Error> {
+    ip_reservations::table
+        .filter(ip_reservations::interface.eq(interface))
+        .filter(ip_reservations::public_key.eq(public_key.to_string()))
+        .order(ip_reservations::id)
+        .load::<Reservation>(conn)?
+        .iter()
+        .map(Reservation::network)
+        .collect()
+}
+
+// Fails with the first of the addresses that's allocated or reserved for anything but this peer.
+pub fn check_available(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+    addresses: &[IpNet],
+) -> Result<(), Error> {
+    let public_key = public_key.to_string();
+    let overlapping = |net: &IpNet| {
+        addresses
+            .iter()
+            .find(|address| address.contains(net) || net.contains(*address))
+            .map(IpNet::addr)
+    };
+
+    for allocation in Allocation::all(conn, interface)? {
+        if allocation.public_key == public_key {
+            continue;
+        }
+        if let Some(address) = overlapping(&allocation.address()?) {
+            return Err(AllocatedError {
+                address,
+                public_key: allocation.public_key,
+            }
+            .into());
+        }
+    }
+
+    for reservation in Reservation::all(conn, interface)? {
+        let network = reservation.network()?;
+        let address = match overlapping(&network) {
+            Some(address) => address,
+            None => continue,
+        };
+        match reservation.public_key {
+            Some(reserved_for) if reserved_for == public_key => {}
+            Some(reserved_for) => {
+                return Err(StaticReservationError {
+                    address,
+                    public_key: reserved_for,
+                }
+                .into())
+            }
+            None => return Err(ReservedRangeError { address, network }.into()),
+        }
+    }
+    Ok(())
+}
+
+// The peer's allocations are replaced with these addresses. The unique index catches an address
+// that was allocated to another peer since it was checked.
+pub fn record(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+    addresses: &[IpNet],
+    now: i64,
+) -> Result<(), Error> {
+    let public_key_string = public_key.to_string();
+
+    conn.transaction::<_, Error, _>(|| {
+        release(conn, interface, public_key)?;
+        for address in addresses {
+            diesel::insert_into(ip_allocations::table)
+                .values(&NewAllocation {
+                    interface,
+                    address: &address.to_string(),
+                    public_key: &public_key_string,
+                    created_at: now,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+// Returns the peer's addresses to the pool. Its static reservations are kept.
+pub fn release(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+) -> QueryResult<()> {
+    diesel::delete(
+        ip_allocations::table
+            .filter(ip_allocations::interface.eq(interface))
+            .filter(ip_allocations::public_key.eq(public_key.to_string())),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+// Releases the addresses of peers that are no longer on the interface, such as ones removed by
+// editing the config file.
+pub fn prune(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_keys: &[PublicKey],
+) -> QueryResult<()> {
+    let public_keys: Vec<String> = public_keys.iter().map(ToString::to_string).collect();
+    diesel::delete(
+        ip_allocations::table
+            .filter(ip_allocations::interface.eq(interface))
+            .filter(ip_allocations::public_key.ne_all(public_keys)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} is already allocated to {}", address, public_key)]
+pub struct AllocatedError {
+    address: IpAddr,
+    public_key: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} is reserved for {}", address, public_key)]
+pub struct StaticReservationError {
+    address: IpAddr,
+    public_key: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} is in the reserved range {}", address, network)]
+pub struct ReservedRangeError {
+    address: IpAddr,
+    network: IpNet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_available, prune, record, reserve, reserved_for, taken, Allocation, NewReservation,
+    };
+    use crate::config::PublicKey;
+    use crate::db;
+    use diesel::SqliteConnection;
+    use ipnet::IpNet;
+    use mktemp::Temp;
+
+    const ALICE: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+    const BOB: &str = "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=";
+
+    fn connect(db_file: &Temp) -> Result<SqliteConnection, failure::Error> {
+        let path = db_file.to_path_buf().display().to_string();
+        db::run_migrations(&path, &mut std::io::sink())?;
+        db::connect(&path)
+    }
+
+    fn nets(nets: &[&str]) -> Result<Vec<IpNet>, failure::Error> {
+        Ok(nets
+            .iter()
+            .map(|net| net.parse())
+            .collect::<Result<_, _>>()?)
+    }
+
+    #[test]
+    fn allocations_and_reservations_are_kept_apart() -> Result<(), failure::Error> {
+        let db_file = Temp::new_file()?;
+        let conn = connect(&db_file)?;
+        let alice: PublicKey = ALICE.parse()?;
+        let bob: PublicKey = BOB.parse()?;
+
+        reserve(
+            &conn,
+            &NewReservation {
+                interface: "wg0",
+                network: "10.0.0.0/29",
+                public_key: None,
+                description: "routers",
+            },
+        )?;
+        reserve(
+            &conn,
+            &NewReservation {
+                interface: "wg0",
+                network: "10.0.0.10/32",
+                public_key: Some(BOB),
+                description: "",
+            },
+        )?;
+        record(&conn, "wg0", &alice, &nets(&["10.0.0.8/32"])?, 1000)?;
+
+        assert_eq!(
+            taken(&conn, "wg0", None)?,
+            nets(&["10.0.0.8/32", "10.0.0.0/29", "10.0.0.10/32"])?
+        );
+        assert_eq!(
+            taken(&conn, "wg0", Some(&bob))?,
+            nets(&["10.0.0.8/32", "10.0.0.0/29"])?
+        );
+        assert_eq!(reserved_for(&conn, "wg0", &bob)?, nets(&["10.0.0.10/32"])?);
+
+        let error = |public_key, address| {
+            check_available(&conn, "wg0", public_key, &nets(&[address]).unwrap())
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert_eq!(
+            error(&bob, "10.0.0.8/32"),
+            Some(format!("10.0.0.8 is already allocated to {}", ALICE))
+        );
+        assert_eq!(
+            error(&alice, "10.0.0.10/32"),
+            Some(format!("10.0.0.10 is reserved for {}", BOB))
+        );
+        assert_eq!(
+            error(&alice, "10.0.0.3/32"),
+            Some("10.0.0.3 is in the reserved range 10.0.0.0/29".to_string())
+        );
+        assert_eq!(error(&alice, "10.0.0.8/32"), None);
+        assert_eq!(error(&bob, "10.0.0.10/32"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn addresses_of_removed_peers_go_back_to_the_pool() -> Result<(), failure::Error> {
+        let db_file = Temp::new_file()?;
+        let conn = connect(&db_file)?;
+        let alice: PublicKey = ALICE.parse()?;
+        let bob: PublicKey = BOB.parse()?;
+
+        record(&conn, "wg0", &alice, &nets(&["10.0.0.2/32"])?, 1000)?;
+        record(&conn, "wg0", &bob, &nets(&["10.0.0.3/32"])?, 1000)?;
+        // Recording again replaces the peer's previous addresses.
+        record(&conn, "wg0", &bob, &nets(&["10.0.0.4/32"])?, 1001)?;
+        assert!(record(&conn, "wg0", &bob, &nets(&["10.0.0.2/32"])?, 1002).is_err());
+
+        prune(&conn, "wg0", &[bob])?;
+        let allocations: Vec<String> = Allocation::all(&conn, "wg0")?
+            .into_iter()
+            .map(|allocation| allocation.address)
+            .collect();
+        assert_eq!(allocations, vec!["10.0.0.4/32"]);
+        Ok(())
+    }
+}
@@ -1,2 +1,11 @@
+pub mod allocation;
+pub use allocation::{Allocation, Reservation};
+
+pub mod peer;
+pub use peer::Peer;
+
+pub mod rotation;
+pub use rotation::{Rotation, RotationPolicy};
+
 pub mod user;
 pub use user::User;
@@ -0,0 +1,159 @@
+use crate::config::PublicKey;
+use crate::diesel;
+use crate::schema::peers;
+use diesel::prelude::*;
+
+#[derive(diesel::Queryable)]
+pub struct Peer {
+    pub id: i32,
+    pub interface: String,
+    pub public_key: String,
+    pub name: String,
+    pub description: String,
+    pub owner_id: Option<i32>,
+    // Only set if the peer was added by a user who was logged in.
+    pub created_by: Option<i32>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(diesel::Insertable)]
+#[table_name = "peers"]
+struct NewPeer<'a> {
+    interface: &'a str,
+    public_key: &'a str,
+    name: &'a str,
+    description: &'a str,
+    owner_id: Option<i32>,
+    created_by: Option<i32>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+// What can be changed about a peer once it's been added.
+pub struct PeerDetails<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub owner_id: Option<i32>,
+}
+
+impl Peer {
+    pub fn all(conn: &SqliteConnection, interface: &str) -> QueryResult<Vec<Self>> {
+        peers::table
+            .filter(peers::interface.eq(interface))
+            .order(peers::id)
+            .load(conn)
+    }
+
+    pub fn find(
+        conn: &SqliteConnection,
+        interface: &str,
+        public_key: &PublicKey,
+    ) -> QueryResult<Option<Self>> {
+        match peers::table
+            .filter(peers::interface.eq(interface))
+            .filter(peers::public_key.eq(public_key.to_string()))
+            .first(conn)
+        {
+            Ok(peer) => Ok(Some(peer)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// The first save records who added the peer and when. Later saves only change the details.
+pub fn save(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+    details: &PeerDetails,
+    user_id: Option<i32>,
+    now: i64,
+) -> QueryResult<()> {
+    conn.transaction(|| {
+        match Peer::find(conn, interface, public_key)? {
+            Some(existing) => diesel::update(peers::table.find(existing.id))
+                .set((
+                    peers::name.eq(details.name),
+                    peers::description.eq(details.description),
+                    peers::owner_id.eq(details.owner_id),
+                    peers::updated_at.eq(now),
+                ))
+                .execute(conn)?,
+            None => diesel::insert_into(peers::table)
+                .values(&NewPeer {
+                    interface,
+                    public_key: &public_key.to_string(),
+                    name: details.name,
+                    description: details.description,
+                    owner_id: details.owner_id,
+                    created_by: user_id,
+                    created_at: now,
+                    updated_at: now,
+                })
+                .execute(conn)?,
+        };
+        Ok(())
+    })
+}
+
+pub fn remove(conn: &SqliteConnection, interface: &str, public_key: &PublicKey) -> QueryResult<()> {
+    diesel::delete(
+        peers::table
+            .filter(peers::interface.eq(interface))
+            .filter(peers::public_key.eq(public_key.to_string())),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remove, save, Peer, PeerDetails};
+    use crate::config::PublicKey;
+    use crate::db;
+    use diesel::SqliteConnection;
+    use mktemp::Temp;
+
+    const PUBLIC_KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    fn connect(db_file: &Temp) -> Result<SqliteConnection, failure::Error> {
+        let path = db_file.to_path_buf().display().to_string();
+        db::run_migrations(&path, &mut std::io::sink())?;
+        db::connect(&path)
+    }
+
+    #[test]
+    fn saving_again_keeps_who_added_the_peer() -> Result<(), failure::Error> {
+        let db_file = Temp::new_file()?;
+        let conn = connect(&db_file)?;
+        let public_key: PublicKey = PUBLIC_KEY.parse()?;
+
+        let details = PeerDetails {
+            name: "Alice's laptop",
+            description: "",
+            owner_id: None,
+        };
+        save(&conn, "wg0", &public_key, &details, Some(1), 1000)?;
+
+        let details = PeerDetails {
+            name: "Alice's old laptop",
+            description: "Replaced in March",
+            owner_id: Some(2),
+        };
+        save(&conn, "wg0", &public_key, &details, Some(3), 1010)?;
+
+        let peer = Peer::find(&conn, "wg0", &public_key)?.expect("peer was not saved");
+        assert_eq!(peer.name, "Alice's old laptop");
+        assert_eq!(peer.description, "Replaced in March");
+        assert_eq!(peer.owner_id, Some(2));
+        assert_eq!(peer.created_by, Some(1));
+        assert_eq!((peer.created_at, peer.updated_at), (1000, 1010));
+        assert!(Peer::all(&conn, "wg1")?.is_empty());
+
+        remove(&conn, "wg0", &public_key)?;
+        assert!(Peer::find(&conn, "wg0", &public_key)?.is_none());
+        Ok(())
+    }
+}
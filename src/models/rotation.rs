@@ -0,0 +1,297 @@
+use crate::config::{PresharedKey, PublicKey};
+use crate::diesel;
+use crate::schema::{psk_rotation_policies, psk_rotations};
+use diesel::prelude::*;
+use failure::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A rotation is pending from when its key is generated until the cutover. It's then either
+// applied, or failed if the key couldn't be set on the peer (such as when the peer was removed).
+pub const PENDING: &str = "pending";
+pub const APPLIED: &str = "applied";
+pub const FAILED: &str = "failed";
+
+// Times are stored as seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn format_time(secs: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64);
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+#[derive(diesel::Queryable)]
+pub struct RotationPolicy {
+    pub id: i32,
+    pub interface: String,
+    pub public_key: String,
+    pub interval_secs: i64,
+    // How long the client has to pick up the new key before the server switches to it.
+    pub cutover_delay_secs: i64,
+    pub next_rotation_at: i64,
+}
+
+#[derive(diesel::Insertable)]
+#[table_name = "psk_rotation_policies"]
+pub struct NewRotationPolicy<'a> {
+    pub interface: &'a str,
+    pub public_key: &'a str,
+    pub interval_secs: i64,
+    pub cutover_delay_secs: i64,
+    pub next_rotation_at: i64,
+}
+
+impl RotationPolicy {
+    pub fn find(
+        conn: &SqliteConnection,
+        interface: &str,
+        public_key: &PublicKey,
+    ) -> QueryResult<Option<Self>> {
+        match psk_rotation_policies::table
+            .filter(psk_rotation_policies::interface.eq(interface))
+            .filter(psk_rotation_policies::public_key.eq(public_key.to_string()))
+            .first(conn)
+        {
+            Ok(policy) => Ok(Some(policy)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn due(conn: &SqliteConnection, interface: &str, now: i64) -> QueryResult<Vec<Self>> {
+        psk_rotation_policies::table
+            .filter(psk_rotation_policies::interface.eq(interface))
+            .filter(psk_rotation_policies::next_rotation_at.le(now))
+            .load(conn)
+    }
+}
+
+// Replaces the peer's existing policy, if it has one.
+pub fn set_policy(conn: &SqliteConnection, policy: &NewRotationPolicy) -> QueryResult<()> {
+    diesel::replace_into(psk_rotation_policies::table)
+        .values(policy)
+        .execute(conn)?;
+    Ok(())
+}
+
+// Rotations that were already started still go ahead.
+pub fn remove_policy(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+) -> QueryResult<()> {
+    diesel::delete(
+        psk_rotation_policies::table
+            .filter(psk_rotation_policies::interface.eq(interface))
+            .filter(psk_rotation_policies::public_key.eq(public_key.to_string())),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(diesel::Queryable)]
+pub struct Rotation {
+    pub id: i32,
+    pub interface: String,
+    pub public_key: String,
+    preshared_key: Option<String>,
+    pub status: String,
+    pub created_at: i64,
+    pub cutover_at: i64,
+    pub applied_at: Option<i64>,
+}
+
+#[derive(diesel::Insertable)]
+#[table_name = "psk_rotations"]
+struct NewRotation<'a> {
+    interface: &'a str,
+    public_key: &'a str,
+    preshared_key: &'a str,
+    status: &'a str,
+    created_at: i64,
+    cutover_at: i64,
+}
+
+impl Rotation {
+    pub fn pending(conn: &SqliteConnection, interface: &str) -> QueryResult<Vec<Self>> {
+        psk_rotations::table
+            .filter(psk_rotations::interface.eq(interface))
+            .filter(psk_rotations::status.eq(PENDING))
+            .order(psk_rotations::cutover_at)
+            .load(conn)
+    }
+
+    pub fn pending_for(
+        conn: &SqliteConnection,
+        interface: &str,
+        public_key: &PublicKey,
+    ) -> QueryResult<Option<Self>> {
+        match psk_rotations::table
+            .filter(psk_rotations::interface.eq(interface))
+            .filter(psk_rotations::public_key.eq(public_key.to_string()))
+            .filter(psk_rotations::status.eq(PENDING))
+            .first(conn)
+        {
+            Ok(rotation) => Ok(Some(rotation)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Most recent first.
+    pub fn history(
+        conn: &SqliteConnection,
+        interface: &str,
+        public_key: &PublicKey,
+    ) -> QueryResult<Vec<Self>> {
+        psk_rotations::table
+            .filter(psk_rotations::interface.eq(interface))
+            .filter(psk_rotations::public_key.eq(public_key.to_string()))
+            .order(psk_rotations::created_at.desc())
+            .load(conn)
+    }
+
+    // Only set while the rotation is pending.
+    pub fn preshared_key(&self) -> Result<Option<PresharedKey>, Error> {
+        self.preshared_key
+            .as_ref()
+            .map(|preshared_key| preshared_key.parse())
+            .transpose()
+    }
+}
+
+// Generates the peer's next key and schedules the rotation after it. Nothing is done if the last
+// rotation is still pending, so a client that's slow to update isn't handed a second new key.
+pub fn start(conn: &SqliteConnection, policy: &RotationPolicy, now: i64) -> Result<(), Error> {
+    let public_key: PublicKey = policy.public_key.parse()?;
+
+    conn.transaction::<_, Error, _>(|| {
+        if Rotation::pending_for(conn, &policy.interface, &public_key)?.is_some() {
+            return Ok(());
+        }
+
+        let preshared_key = PresharedKey::new()?;
+        diesel::insert_into(psk_rotations::table)
+            .values(&NewRotation {
+                interface: &policy.interface,
+                public_key: &policy.public_key,
+                preshared_key: &preshared_key.expose_secret(),
+                status: PENDING,
+                created_at: now,
+                cutover_at: now + policy.cutover_delay_secs,
+            })
+            .execute(conn)?;
+
+        diesel::update(psk_rotation_policies::table.find(policy.id))
+            .set(psk_rotation_policies::next_rotation_at.eq(now + policy.interval_secs))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+// The key is cleared either way. Once applied it's in the config file, and a failed one is of no
+// use to anyone. Only an applied rotation gets a time it was applied at.
+pub fn finish(conn: &SqliteConnection, id: i32, status: &str, now: i64) -> QueryResult<()> {
+    let applied_at = Some(now).filter(|_| status == APPLIED);
+    diesel::update(psk_rotations::table.find(id))
+        .set((
+            psk_rotations::status.eq(status),
+            psk_rotations::applied_at.eq(applied_at),
+            psk_rotations::preshared_key.eq(None::<String>),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+// A pending rotation would only fail at its cutover once the peer is gone, so it's failed right away
+// and its key dropped.
+pub fn cancel_pending(
+    conn: &SqliteConnection,
+    interface: &str,
+    public_key: &PublicKey,
+    now: i64,
+) -> QueryResult<()> {
+    match Rotation::pending_for(conn, interface, public_key)? {
+        Some(pending) => finish(conn, pending.id, FAILED, now),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{finish, set_policy, start, NewRotationPolicy, Rotation, RotationPolicy};
+    use crate::config::PublicKey;
+    use crate::db;
+    use diesel::SqliteConnection;
+    use mktemp::Temp;
+
+    const PUBLIC_KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    fn connect(db_file: &Temp) -> Result<SqliteConnection, failure::Error> {
+        let path = db_file.to_path_buf().display().to_string();
+        db::run_migrations(&path, &mut std::io::sink())?;
+        db::connect(&path)
+    }
+
+    #[test]
+    fn rotations_wait_for_the_cutover_and_keep_no_key_afterwards() -> Result<(), failure::Error> {
+        let db_file = Temp::new_file()?;
+        let conn = connect(&db_file)?;
+        let public_key: PublicKey = PUBLIC_KEY.parse()?;
+
+        set_policy(
+            &conn,
+            &NewRotationPolicy {
+                interface: "wg0",
+                public_key: PUBLIC_KEY,
+                interval_secs: 100,
+                cutover_delay_secs: 10,
+                next_rotation_at: 1000,
+            },
+        )?;
+        assert!(RotationPolicy::due(&conn, "wg0", 999)?.is_empty());
+
+        let policy = RotationPolicy::due(&conn, "wg0", 1000)?
+            .pop()
+            .expect("policy should be due");
+        start(&conn, &policy, 1000)?;
+        // A second start while the first is pending is ignored.
+        start(&conn, &policy, 1001)?;
+
+        let pending = Rotation::pending(&conn, "wg0")?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].cutover_at, 1010);
+        assert!(pending[0].preshared_key()?.is_some());
+
+        let policy = RotationPolicy::find(&conn, "wg0", &public_key)?.expect("policy was removed");
+        assert_eq!(policy.next_rotation_at, 1100);
+
+        finish(&conn, pending[0].id, super::APPLIED, 1010)?;
+        assert!(Rotation::pending(&conn, "wg0")?.is_empty());
+
+        let history = Rotation::history(&conn, "wg0", &public_key)?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].status, super::APPLIED);
+        assert_eq!(history[0].applied_at, Some(1010));
+        assert!(history[0].preshared_key()?.is_none());
+
+        let policy = RotationPolicy::due(&conn, "wg0", 1100)?
+            .pop()
+            .expect("policy should be due again");
+        start(&conn, &policy, 1100)?;
+        let pending = Rotation::pending(&conn, "wg0")?;
+        finish(&conn, pending[0].id, super::FAILED, 1110)?;
+
+        let history = Rotation::history(&conn, "wg0", &public_key)?;
+        let failed = history
+            .iter()
+            .find(|entry| entry.status == super::FAILED)
+            .expect("failed rotation should be in the history");
+        assert_eq!(failed.applied_at, None);
+        Ok(())
+    }
+}
@@ -1,9 +1,7 @@
 use crate::diesel;
 use crate::schema::users;
-use argon2;
 use diesel::prelude::*;
 use failure::Error;
-use rand_os;
 use rand_os::rand_core::RngCore;
 
 fn hash(text: &str) -> Result<String, Error> {
@@ -35,6 +33,13 @@ impl User {
         }
     }
 
+    pub fn emails(conn: &SqliteConnection) -> QueryResult<Vec<(i32, String)>> {
+        users::table
+            .select((users::id, users::email))
+            .order(users::id)
+            .load(conn)
+    }
+
     pub fn verify_password(&self, password: &str) -> argon2::Result<bool> {
         match self.password {
             None => Ok(false),
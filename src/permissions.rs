@@ -0,0 +1,218 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+// Config, key and database files hold secrets, so only the user the server runs as may read them.
+const PRIVATE_MODE: u32 = 0o600;
+
+// Creates (or truncates) a file that only its owner can read. The mode is also set after opening
+// since it's only applied by open when the file didn't already exist.
+pub fn create_private_file(path: &Path) -> io::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_MODE)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(PRIVATE_MODE))?;
+    Ok(file)
+}
+
+// For files created by something else, such as SQLite, which would otherwise use the umask.
+pub fn create_private_file_if_missing(path: &Path) -> io::Result<()> {
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(PRIVATE_MODE)
+        .open(path)
+    {
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+// SQLite creates its journal and WAL files next to the database whenever it needs them, with the
+// umask deciding their mode. Nothing the server writes is meant for other users, so the umask is
+// narrowed for the whole process.
+pub fn restrict_umask() {
+    unsafe { libc::umask(0o077) };
+}
+
+// Sidecars left behind by a run with a wider umask are narrowed too.
+pub fn restrict_sqlite_sidecars(db_path: &Path) -> io::Result<()> {
+    for suffix in &["-journal", "-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        match fs::set_permissions(&path, fs::Permissions::from_mode(PRIVATE_MODE)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+// Files that don't exist yet are fine, since they'll be created with the right mode.
+pub fn check(path: &Path) -> Result<(), failure::Error> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let display = path.display().to_string();
+
+    let mode = metadata.mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(ReadableByOthersError {
+            path: display,
+            mode,
+        }
+        .into());
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        return Err(OwnedByOtherUserError {
+            path: display,
+            uid: metadata.uid(),
+        }
+        .into());
+    }
+
+    // Anyone who can write to the directory can swap the file for their own. The sticky bit, as
+    // on /tmp, stops them from replacing files they don't own.
+    if let Some(parent) = path.parent().filter(|parent| parent != &Path::new("")) {
+        let parent_mode = fs::metadata(parent)?.mode();
+        if parent_mode & 0o022 != 0 && parent_mode & libc::S_ISVTX as u32 == 0 {
+            return Err(WritableDirectoryError {
+                path: display,
+                directory: parent.display().to_string(),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+// Problems are added to the warnings instead when insecure permissions are allowed.
+pub fn check_or_warn(
+    path: &Path,
+    allow_insecure: bool,
+    warnings: &mut Vec<String>,
+) -> Result<(), failure::Error> {
+    match check(path) {
+        Err(err) if allow_insecure => {
+            warnings.push(err.to_string());
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} can be read by other users (mode {:o}). Run chmod 600 on it, or pass \
+               --allow-insecure-permissions to start anyway",
+    path, mode
+)]
+pub struct ReadableByOthersError {
+    path: String,
+    mode: u32,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} is owned by another user (uid {}). Pass --allow-insecure-permissions to start \
+               anyway",
+    path, uid
+)]
+pub struct OwnedByOtherUserError {
+    path: String,
+    uid: u32,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} is in {}, which other users can write to. Pass --allow-insecure-permissions \
+               to start anyway",
+    path, directory
+)]
+pub struct WritableDirectoryError {
+    path: String,
+    directory: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check, check_or_warn, create_private_file, create_private_file_if_missing,
+        restrict_sqlite_sidecars,
+    };
+    use mktemp::Temp;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn created_files_are_private() -> Result<(), failure::Error> {
+        let dir = Temp::new_dir()?;
+        let path = dir.to_path_buf().join("wg0.conf");
+        create_private_file(&path)?;
+        assert_eq!(fs::metadata(&path)?.mode() & 0o777, 0o600);
+
+        let db_path = dir.to_path_buf().join("wg0.sqlite3");
+        create_private_file_if_missing(&db_path)?;
+        assert_eq!(fs::metadata(&db_path)?.mode() & 0o777, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_sidecars_are_made_private() -> Result<(), failure::Error> {
+        let dir = Temp::new_dir()?;
+        let db_path = dir.to_path_buf().join("wg0.sqlite3");
+        let journal_path = dir.to_path_buf().join("wg0.sqlite3-journal");
+        fs::write(&journal_path, "")?;
+        fs::set_permissions(&journal_path, fs::Permissions::from_mode(0o644))?;
+
+        restrict_sqlite_sidecars(&db_path)?;
+        assert_eq!(fs::metadata(&journal_path)?.mode() & 0o777, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn readable_files_are_refused() -> Result<(), failure::Error> {
+        let dir = Temp::new_dir()?;
+        fs::set_permissions(dir.to_path_buf(), fs::Permissions::from_mode(0o700))?;
+        let path = dir.to_path_buf().join("wg0.key");
+        assert!(check(&path).is_ok());
+
+        create_private_file(&path)?;
+        assert!(check(&path).is_ok());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640))?;
+        assert!(check(&path).is_err());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        fs::set_permissions(dir.to_path_buf(), fs::Permissions::from_mode(0o777))?;
+        assert!(check(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn allowed_problems_come_back_as_warnings() -> Result<(), failure::Error> {
+        let dir = Temp::new_dir()?;
+        fs::set_permissions(dir.to_path_buf(), fs::Permissions::from_mode(0o700))?;
+        let path = dir.to_path_buf().join("wg0.conf");
+        create_private_file(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+
+        let mut warnings = vec![];
+        assert!(check_or_warn(&path, false, &mut warnings).is_err());
+        assert!(warnings.is_empty());
+
+        check_or_warn(&path, true, &mut warnings)?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("chmod 600"));
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+
+// The WireGuard mobile apps import a config by scanning a QR code of the config file's contents.
+// Low error correction keeps the code small enough to scan easily, since it's read off a screen.
+pub fn to_svg(text: &str) -> Result<String, failure::Error> {
+    let code = QrCode::with_error_correction_level(text, EcLevel::L)?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .quiet_zone(true)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_svg;
+
+    #[test]
+    fn renders_configs_as_svg() -> Result<(), failure::Error> {
+        let svg =
+            to_svg("[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n")?;
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("<svg"));
+        Ok(())
+    }
+}
@@ -1,3 +1,61 @@
+table! {
+    ip_allocations (id) {
+        id -> Integer,
+        interface -> Text,
+        address -> Text,
+        public_key -> Text,
+        created_at -> BigInt,
+    }
+}
+
+table! {
+    ip_reservations (id) {
+        id -> Integer,
+        interface -> Text,
+        network -> Text,
+        public_key -> Nullable<Text>,
+        description -> Text,
+    }
+}
+
+table! {
+    peers (id) {
+        id -> Integer,
+        interface -> Text,
+        public_key -> Text,
+        name -> Text,
+        description -> Text,
+        owner_id -> Nullable<Integer>,
+        created_by -> Nullable<Integer>,
+        created_at -> BigInt,
+        updated_at -> BigInt,
+    }
+}
+
+table! {
+    psk_rotation_policies (id) {
+        id -> Integer,
+        interface -> Text,
+        public_key -> Text,
+        interval_secs -> BigInt,
+        cutover_delay_secs -> BigInt,
+        next_rotation_at -> BigInt,
+    }
+}
+
+table! {
+    psk_rotations (id) {
+        id -> Integer,
+        interface -> Text,
+        public_key -> Text,
+        preshared_key -> Nullable<Text>,
+        status -> Text,
+        created_at -> BigInt,
+        cutover_at -> BigInt,
+        applied_at -> Nullable<BigInt>,
+    }
+}
+
 table! {
     users (id) {
         id -> Integer,
@@ -6,3 +64,12 @@ table! {
         administrator -> Integer,
     }
 }
+
+allow_tables_to_appear_in_same_query!(
+    ip_allocations,
+    ip_reservations,
+    peers,
+    psk_rotation_policies,
+    psk_rotations,
+    users,
+);
@@ -0,0 +1,26 @@
+use crate::states::WgState;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Every interface managed by this server, keyed by interface name. Routes are scoped to an
+// interface and look up its state here.
+#[derive(Default)]
+pub struct Interfaces(BTreeMap<String, Arc<WgState>>);
+
+impl Interfaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, wgstate: Arc<WgState>) {
+        self.0.insert(wgstate.name().to_string(), wgstate);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<WgState>> {
+        self.0.get(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+}
@@ -1,2 +1,11 @@
+pub mod interfaces;
+pub use interfaces::Interfaces;
+
+pub mod provisionedclients;
+pub use provisionedclients::ProvisionedClients;
+
+pub mod publichost;
+pub use publichost::PublicHost;
+
 pub mod wgstate;
 pub use wgstate::WgState;
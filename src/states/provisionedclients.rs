@@ -0,0 +1,96 @@
+use crate::config::{Config, PublicKey};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Long enough to get a phone out and scan the QR code.
+const PICKUP_TIME: Duration = Duration::from_secs(15 * 60);
+
+type Key = (String, [u8; 32]);
+
+// When the config was provisioned and by which user, who's the only one who can pick it up.
+struct Pending {
+    created: Instant,
+    user_id: i32,
+    config: Config,
+}
+
+// Configs of newly provisioned clients, keyed by interface and the client's public key. Each one
+// holds the only copy of a client's private key, so they're only kept in memory, and only until
+// they're downloaded or PICKUP_TIME passes. Keys are wiped as the configs are dropped.
+#[derive(Default)]
+pub struct ProvisionedClients(Mutex<HashMap<Key, Pending>>);
+
+impl ProvisionedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, interface: &str, public_key: &PublicKey, user_id: i32, config: Config) {
+        let key = (interface.to_string(), *public_key.as_bytes());
+        let pending = Pending {
+            created: Instant::now(),
+            user_id,
+            config,
+        };
+        self.lock().insert(key, pending);
+    }
+
+    // The config file's contents, for showing as a QR code. The config is kept.
+    pub fn get(&self, interface: &str, public_key: &PublicKey, user_id: i32) -> Option<String> {
+        let key = (interface.to_string(), *public_key.as_bytes());
+        self.lock()
+            .get(&key)
+            .filter(|pending| pending.user_id == user_id)
+            .map(|pending| pending.config.to_string())
+    }
+
+    // The config file's contents, for downloading. The config is forgotten afterwards.
+    pub fn take(&self, interface: &str, public_key: &PublicKey, user_id: i32) -> Option<String> {
+        let key = (interface.to_string(), *public_key.as_bytes());
+        let mut pending = self.lock();
+        if pending.get(&key)?.user_id != user_id {
+            return None;
+        }
+        pending
+            .remove(&key)
+            .map(|pending| pending.config.to_string())
+    }
+
+    // For a peer that's been removed, whose config is no use to anyone.
+    pub fn forget(&self, interface: &str, public_key: &PublicKey) {
+        let key = (interface.to_string(), *public_key.as_bytes());
+        self.lock().remove(&key);
+    }
+
+    // Expired configs are dropped whenever the map is used, rather than on a timer.
+    fn lock(&self) -> MutexGuard<HashMap<Key, Pending>> {
+        let mut guard = match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        guard.retain(|_, pending| pending.created.elapsed() < PICKUP_TIME);
+        guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProvisionedClients;
+    use crate::config::Config;
+
+    #[test]
+    fn configs_are_only_handed_to_whoever_provisioned_them() -> Result<(), failure::Error> {
+        let provisioned_clients = ProvisionedClients::new();
+        let config = Config::new("wg0".to_string())?;
+        let public_key = config.interface.private_key.public_key();
+        provisioned_clients.insert("wg0", &public_key, 1, config);
+
+        assert!(provisioned_clients.get("wg0", &public_key, 2).is_none());
+        assert!(provisioned_clients.take("wg0", &public_key, 2).is_none());
+        assert!(provisioned_clients.get("wg0", &public_key, 1).is_some());
+        assert!(provisioned_clients.take("wg0", &public_key, 1).is_some());
+        assert!(provisioned_clients.take("wg0", &public_key, 1).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use rocket::fairing::{AdHoc, Fairing};
+
+// The host clients use to reach this server, given with --public-host. It's the only part of a
+// generated client config's Endpoint that can't be read from the interface itself.
+pub struct PublicHost(pub Option<String>);
+
+impl PublicHost {
+    // Read from Rocket's config, where it's put by launchpad::get_config_from_args.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::on_attach("Public host", |rocket| {
+            let public_host = rocket
+                .config()
+                .get_str("public_host")
+                .ok()
+                .map(ToString::to_string);
+            Ok(rocket.manage(PublicHost(public_host)))
+        })
+    }
+
+    // IPv6 addresses need brackets to be followed by a port.
+    pub fn endpoint(&self, port: u16) -> Option<String> {
+        self.0
+            .as_ref()
+            .map(|host| match host.parse::<std::net::Ipv6Addr>() {
+                Ok(_) => format!("[{}]:{}", host, port),
+                Err(_) => format!("{}:{}", host, port),
+            })
+    }
+}
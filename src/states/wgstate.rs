@@ -1,10 +1,24 @@
 use crate::config;
-use crate::config::Config;
+use crate::config::client::ClientOptions;
+use crate::config::document::Secrets;
+use crate::config::endpoint;
+use crate::config::lint::TakenOverRoute;
+use crate::config::lookup::Lookup;
+use crate::config::peer::{AllowedIps, PeerEdit, PresharedKeyEdit};
+use crate::config::{Config, ConfigDocument, Drift, Endpoint, PresharedKey, PublicKey};
+use crate::permissions;
+use ipnet::IpNet;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
 use wireguard_uapi::err::ConnectError;
 use wireguard_uapi::get::Device;
 use wireguard_uapi::set;
-use wireguard_uapi::set::WgPeerF;
+use wireguard_uapi::set::{WgDeviceF, WgPeerF};
 use wireguard_uapi::{DeviceInterface, RouteSocket, WgSocket};
 
 pub struct WgState {
@@ -23,32 +37,123 @@ pub struct WgState {
     // in a hacky way (and we need sequence ids).
     pub wg_socket: Mutex<WgSocket>,
     pub route_socket: Mutex<RouteSocket>,
-    interface_config: Config,
+    // Kept outside of the config so the interface can be named without taking the config lock.
+    name: String,
+    // The in-memory config is the source of truth for what gets written back to the config file.
+    // It's kept in sync with every change we make to the device.
+    interface_config: Mutex<Config>,
+    interface_config_path: PathBuf,
+    // The config file as we last wrote or read it, so our own writes aren't taken for someone else's
+    // edits.
+    written: Mutex<Option<FileStamp>>,
 }
 
 impl WgState {
-    pub fn init(interface_config: Config) -> Result<Self, ConnectError> {
+    pub fn init(
+        interface_config: Config,
+        interface_config_path: PathBuf,
+    ) -> Result<Self, ConnectError> {
         Ok(Self {
             wg_socket: Mutex::new(WgSocket::connect()?),
             route_socket: Mutex::new(RouteSocket::connect()?),
-            interface_config,
+            name: interface_config.name.clone(),
+            interface_config: Mutex::new(interface_config),
+            written: Mutex::new(FileStamp::of(&interface_config_path).ok()),
+            interface_config_path,
         })
     }
 
-    pub fn apply_config(&self) -> Result<(), failure::Error> {
+    // Adopts an interface that's already up by reading the config back from the device and
+    // writing it out, rather than generating a new one. The device itself is left untouched so
+    // existing peers stay connected.
+    pub fn import(name: String, interface_config_path: PathBuf) -> Result<Self, failure::Error> {
+        if interface_config_path.exists() {
+            return Err(ImportConfigExistsError {
+                path: interface_config_path.display().to_string(),
+            }
+            .into());
+        }
+
+        let mut wg_socket = WgSocket::connect()?;
+        let device = wg_socket.get_device(DeviceInterface::from_name(&name))?;
+        let mut interface_config = Config::from_device(name, &device)?;
+        interface_config.write_to_path(&interface_config_path)?;
+
+        Ok(Self {
+            wg_socket: Mutex::new(wg_socket),
+            route_socket: Mutex::new(RouteSocket::connect()?),
+            name: interface_config.name.clone(),
+            interface_config: Mutex::new(interface_config),
+            written: Mutex::new(FileStamp::of(&interface_config_path).ok()),
+            interface_config_path,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Brings the interface up as the config describes it. Peers whose hostnames didn't resolve are
+    // returned as warnings.
+    pub fn apply_config(&self) -> Result<Vec<String>, failure::Error> {
+        let mut interface_config = self.get_interface_config_guard();
+
+        // A peer whose hostname fails to resolve shouldn't keep the rest of the interface from
+        // coming up. It'll be retried the next time endpoints are re-resolved.
+        let mut warnings = vec![];
+        for peer in &mut interface_config.peers {
+            if let Some(endpoint) = &mut peer.endpoint {
+                if let Err(err) = endpoint.resolve() {
+                    warnings.push(unresolved_endpoint(endpoint, &err));
+                }
+            }
+        }
+
         let mut route_socket = self.get_route_socket_guard()?;
         let route_socket = &mut *route_socket;
 
         // TODO: Handle the result type and continue only if the error is because the device already
         // exists.
-        let _ = route_socket.add_device(&self.interface_config.name);
+        let _ = route_socket.add_device(&interface_config.name);
 
         let mut wg_guard = self.get_wg_socket_guard()?;
         let wg_socket = &mut *wg_guard;
 
-        wg_socket.set_device((&self.interface_config).into())?;
+        set_device(wg_socket, (&*interface_config).into())?;
 
-        Ok(())
+        Ok(warnings)
+    }
+
+    fn get_interface_config_guard(&self) -> MutexGuard<Config> {
+        // A panic while holding this lock can't leave the config half-written to disk since writes
+        // go through a rename, so the in-memory copy is still safe to use.
+        match self.interface_config.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn get_written_guard(&self) -> MutexGuard<Option<FileStamp>> {
+        match self.written.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Writes are made with the config locked, so the stamp always matches the last one. It's taken
+    // from the new file before the rename, since a watcher can look as soon as the rename is done.
+    // Renaming keeps the inode and modification time, so the stamp still matches afterwards.
+    fn write_config(&self, interface_config: &mut Config) -> Result<(), failure::Error> {
+        interface_config.write_to_path_with(&self.interface_config_path, |tmp_path| {
+            *self.get_written_guard() = FileStamp::of(tmp_path).ok();
+        })
+    }
+
+    // Whether the config file is still the one we last wrote or read. A file that was edited in place or
+    // replaced has a different modification time or inode.
+    pub fn is_own_write(&self) -> bool {
+        let written = self.get_written_guard();
+        written.is_some() && *written == FileStamp::of(&self.interface_config_path).ok()
     }
 
     fn get_wg_socket_guard(&self) -> Result<MutexGuard<WgSocket>, ConnectError> {
@@ -78,11 +183,27 @@ impl WgState {
     pub fn get_device(&self) -> Result<Device, failure::Error> {
         let mut guard = self.get_wg_socket_guard()?;
         let socket = &mut *guard;
-        let device = socket.get_device(DeviceInterface::from_name(&self.interface_config.name))?;
+        let device = socket.get_device(DeviceInterface::from_name(&self.name))?;
         Ok(device)
     }
 
-    pub fn add_peer(&self, config_peer: config::Peer) -> Result<(), failure::Error> {
+    // Locks the config for adding or editing peers. Addresses picked as free or checked through the
+    // lock can't be taken by a peer being changed at the same time, and whatever is recorded about
+    // a peer before the lock is dropped is in place before the next change looks.
+    pub fn lock_config(&self) -> LockedConfig {
+        LockedConfig {
+            wg: self,
+            interface_config: self.get_interface_config_guard(),
+        }
+    }
+
+    fn add_peer_locked(
+        &self,
+        interface_config: &mut Config,
+        config_peer: config::Peer,
+    ) -> Result<(), failure::Error> {
+        interface_config.check_peer_public_key(&config_peer.public_key)?;
+
         let mut guard = self.get_wg_socket_guard()?;
         let socket = &mut *guard;
 
@@ -102,7 +223,7 @@ impl WgState {
                 .collect(),
         );
 
-        if let Some(endpoint) = &config_peer.endpoint {
+        if let Some(endpoint) = config_peer.endpoint.as_ref().and_then(Endpoint::resolved) {
             peer = peer.endpoint(endpoint);
         }
 
@@ -111,15 +232,650 @@ impl WgState {
         }
 
         let device = wireguard_uapi::set::Device {
-            interface: DeviceInterface::from_name(&self.interface_config.name),
+            interface: DeviceInterface::from_name(&interface_config.name),
             flags: vec![],
             private_key: None,
             listen_port: None,
             fwmark: None,
             peers: vec![peer],
         };
-        socket.set_device(device)?;
+        set_device(socket, device)?;
+        drop(guard);
+
+        interface_config.upsert_peer(config_peer);
+        self.write_config(interface_config)?;
+
+        Ok(())
+    }
+
+    // Takes the peer off the device and out of the config. A peer that's only on the device, such as
+    // one added with `wg set`, is taken off the device all the same.
+    pub fn remove_peer(&self, public_key: &PublicKey) -> Result<(), failure::Error> {
+        let mut interface_config = self.get_interface_config_guard();
+
+        let device = set::Device {
+            interface: DeviceInterface::from_name(&self.name),
+            flags: vec![],
+            private_key: None,
+            listen_port: None,
+            fwmark: None,
+            peers: vec![
+                set::Peer::from_public_key(public_key.as_bytes()).flags(vec![WgPeerF::RemoveMe])
+            ],
+        };
+        set_device(&mut *self.get_wg_socket_guard()?, device)?;
+
+        if interface_config.remove_peer(public_key).is_some() {
+            self.write_config(&mut interface_config)?;
+        }
+        Ok(())
+    }
+
+    // Reads the peer as it's written in the config, or as it is on the device for a peer that's
+    // only there. None if neither has the peer.
+    pub fn peer<T>(
+        &self,
+        public_key: &PublicKey,
+        read: impl FnOnce(&config::Peer) -> T,
+    ) -> Result<Option<T>, failure::Error> {
+        let interface_config = self.get_interface_config_guard();
+        if let Some(peer) = interface_config
+            .peers
+            .iter()
+            .find(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+        {
+            return Ok(Some(read(peer)));
+        }
+        drop(interface_config);
+
+        Ok(self
+            .get_device()?
+            .peers
+            .iter()
+            .find(|peer| &peer.public_key == public_key.as_bytes())
+            .map(|peer| read(&config::Peer::from(peer))))
+    }
+
+    // Applies only what changed, so the peer's session is left alone. A peer that's only on the
+    // device is written to the config as it's edited.
+    fn edit_peer_locked(
+        &self,
+        interface_config: &mut Config,
+        public_key: &PublicKey,
+        edit: PeerEdit,
+    ) -> Result<(), failure::Error> {
+        let index = interface_config
+            .peers
+            .iter()
+            .position(|peer| peer.public_key.as_bytes() == public_key.as_bytes());
+
+        let device_peer;
+        let old_peer = match index {
+            Some(index) => &interface_config.peers[index],
+            None => {
+                device_peer = self
+                    .get_device()?
+                    .peers
+                    .iter()
+                    .find(|peer| &peer.public_key == public_key.as_bytes())
+                    .map(config::Peer::from)
+                    .ok_or_else(|| UnknownPeerError {
+                        public_key: public_key.to_string(),
+                    })?;
+                &device_peer
+            }
+        };
+
+        // The key would otherwise have to be written back to wherever it's read from.
+        if let Some(source) = &old_peer.preshared_key_source {
+            if !matches!(edit.preshared_key, PresharedKeyEdit::Keep) {
+                return Err(ExternalPresharedKeyError {
+                    key_source: source.to_string(),
+                }
+                .into());
+            }
+        }
+
+        let (preshared_key, preshared_key_source) = match edit.preshared_key {
+            PresharedKeyEdit::Keep => (
+                old_peer
+                    .preshared_key
+                    .as_ref()
+                    .map(|preshared_key| PresharedKey::from(*preshared_key.as_bytes())),
+                old_peer.preshared_key_source.clone(),
+            ),
+            PresharedKeyEdit::Replace(preshared_key) => (Some(preshared_key), None),
+            PresharedKeyEdit::Clear => (None, None),
+        };
+        let new_peer = config::Peer {
+            public_key: public_key.clone(),
+            preshared_key,
+            preshared_key_source,
+            allowed_ips: edit
+                .allowed_ips
+                .unwrap_or_else(|| old_peer.allowed_ips.clone()),
+            // WireGuard can't clear an endpoint, so a config without one would only disagree with
+            // the device.
+            endpoint: edit.endpoint.or_else(|| old_peer.endpoint.clone()),
+            persistent_keepalive: edit.persistent_keepalive,
+        };
+
+        if let Some(set_peer) = new_peer.changes_from(old_peer) {
+            let device = set::Device {
+                interface: DeviceInterface::from_name(&self.name),
+                flags: vec![],
+                private_key: None,
+                listen_port: None,
+                fwmark: None,
+                peers: vec![set_peer],
+            };
+            set_device(&mut *self.get_wg_socket_guard()?, device)?;
+        }
+
+        match index {
+            Some(index) => interface_config.peers[index] = new_peer,
+            None => interface_config.peers.push(new_peer),
+        }
+        self.write_config(interface_config)?;
+        Ok(())
+    }
+
+    // Lets a form point at the public key field when it's been given one of our own keys.
+    pub fn check_peer_public_key(&self, public_key: &PublicKey) -> Result<(), failure::Error> {
+        self.get_interface_config_guard()
+            .check_peer_public_key(public_key)
+    }
+
+    pub fn has_peer(&self, public_key: &PublicKey) -> bool {
+        self.get_interface_config_guard()
+            .peers
+            .iter()
+            .any(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+    }
+
+    // Whether the peer's preshared key is read from a file or command rather than written in the
+    // config. Such a key can't be changed from here.
+    pub fn has_external_preshared_key(&self, public_key: &PublicKey) -> bool {
+        self.get_interface_config_guard().peers.iter().any(|peer| {
+            peer.public_key.as_bytes() == public_key.as_bytes()
+                && peer.preshared_key_source.is_some()
+        })
+    }
+
+    // Switches a peer to a new preshared key on the device and in the config. Nothing else about
+    // the peer is changed. The peer stops being able to connect until it has the new key too.
+    pub fn set_preshared_key(
+        &self,
+        public_key: &PublicKey,
+        preshared_key: PresharedKey,
+    ) -> Result<(), failure::Error> {
+        let mut interface_config = self.get_interface_config_guard();
+        let config_peer = interface_config
+            .peers
+            .iter_mut()
+            .find(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+            .ok_or_else(|| UnknownPeerError {
+                public_key: public_key.to_string(),
+            })?;
+
+        // The key would otherwise have to be written back to wherever it's read from.
+        if let Some(source) = &config_peer.preshared_key_source {
+            return Err(ExternalPresharedKeyError {
+                key_source: source.to_string(),
+            }
+            .into());
+        }
+
+        let device = set::Device {
+            interface: DeviceInterface::from_name(&self.name),
+            flags: vec![],
+            private_key: None,
+            listen_port: None,
+            fwmark: None,
+            peers: vec![set::Peer::from_public_key(public_key.as_bytes())
+                .preshared_key(preshared_key.as_bytes())],
+        };
+        set_device(&mut *self.get_wg_socket_guard()?, device)?;
+
+        config_peer.preshared_key = Some(preshared_key);
+        self.write_config(&mut interface_config)?;
+        Ok(())
+    }
+
+    // Reads the config file again and applies only what changed. The file is read and hostnames
+    // are resolved while holding the config lock, so a peer added in the meantime can't be lost to
+    // a stale read. Warnings about the new config are returned. The file and any key files it
+    // names are held to the same permissions as at startup.
+    pub fn reload(&self, allow_insecure_permissions: bool) -> Result<Vec<String>, failure::Error> {
+        let mut interface_config = self.get_interface_config_guard();
+        // Stamped before it's read, so further events for the same file don't reload it again,
+        // while a change made in the meantime still does.
+        *self.get_written_guard() = FileStamp::of(&self.interface_config_path).ok();
+        let mut warnings = vec![];
+        permissions::check_or_warn(
+            &self.interface_config_path,
+            allow_insecure_permissions,
+            &mut warnings,
+        )?;
+        let (mut new_config, diagnostics) =
+            Config::read_from_file(self.name.clone(), &self.interface_config_path)?;
+        if !diagnostics.is_empty() {
+            warnings.push(diagnostics.to_string());
+        }
+        for key_file in new_config.key_files() {
+            permissions::check_or_warn(key_file, allow_insecure_permissions, &mut warnings)?;
+        }
+
+        for peer in &mut new_config.peers {
+            let public_key = peer.public_key.as_bytes();
+            let endpoint = match &mut peer.endpoint {
+                Some(endpoint) => endpoint,
+                None => continue,
+            };
+
+            // Endpoints that didn't change keep the address they were resolved to before.
+            let previous = interface_config
+                .peers
+                .iter()
+                .find(|old_peer| old_peer.public_key.as_bytes() == public_key)
+                .and_then(|old_peer| old_peer.endpoint.as_ref())
+                .filter(|old| old.host == endpoint.host && old.port == endpoint.port)
+                .and_then(Endpoint::resolved);
+
+            match previous {
+                Some(addr) => endpoint.set_resolved(*addr),
+                None => {
+                    if let Err(err) = endpoint.resolve() {
+                        warnings.push(unresolved_endpoint(endpoint, &err));
+                    }
+                }
+            }
+        }
+
+        if let Some(device) = new_config.changes_from(&interface_config) {
+            let mut guard = self.get_wg_socket_guard()?;
+            let socket = &mut *guard;
+            set_device(socket, device)?;
+        }
+
+        *interface_config = new_config;
+        Ok(warnings)
+    }
+
+    // Compares the device against the config to find changes made behind our back.
+    pub fn drift(&self) -> Result<Drift, failure::Error> {
+        let interface_config = self.get_interface_config_guard();
+        let device = self.get_device()?;
+        Ok(Drift::between(&interface_config, &device))
+    }
+
+    // Which peer the device routes the address to.
+    pub fn lookup(&self, address: IpAddr) -> Result<Lookup, failure::Error> {
+        let device = self.get_device()?;
+        Ok(Lookup::on_device(&device, address))
+    }
+
+    // Which peer the config says the address should be routed to.
+    pub fn lookup_in_config(&self, address: IpAddr) -> Lookup {
+        Lookup::in_config(&self.get_interface_config_guard(), address)
+    }
+
+    pub fn listen_port(&self) -> Option<u16> {
+        self.get_interface_config_guard().interface.listen_port
+    }
+
+    // The networks the interface's addresses are in, which clients route to by default.
+    pub fn networks(&self) -> Vec<IpNet> {
+        self.get_interface_config_guard()
+            .interface
+            .address
+            .iter()
+            .map(IpNet::trunc)
+            .collect()
+    }
+
+    // What peers need in their own config to connect to this interface.
+    pub fn public_key(&self) -> PublicKey {
+        self.get_interface_config_guard()
+            .interface
+            .private_key
+            .public_key()
+    }
+
+    pub fn export(&self, secrets: Secrets) -> ConfigDocument {
+        ConfigDocument::from_config(&self.get_interface_config_guard(), secrets)
+    }
+
+    // Pushes the config back onto the device. Replacing peers drops any that were added outside
+    // of the config, and resets the rest to exactly what the config says.
+    pub fn reconcile(&self) -> Result<(), failure::Error> {
+        let interface_config = self.get_interface_config_guard();
+
+        let mut guard = self.get_wg_socket_guard()?;
+        let socket = &mut *guard;
+        let device = set::Device::from(&*interface_config).flags(vec![WgDeviceF::ReplacePeers]);
+        set_device(socket, device)?;
+
+        Ok(())
+    }
+
+    // Looks up hostname endpoints again and updates any peers whose address has changed. This
+    // follows WireGuard's reresolve-dns.sh and skips peers that have completed a handshake
+    // recently, since their current endpoint is evidently still working. Hostnames that didn't
+    // resolve are returned as warnings.
+    pub fn reresolve_endpoints(&self) -> Result<Vec<String>, failure::Error> {
+        let device = self.get_device()?;
+
+        let stale_hostnames: Vec<(PublicKey, Endpoint)> = self
+            .get_interface_config_guard()
+            .peers
+            .iter()
+            .filter(|peer| {
+                let last_handshake_time = device
+                    .peers
+                    .iter()
+                    .find(|device_peer| &device_peer.public_key == peer.public_key.as_bytes())
+                    .map(|device_peer| device_peer.last_handshake_time);
+                last_handshake_time.map_or(true, is_handshake_stale)
+            })
+            .filter_map(|peer| match &peer.endpoint {
+                Some(endpoint) if endpoint.is_hostname() => {
+                    Some((peer.public_key.clone(), endpoint.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        // Resolution can be slow, so it's done without holding any locks.
+        let mut warnings = vec![];
+        let resolved: Vec<(PublicKey, SocketAddr)> = stale_hostnames
+            .into_iter()
+            .filter_map(|(public_key, endpoint)| {
+                match endpoint::lookup(&endpoint.host, endpoint.port) {
+                    Ok(addr) if Some(&addr) != endpoint.resolved() => Some((public_key, addr)),
+                    Ok(_) => None,
+                    Err(err) => {
+                        warnings.push(unresolved_endpoint(&endpoint, &err));
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        if resolved.is_empty() {
+            return Ok(warnings);
+        }
+
+        let mut interface_config = self.get_interface_config_guard();
+        for (public_key, addr) in &resolved {
+            let endpoint = interface_config
+                .peers
+                .iter_mut()
+                .find(|peer| peer.public_key.as_bytes() == public_key.as_bytes())
+                .and_then(|peer| peer.endpoint.as_mut());
+            if let Some(endpoint) = endpoint {
+                endpoint.set_resolved(*addr);
+            }
+        }
+
+        // UpdateOnly keeps a peer that was removed in the meantime from being added back.
+        let peers = resolved
+            .iter()
+            .map(|(public_key, addr)| {
+                set::Peer::from_public_key(public_key.as_bytes())
+                    .flags(vec![WgPeerF::UpdateOnly])
+                    .endpoint(addr)
+            })
+            .collect();
+
+        let mut guard = self.get_wg_socket_guard()?;
+        let socket = &mut *guard;
+        set_device(
+            socket,
+            set::Device::from_ifname(&interface_config.name).peers(peers),
+        )?;
+
+        Ok(warnings)
+    }
+}
+
+fn unresolved_endpoint(endpoint: &Endpoint, err: &failure::Error) -> String {
+    format!("Unable to resolve endpoint {}: {}", endpoint, err)
+}
+
+// WireGuard initiates a new handshake at least every REKEY_AFTER_TIME (120 seconds) on an active
+// session. reresolve-dns.sh uses 135 seconds to allow for some slack.
+const STALE_HANDSHAKE_AGE: Duration = Duration::from_secs(135);
+
+// The config of an interface, locked by WgState::lock_config. Nothing else can read or change the
+// config until it's dropped, including through the WgState it came from.
+pub struct LockedConfig<'a> {
+    wg: &'a WgState,
+    interface_config: MutexGuard<'a, Config>,
+}
+
+impl LockedConfig<'_> {
+    pub fn name(&self) -> &str {
+        &self.wg.name
+    }
+
+    // The networks the interface's addresses are in. Empty if it has no Address, in which case
+    // there's nothing to allocate peers' addresses from.
+    pub fn networks(&self) -> Vec<IpNet> {
+        self.interface_config
+            .interface
+            .address
+            .iter()
+            .map(IpNet::trunc)
+            .collect()
+    }
+
+    pub fn peer_public_keys(&self) -> Vec<PublicKey> {
+        self.interface_config
+            .peers
+            .iter()
+            .map(|peer| peer.public_key.clone())
+            .collect()
+    }
+
+    pub fn next_free_addresses(&self, taken: &[IpNet]) -> Result<Vec<IpNet>, failure::Error> {
+        self.interface_config.next_free_addresses(taken)
+    }
+
+    pub fn routes_taken_over(
+        &self,
+        public_key: &PublicKey,
+        allowed_ips: &AllowedIps,
+    ) -> Vec<TakenOverRoute> {
+        self.interface_config
+            .routes_taken_over(public_key, allowed_ips)
+    }
+
+    pub fn add_peer(&mut self, config_peer: config::Peer) -> Result<(), failure::Error> {
+        self.wg
+            .add_peer_locked(&mut self.interface_config, config_peer)
+    }
+
+    pub fn edit_peer(
+        &mut self,
+        public_key: &PublicKey,
+        edit: PeerEdit,
+    ) -> Result<(), failure::Error> {
+        self.wg
+            .edit_peer_locked(&mut self.interface_config, public_key, edit)
+    }
+
+    // Generates a new client with the next free addresses, without adding it yet.
+    pub fn new_client(
+        &self,
+        options: ClientOptions,
+        taken: &[IpNet],
+    ) -> Result<(config::Peer, Config), failure::Error> {
+        self.interface_config.new_client(options, taken)
+    }
+}
+
+// Errors from the device can end up on pages, so the keys sent with a change are scrubbed from
+// them in case the error repeats any of the request.
+fn set_device(socket: &mut WgSocket, device: set::Device) -> Result<(), failure::Error> {
+    let keys: Vec<[u8; 32]> = device
+        .peers
+        .iter()
+        .filter_map(|peer| peer.preshared_key.cloned())
+        .chain(device.private_key.cloned())
+        .collect();
+    socket.set_device(device).map_err(|err| {
+        DeviceError {
+            message: redact_keys(&err.to_string(), &keys),
+        }
+        .into()
+    })
+}
+
+fn redact_keys(message: &str, keys: &[[u8; 32]]) -> String {
+    keys.iter().fold(message.to_string(), |message, key| {
+        message
+            .replace(&base64::encode(key), "(redacted)")
+            .replace(&format!("{:?}", key), "(redacted)")
+    })
+}
+
+// Enough of a file's metadata to tell whether it's been changed or replaced since.
+#[derive(PartialEq)]
+struct FileStamp {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            len: metadata.len(),
+        })
+    }
+}
+
+fn is_handshake_stale(last_handshake_time: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .and_then(|now| now.checked_sub(last_handshake_time))
+        .map_or(true, |age| age > STALE_HANDSHAKE_AGE)
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "{} already exists. Remove it to import the interface's current configuration.",
+    path
+)]
+pub struct ImportConfigExistsError {
+    path: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "unable to update the device: {}", message)]
+pub struct DeviceError {
+    message: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "{} isn't a peer of this interface", public_key)]
+pub struct UnknownPeerError {
+    public_key: String,
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "the preshared key is read from {}, so it has to be changed there",
+    key_source
+)]
+pub struct ExternalPresharedKeyError {
+    key_source: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_keys, FileStamp, WgState};
+    use crate::config::endpoint::{self, Host};
+    use crate::config::{self, Config, Endpoint, PublicKey};
+    use std::fs;
+    use wireguard_uapi::{DeviceInterface, WgSocket};
+
+    #[test]
+    fn keys_are_scrubbed_from_device_errors() {
+        let key = [7u8; 32];
+        let message = format!("rejected {} and {:?}", base64::encode(&key), key);
+        assert_eq!(
+            redact_keys(&message, &[key]),
+            "rejected (redacted) and (redacted)"
+        );
+    }
+
+    #[test]
+    fn replaced_files_are_told_apart() -> Result<(), failure::Error> {
+        let dir = mktemp::Temp::new_dir()?;
+        let path = dir.to_path_buf().join("wg0.conf");
+        fs::write(&path, "[Interface]\n")?;
+        let written = FileStamp::of(&path)?;
+        assert!(written == FileStamp::of(&path)?);
+
+        // Renamed over the original, the way editors and our own writes replace it.
+        let replacement = dir.to_path_buf().join("wg0.conf.tmp");
+        fs::write(&replacement, "[Interface]\n")?;
+        fs::rename(&replacement, &path)?;
+        assert!(written != FileStamp::of(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn hostnames_are_resolved_again() -> Result<(), failure::Error> {
+        let interface_config_file = mktemp::Temp::new_file()?;
+        let wgstate = WgState::init(
+            Config::new("wgtest".to_owned())?,
+            interface_config_file.to_path_buf(),
+        )?;
+
+        // The peer starts out on an address that localhost doesn't resolve to.
+        let public_key: PublicKey = "4JAoecLXDTmCANmZ5hDdrULSkgU9liwrFOaiH68nXZ4=".parse()?;
+        let mut stale_endpoint: Endpoint = "localhost:51820".parse()?;
+        stale_endpoint.set_resolved("192.0.2.1:51820".parse()?);
+        wgstate.lock_config().add_peer(config::Peer {
+            public_key: public_key.clone(),
+            preshared_key: None,
+            preshared_key_source: None,
+            allowed_ips: "10.82.0.2/32".parse()?,
+            endpoint: Some(stale_endpoint),
+            persistent_keepalive: None,
+        })?;
+
+        wgstate.reresolve_endpoints()?;
+
+        let expected = endpoint::lookup(&Host::Domain("localhost".to_string()), 51820)?;
+        let device = WgSocket::connect()?.get_device(DeviceInterface::from_name("wgtest"))?;
+        let peer = device
+            .peers
+            .iter()
+            .find(|peer| &peer.public_key == public_key.as_bytes())
+            .expect("Peer not found");
+        assert_eq!(peer.endpoint, Some(expected));
+        assert_eq!(
+            wgstate.peer(&public_key, |peer| peer
+                .endpoint
+                .as_ref()
+                .and_then(Endpoint::resolved)
+                .cloned())?,
+            Some(Some(expected))
+        );
 
+        wgstate.remove_peer(&public_key)?;
         Ok(())
     }
 }
@@ -0,0 +1,11 @@
+use std::fmt;
+
+pub mod reload;
+pub mod reresolve;
+pub mod rotation;
+
+// Tasks run on threads of their own with nobody to hand a problem back to, so whatever they have
+// to say is printed here, along with the interface it's about.
+fn report(interface: &str, message: impl fmt::Display) {
+    eprintln!("{}: {}", interface, message);
+}
@@ -0,0 +1,197 @@
+use super::report;
+use crate::states::WgState;
+use std::ffi::{CString, OsString};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+
+fn reload(wgstate: &WgState, allow_insecure_permissions: bool) {
+    match wgstate.reload(allow_insecure_permissions) {
+        Ok(warnings) => {
+            for warning in warnings {
+                report(wgstate.name(), warning);
+            }
+        }
+        Err(err) => report(
+            wgstate.name(),
+            format_args!("Failed to reload the config: {}", err),
+        ),
+    }
+}
+
+fn sighup_set() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGHUP);
+        set
+    }
+}
+
+// Signal masks are inherited by new threads, so this has to be called before any other threads
+// are spawned. Otherwise SIGHUP may be delivered to a thread that isn't waiting for it, which
+// terminates the process.
+pub fn block_sighup() -> io::Result<()> {
+    let set = sighup_set();
+    let result = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+// Reloads every interface's config on SIGHUP, the same as most daemons.
+pub fn spawn_on_sighup(
+    interfaces: Vec<Arc<WgState>>,
+    allow_insecure_permissions: bool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        let set = sighup_set();
+        let mut signal = 0;
+        if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+            continue;
+        }
+
+        for wgstate in &interfaces {
+            reload(wgstate, allow_insecure_permissions);
+        }
+    })
+}
+
+// Reloads the config whenever its file changes. The directory is watched rather than the file,
+// since both editors and our own writes replace the file through a rename. Our own writes are
+// skipped, since the config already has them.
+pub fn spawn_watch(
+    wgstate: Arc<WgState>,
+    path: &Path,
+    allow_insecure_permissions: bool,
+) -> io::Result<thread::JoinHandle<()>> {
+    let name = wgstate.name().to_string();
+    watch(path, name, move || {
+        if !wgstate.is_own_write() {
+            reload(&wgstate, allow_insecure_permissions);
+        }
+    })
+}
+
+// Calls on_change after every batch of events that touch the file.
+fn watch(
+    path: &Path,
+    name: String,
+    mut on_change: impl FnMut() + Send + 'static,
+) -> io::Result<thread::JoinHandle<()>> {
+    let dir = path
+        .parent()
+        .filter(|parent| parent != &Path::new(""))
+        .unwrap_or_else(|| Path::new("."));
+    let file_name: OsString = path.file_name().map(OsString::from).unwrap_or_default();
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+    if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    Ok(thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let len =
+                unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if len < 0 {
+                report(
+                    &name,
+                    format_args!(
+                        "Stopped watching the config: {}",
+                        io::Error::last_os_error()
+                    ),
+                );
+                return;
+            }
+
+            if changed_names(&buffer[..len as usize]).any(|name| name == file_name.as_bytes()) {
+                on_change();
+            }
+        }
+    }))
+}
+
+// Each event is followed by the NUL padded name of the file it's about.
+fn changed_names(mut events: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let header_len = mem::size_of::<libc::inotify_event>();
+
+    std::iter::from_fn(move || {
+        if events.len() < header_len {
+            return None;
+        }
+
+        // The name's length is the last field of the event header.
+        let mut name_len = [0u8; 4];
+        name_len.copy_from_slice(&events[header_len - 4..header_len]);
+        let name_len = u32::from_ne_bytes(name_len) as usize;
+
+        let name_end = (header_len + name_len).min(events.len());
+        let name = &events[header_len..name_end];
+        events = &events[name_end..];
+
+        Some(name.split(|byte| *byte == 0).next().unwrap_or(name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::watch;
+    use crate::config::{self, Config, PublicKey};
+    use crate::states::WgState;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn own_writes_are_not_reloaded() -> Result<(), failure::Error> {
+        let dir = mktemp::Temp::new_dir()?;
+        let path = dir.to_path_buf().join("wgtest.conf");
+        let wgstate = Arc::new(WgState::init(
+            Config::new("wgtest".to_owned())?,
+            path.clone(),
+        )?);
+
+        // Reports what spawn_watch would make of each change, as soon as it's told of it.
+        let (sender, receiver) = mpsc::channel();
+        let watched = Arc::clone(&wgstate);
+        watch(&path, "wgtest".to_owned(), move || {
+            let _ = sender.send(watched.is_own_write());
+        })?;
+
+        let public_key: PublicKey = "jw+UCAyQ3ki5pQqTxLmFqt/JNZa8uRQk4wjoSugHYko=".parse()?;
+        wgstate.lock_config().add_peer(config::Peer {
+            public_key: public_key.clone(),
+            preshared_key: None,
+            preshared_key_source: None,
+            allowed_ips: "10.84.0.2/32".parse()?,
+            endpoint: None,
+            persistent_keepalive: None,
+        })?;
+        assert!(receiver.recv_timeout(Duration::from_secs(5))?);
+        while let Ok(own_write) = receiver.recv_timeout(Duration::from_millis(200)) {
+            assert!(own_write);
+        }
+
+        fs::write(&path, "[Interface]\n")?;
+        assert!(!receiver.recv_timeout(Duration::from_secs(5))?);
+
+        wgstate.remove_peer(&public_key)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use super::report;
+use crate::states::WgState;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Peers on dynamic IPs (such as the other end of a site-to-site tunnel) are often configured by
+// hostname. WireGuard only resolves an endpoint once when it's set, so this periodically looks the
+// hostnames up again and pushes any changes to the device.
+pub fn spawn(wgstate: Arc<WgState>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        match wgstate.reresolve_endpoints() {
+            Ok(warnings) => {
+                for warning in warnings {
+                    report(wgstate.name(), warning);
+                }
+            }
+            Err(err) => report(
+                wgstate.name(),
+                format_args!("Failed to re-resolve peer endpoints: {}", err),
+            ),
+        }
+    })
+}
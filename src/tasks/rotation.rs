@@ -0,0 +1,81 @@
+use super::report;
+use crate::config::PublicKey;
+use crate::db;
+use crate::models::rotation;
+use crate::models::{Rotation, RotationPolicy};
+use crate::states::WgState;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// New keys are generated and cut over to within this long of when they're due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Rotates preshared keys according to each peer's policy. A new key is generated when one is due,
+// and only put in place at the cutover, which leaves time to get it onto the client first.
+pub fn spawn(wgstate: Arc<WgState>, db_path: String) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        if let Err(err) = rotate(&wgstate, &db_path) {
+            report(
+                wgstate.name(),
+                format_args!("Failed to rotate preshared keys: {}", err),
+            );
+        }
+
+        thread::sleep(CHECK_INTERVAL);
+    })
+}
+
+fn rotate(wgstate: &WgState, db_path: &str) -> Result<(), failure::Error> {
+    let conn = db::connect(db_path)?;
+    let now = rotation::now();
+
+    for policy in RotationPolicy::due(&conn, wgstate.name(), now)? {
+        // The config may have been changed to read the peer's key from elsewhere since the policy
+        // was set. The server couldn't switch to a new key, so the client isn't given one.
+        let public_key: PublicKey = policy.public_key.parse()?;
+        if wgstate.has_external_preshared_key(&public_key) {
+            report(
+                wgstate.name(),
+                format_args!(
+                    "Stopped rotating the preshared key of {}, since it's now read from elsewhere",
+                    public_key
+                ),
+            );
+            rotation::remove_policy(&conn, wgstate.name(), &public_key)?;
+            continue;
+        }
+        rotation::start(&conn, &policy, now)?;
+    }
+
+    for pending in Rotation::pending(&conn, wgstate.name())? {
+        if pending.cutover_at > now {
+            continue;
+        }
+
+        let status = match cut_over(wgstate, &pending) {
+            Ok(()) => rotation::APPLIED,
+            Err(err) => {
+                report(
+                    wgstate.name(),
+                    format_args!(
+                        "Failed to switch {} to its new preshared key: {}",
+                        pending.public_key, err
+                    ),
+                );
+                rotation::FAILED
+            }
+        };
+        rotation::finish(&conn, pending.id, status, now)?;
+    }
+
+    Ok(())
+}
+
+fn cut_over(wgstate: &WgState, pending: &Rotation) -> Result<(), failure::Error> {
+    let public_key: PublicKey = pending.public_key.parse()?;
+    let preshared_key = pending
+        .preshared_key()?
+        .ok_or_else(|| failure::format_err!("the pending key is missing"))?;
+    wgstate.set_preshared_key(&public_key, preshared_key)
+}
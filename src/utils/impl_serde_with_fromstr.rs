@@ -0,0 +1,25 @@
+// Serializes a type as the string its Display implementation writes, and deserializes it through
+// FromStr so that values are checked the same way as when they're read from a config file.
+#[macro_export]
+macro_rules! impl_serde_with_fromstr {
+    ($($T:ident),+) => ($(
+        impl serde::Serialize for $T {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $T {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+                $T::from_str(&s).map_err(serde::de::Error::custom)
+            }
+        }
+    )+)
+}
@@ -1,4 +1,5 @@
 mod form_option;
+mod impl_serde_with_fromstr;
 mod impl_with_fromstr;
 
 pub(crate) use form_option::FormOption;
@@ -20,3 +20,129 @@ fn invalid_port() -> Result<(), Error> {
     assert.failure().stderr(predicate::str::contains("port"));
     Ok(())
 }
+
+#[test]
+fn check_reports_config_errors() -> Result<(), Error> {
+    let config_file = mktemp::Temp::new_file()?;
+    std::fs::write(
+        config_file.to_path_buf(),
+        "[Interface]\nListenPort = 51820\n\n[Peer]\nAllowedIPs = 10.0.0.2/32\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let assert = cmd
+        .arg("check")
+        .arg("-c")
+        .arg(config_file.to_path_buf())
+        .assert();
+    assert
+        .failure()
+        .stderr(predicate::str::contains(
+            "missing the required PrivateKey field",
+        ))
+        .stderr(predicate::str::contains(
+            "missing the required PublicKey field",
+        ));
+    Ok(())
+}
+
+#[test]
+fn check_accepts_valid_configs() -> Result<(), Error> {
+    let config_file = mktemp::Temp::new_file()?;
+    std::fs::write(
+        config_file.to_path_buf(),
+        "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let assert = cmd
+        .arg("check")
+        .arg("-c")
+        .arg(config_file.to_path_buf())
+        .assert();
+    assert
+        .success()
+        .stdout(predicate::str::contains("is valid"));
+    Ok(())
+}
+
+#[test]
+fn exports_can_be_imported() -> Result<(), Error> {
+    let config_file = mktemp::Temp::new_file()?;
+    std::fs::write(
+        config_file.to_path_buf(),
+        "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n\n\
+         [Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+         AllowedIPs = 10.0.0.2/32\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let output = cmd
+        .arg("export")
+        .arg("--format")
+        .arg("toml")
+        .arg("-c")
+        .arg(config_file.to_path_buf())
+        .output()?;
+    assert!(output.status.success());
+
+    let import_dir = mktemp::Temp::new_dir()?;
+    let import_path = import_dir.to_path_buf().join("wg0.conf");
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let assert = cmd
+        .arg("import")
+        .arg("--format")
+        .arg("toml")
+        .arg("-c")
+        .arg(&import_path)
+        .write_stdin(output.stdout)
+        .assert();
+    assert
+        .success()
+        .stdout(predicate::str::contains("Imported 1 peer(s)"));
+
+    let imported = std::fs::read_to_string(&import_path)?;
+    assert!(imported.contains("PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk="));
+    assert!(imported.contains("PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="));
+    assert!(imported.contains("AllowedIPs = 10.0.0.2/32"));
+    Ok(())
+}
+
+#[test]
+fn pubkey_derives_the_public_key() -> Result<(), Error> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let assert = cmd
+        .arg("pubkey")
+        .write_stdin("dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=\n")
+        .assert();
+    assert
+        .success()
+        .stdout("hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=\n");
+    Ok(())
+}
+
+#[test]
+fn genkey_and_genpsk_print_base64_keys() -> Result<(), Error> {
+    for subcommand in &["genkey", "genpsk"] {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        let assert = cmd.arg(subcommand).assert();
+        assert
+            .success()
+            .stdout(predicate::str::is_match("^[A-Za-z0-9+/]{43}=\n$")?);
+    }
+    Ok(())
+}
+
+#[test]
+fn allowedips_leaves_out_excluded_networks() -> Result<(), Error> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let assert = cmd
+        .args(&["allowedips", "0.0.0.0/1", "::/0", "--except", "10.0.0.0/8"])
+        .args(&["--except", "::/1"])
+        .assert();
+    assert.success().stdout(
+        "0.0.0.0/5, 8.0.0.0/7, 11.0.0.0/8, 12.0.0.0/6, 16.0.0.0/4, 32.0.0.0/3, 64.0.0.0/2, \
+         8000::/1\n",
+    );
+    Ok(())
+}